@@ -0,0 +1,704 @@
+use std::cell::{Cell, Ref, RefCell};
+use std::ops::Range;
+
+use memmap2::Mmap;
+
+use crate::Pointer;
+
+// Text storage as a piece table: the file as loaded stays untouched in
+// `original`, everything typed afterwards is appended to `added`, and the
+// document is the concatenation of `pieces` pointing into either of them.
+// Edits only split or trim pieces, so they never move the rest of the text.
+//
+// A large file is memory-mapped instead of read, and its line breaks are
+// found as lines are asked for, so opening it doesn't wait for the whole
+// file to come off the disk. It is checked for UTF-8 at the same time;
+// bytes that aren't come out of it as one '?' each, so offsets into the
+// text stay those of the bytes, and they are written back as they were.
+
+// How much of a mapped file is indexed at a time
+const INDEX_CHUNK: usize = 4 << 20;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Source {
+    Original,
+    Added,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Piece {
+    source: Source,
+    start: usize,
+    len: usize,
+}
+
+impl Piece {
+    fn end(&self) -> usize {
+        self.start + self.len
+    }
+}
+
+enum Original {
+    Text(String),
+    // Read by the OS as it is looked at
+    Mapped(Mmap),
+}
+
+pub struct Buffer {
+    original: Original,
+    added: String,
+    // Byte offsets of every '\n' in `original` and `added`. Those of a
+    // mapped file only as far as `indexed`, the offset it has been
+    // searched up to.
+    original_breaks: RefCell<Vec<usize>>,
+    added_breaks: RefCell<Vec<usize>>,
+    indexed: Cell<usize>,
+    // Offset of the first byte of a mapped file found not to be UTF-8, and
+    // whether anyone was told
+    invalid_utf8: Cell<Option<usize>>,
+    invalid_utf8_reported: Cell<bool>,
+    // Where the text in `original` ends
+    original_end: usize,
+    pieces: Vec<Piece>,
+    len: usize,
+    // Line breaks inserted minus line breaks removed
+    edited_breaks: isize,
+}
+
+fn line_breaks(text: &[u8], base: usize) -> impl Iterator<Item = usize> + '_ {
+    text.iter()
+        .enumerate()
+        .filter(|(_, b)| **b == b'\n')
+        .map(move |(i, _)| base + i)
+}
+
+// `bytes` with every byte that isn't part of a UTF-8 character turned into
+// a '?', keeping the length
+fn replace_invalid(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+    let mut rest = bytes;
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                text.push_str(valid);
+                return text;
+            }
+            Err(e) => {
+                let (valid, after) = rest.split_at(e.valid_up_to());
+                text.push_str(std::str::from_utf8(valid).unwrap_or(""));
+                let bad = e.error_len().unwrap_or(after.len());
+                text.extend(std::iter::repeat_n('?', bad));
+                rest = &after[bad..];
+            }
+        }
+    }
+}
+
+impl Buffer {
+    pub fn new(text: String) -> Self {
+        let range = 0..text.len();
+        Self::with_original(Original::Text(text), range)
+    }
+
+    // The text in `range` of a mapped file, which should be UTF-8. Nothing
+    // is read until lines are asked for.
+    pub fn mapped(map: Mmap, range: Range<usize>) -> Self {
+        Self::with_original(Original::Mapped(map), range)
+    }
+
+    fn with_original(original: Original, range: Range<usize>) -> Self {
+        let (breaks, indexed) = match &original {
+            Original::Text(text) => (
+                line_breaks(&text.as_bytes()[range.clone()], range.start).collect(),
+                range.end,
+            ),
+            Original::Mapped(_) => (Vec::new(), range.start),
+        };
+        let pieces = if range.is_empty() {
+            Vec::new()
+        } else {
+            vec![Piece {
+                source: Source::Original,
+                start: range.start,
+                len: range.len(),
+            }]
+        };
+
+        Buffer {
+            original,
+            added: String::new(),
+            original_breaks: RefCell::new(breaks),
+            added_breaks: RefCell::new(Vec::new()),
+            indexed: Cell::new(indexed),
+            invalid_utf8: Cell::new(None),
+            invalid_utf8_reported: Cell::new(false),
+            original_end: range.end,
+            len: range.len(),
+            pieces,
+            edited_breaks: 0,
+        }
+    }
+
+    pub fn from_lines(lines: &[String]) -> Self {
+        Self::new(lines.join("\n"))
+    }
+
+    // A large file, read lazily
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.original, Original::Mapped(_))
+    }
+
+    // Every line break is known, so `len_lines` is the real count
+    pub fn is_indexed(&self) -> bool {
+        self.indexed.get() >= self.original_end
+    }
+
+    // Find the line breaks in the next chunk of a mapped file. Returns
+    // false once the whole file is indexed.
+    pub fn index_more(&self) -> bool {
+        self.index_to(self.indexed.get() + 1);
+        !self.is_indexed()
+    }
+
+    // Where the first bytes of a mapped file that aren't UTF-8 are, the
+    // first time this is asked after indexing came across them
+    pub fn take_invalid_utf8(&self) -> Option<usize> {
+        let offset = self.invalid_utf8.get()?;
+        if self.invalid_utf8_reported.replace(true) {
+            return None;
+        }
+        Some(offset)
+    }
+
+    // Index a mapped file up to at least `offset`, a whole chunk at a time
+    fn index_to(&self, offset: usize) {
+        let indexed = self.indexed.get();
+        if indexed >= std::cmp::min(offset, self.original_end) {
+            return;
+        }
+        let all = self.bytes(Source::Original);
+        let mut end = std::cmp::max(offset, indexed + INDEX_CHUNK).min(self.original_end);
+        // End the chunk on a character boundary, so every chunk can be
+        // checked on its own
+        for _ in 0..3 {
+            if end < self.original_end && all[end] & 0xC0 == 0x80 {
+                end += 1;
+            }
+        }
+        let bytes = &all[indexed..end];
+        self.original_breaks
+            .borrow_mut()
+            .extend(line_breaks(bytes, indexed));
+        if self.invalid_utf8.get().is_none()
+            && let Err(e) = std::str::from_utf8(bytes)
+        {
+            self.invalid_utf8.set(Some(indexed + e.valid_up_to()));
+        }
+        self.indexed.set(end);
+    }
+
+    fn bytes(&self, source: Source) -> &[u8] {
+        match source {
+            Source::Original => match &self.original {
+                Original::Text(text) => text.as_bytes(),
+                Original::Mapped(map) => map,
+            },
+            Source::Added => self.added.as_bytes(),
+        }
+    }
+
+    fn breaks(&self, source: Source) -> Ref<'_, Vec<usize>> {
+        match source {
+            Source::Original => self.original_breaks.borrow(),
+            Source::Added => self.added_breaks.borrow(),
+        }
+    }
+
+    // Range into the source's break list covering the newlines of `piece`
+    fn piece_breaks(&self, piece: &Piece) -> Range<usize> {
+        if piece.source == Source::Original {
+            self.index_to(piece.end());
+        }
+        let breaks = self.breaks(piece.source);
+        let from = breaks.partition_point(|&b| b < piece.start);
+        let to = breaks.partition_point(|&b| b < piece.end());
+        from..to
+    }
+
+    // Length of the whole document in bytes
+    pub fn len_bytes(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Number of lines, always at least one. Of a mapped file that isn't
+    // fully indexed yet, the lines found so far.
+    pub fn len_lines(&self) -> usize {
+        let breaks = self.original_breaks.borrow().len() as isize + self.edited_breaks;
+        std::cmp::max(breaks, 0) as usize + 1
+    }
+
+    // Byte offset where `line` starts. Lines past the end map to the end.
+    pub fn line_to_byte(&self, line: usize) -> usize {
+        self.line_start(line).unwrap_or(self.len)
+    }
+
+    // Byte offset where `line` starts, None if there is no such line. Only
+    // indexes as much of a mapped file as it takes to get there.
+    fn line_start(&self, line: usize) -> Option<usize> {
+        if line == 0 {
+            return Some(0);
+        }
+
+        let mut remaining = line;
+        let mut offset = 0;
+        for piece in &self.pieces {
+            match self.nth_break(piece, remaining) {
+                Ok(newline) => return Some(offset + newline - piece.start + 1),
+                Err(count) => remaining -= count,
+            }
+            offset += piece.len;
+        }
+        None
+    }
+
+    // Offset in its source of the `n`th newline in `piece`, counting from
+    // one, or the number of newlines in it when it has fewer
+    fn nth_break(&self, piece: &Piece, n: usize) -> Result<usize, usize> {
+        loop {
+            let searched = match piece.source {
+                Source::Original => std::cmp::min(self.indexed.get(), piece.end()),
+                Source::Added => piece.end(),
+            };
+            {
+                let breaks = self.breaks(piece.source);
+                let from = breaks.partition_point(|&b| b < piece.start);
+                let to = breaks.partition_point(|&b| b < searched);
+                if n <= to - from {
+                    return Ok(breaks[from + n - 1]);
+                }
+                if searched >= piece.end() {
+                    return Err(to - from);
+                }
+            }
+            self.index_to(searched + 1);
+        }
+    }
+
+    // Line containing the byte at `offset`
+    pub fn byte_to_line(&self, offset: usize) -> usize {
+        let mut line = 0;
+        let mut consumed = 0;
+        for piece in &self.pieces {
+            if consumed + piece.len <= offset {
+                line += self.piece_breaks(piece).len();
+                consumed += piece.len;
+                continue;
+            }
+            let limit = piece.start + (offset - consumed);
+            if piece.source == Source::Original {
+                self.index_to(limit);
+            }
+            let breaks = self.breaks(piece.source);
+            let from = breaks.partition_point(|&b| b < piece.start);
+            let to = breaks.partition_point(|&b| b < limit);
+            return line + to - from;
+        }
+        line
+    }
+
+    // Byte offset of a (line, byte column) position
+    pub fn position_to_byte(&self, position: Pointer) -> usize {
+        std::cmp::min(self.line_to_byte(position.y) + position.x, self.len)
+    }
+
+    pub fn byte_to_position(&self, offset: usize) -> Pointer {
+        let y = self.byte_to_line(offset);
+        Pointer::new(offset - self.line_to_byte(y), y)
+    }
+
+    // Byte column of the `char_idx`th character on `line`
+    pub fn char_to_byte(&self, line: usize, char_idx: usize) -> usize {
+        self.line(line)
+            .char_indices()
+            .nth(char_idx)
+            .map_or_else(|| self.line_len(line), |(i, _)| i)
+    }
+
+    // Character column of byte column `byte_idx` on `line`
+    pub fn byte_to_char(&self, line: usize, byte_idx: usize) -> usize {
+        let text = self.line(line);
+        text[..std::cmp::min(byte_idx, text.len())].chars().count()
+    }
+
+    // Copy out the bytes in `range`
+    pub fn slice(&self, range: Range<usize>) -> String {
+        let mut bytes = Vec::with_capacity(range.len());
+        let mut offset = 0;
+        for piece in &self.pieces {
+            let piece_range = offset..offset + piece.len;
+            offset += piece.len;
+            if piece_range.end <= range.start {
+                continue;
+            }
+            if piece_range.start >= range.end {
+                break;
+            }
+
+            let from = range.start.max(piece_range.start) - piece_range.start + piece.start;
+            let to = range.end.min(piece_range.end) - piece_range.start + piece.start;
+            bytes.extend_from_slice(&self.bytes(piece.source)[from..to]);
+        }
+        String::from_utf8(bytes).unwrap_or_else(|e| replace_invalid(e.as_bytes()))
+    }
+
+    // Byte range of `line`, without the line break after it
+    pub fn line_range(&self, line: usize) -> Range<usize> {
+        self.line_to_byte(line)..self.line_break(line).start
+    }
+
+    // Byte range of the line break after `line`: a '\n', with the '\r'
+    // before it when there is one, as in files whose CRLF line breaks are
+    // kept as they are. Empty after the last line.
+    pub fn line_break(&self, line: usize) -> Range<usize> {
+        let Some(next) = self.line_start(line + 1) else {
+            return self.len..self.len;
+        };
+        let newline = next - 1;
+        if newline > self.line_to_byte(line) && self.slice(newline - 1..newline) == "\r" {
+            newline - 1..next
+        } else {
+            newline..next
+        }
+    }
+
+    // Lines past the end are empty
+    pub fn line(&self, line: usize) -> String {
+        self.slice(self.line_range(line))
+    }
+
+    // Length of `line` in bytes, without its newline
+    pub fn line_len(&self, line: usize) -> usize {
+        self.line_range(line).len()
+    }
+
+    // The lines in `lines`, fetched in one pass
+    pub fn lines(&self, lines: Range<usize>) -> Vec<String> {
+        if lines.start >= lines.end {
+            return Vec::new();
+        }
+        let Some(from) = self.line_start(lines.start) else {
+            return Vec::new();
+        };
+        let next = self.line_start(lines.end);
+        let to = next.map_or(self.len, |next| next - 1);
+        let mut lines: Vec<String> = self
+            .slice(from..to)
+            .split('\n')
+            .map(str::to_string)
+            .collect();
+        // Without the '\r' of CRLF line breaks, as `line_range` leaves it out
+        let last = lines.len() - 1;
+        for (i, line) in lines.iter_mut().enumerate() {
+            if (i < last || next.is_some()) && line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        lines
+    }
+
+    pub fn contents(&self) -> String {
+        self.slice(0..self.len)
+    }
+
+    // The whole text a piece at a time, for writing it out without making
+    // a copy. Pieces start and end on character boundaries.
+    pub fn chunks(&self) -> impl Iterator<Item = &[u8]> {
+        self.pieces
+            .iter()
+            .map(|piece| &self.bytes(piece.source)[piece.start..piece.end()])
+    }
+
+    // Index of the piece holding `offset` and the offset's position inside
+    // it. An offset on a boundary belongs to the piece that starts there.
+    fn locate(&self, offset: usize) -> (usize, usize) {
+        let mut consumed = 0;
+        for (i, piece) in self.pieces.iter().enumerate() {
+            if offset < consumed + piece.len {
+                return (i, offset - consumed);
+            }
+            consumed += piece.len;
+        }
+        (self.pieces.len(), 0)
+    }
+
+    // Insert `text` at byte `offset`
+    pub fn insert(&mut self, offset: usize, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        let offset = std::cmp::min(offset, self.len);
+
+        let start = self.added.len();
+        let breaks = self.added_breaks.get_mut();
+        let before = breaks.len();
+        breaks.extend(line_breaks(text.as_bytes(), start));
+        self.edited_breaks += (breaks.len() - before) as isize;
+        self.added.push_str(text);
+        self.len += text.len();
+
+        let (index, inner) = self.locate(offset);
+
+        // Typing appends to the piece that ended right here, keep it growing
+        if inner == 0 && index > 0 {
+            let prev = &mut self.pieces[index - 1];
+            if prev.source == Source::Added && prev.end() == start {
+                prev.len += text.len();
+                return;
+            }
+        }
+
+        let new_piece = Piece {
+            source: Source::Added,
+            start,
+            len: text.len(),
+        };
+
+        if inner == 0 {
+            self.pieces.insert(index, new_piece);
+        } else {
+            let piece = self.pieces[index];
+            let left = Piece {
+                len: inner,
+                ..piece
+            };
+            let right = Piece {
+                start: piece.start + inner,
+                len: piece.len - inner,
+                ..piece
+            };
+            self.pieces.splice(index..=index, [left, new_piece, right]);
+        }
+    }
+
+    // Remove the bytes in `range` and return them
+    pub fn remove(&mut self, range: Range<usize>) -> String {
+        let range = std::cmp::min(range.start, self.len)..std::cmp::min(range.end, self.len);
+        if range.is_empty() {
+            return String::new();
+        }
+
+        let removed = self.slice(range.clone());
+        self.len -= range.len();
+        self.edited_breaks -= removed.bytes().filter(|&b| b == b'\n').count() as isize;
+
+        let (first, first_inner) = self.locate(range.start);
+        let (last, last_inner) = self.locate(range.end);
+
+        let mut replacement = Vec::with_capacity(2);
+        if first_inner > 0 {
+            let piece = self.pieces[first];
+            replacement.push(Piece {
+                len: first_inner,
+                ..piece
+            });
+        }
+
+        // `last` is the piece holding the first byte kept after the range
+        let mut replace_to = last;
+        if last < self.pieces.len() && last_inner > 0 {
+            let piece = self.pieces[last];
+            replacement.push(Piece {
+                start: piece.start + last_inner,
+                len: piece.len - last_inner,
+                ..piece
+            });
+            replace_to += 1;
+        }
+
+        self.pieces.splice(first..replace_to, replacement);
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every line query, checked against the text split the obvious way
+    fn assert_text(buffer: &Buffer, text: &str) {
+        assert_eq!(buffer.contents(), text);
+        assert_eq!(buffer.len_bytes(), text.len());
+        assert_eq!(
+            buffer.chunks().collect::<Vec<_>>().concat(),
+            text.as_bytes()
+        );
+
+        let lines: Vec<&str> = text.split('\n').collect();
+        assert_eq!(buffer.len_lines(), lines.len());
+        assert_eq!(buffer.lines(0..lines.len()), lines);
+        let mut start = 0;
+        for (i, line) in lines.iter().enumerate() {
+            assert_eq!(buffer.line(i), *line);
+            assert_eq!(buffer.line_range(i), start..start + line.len());
+            assert_eq!(buffer.byte_to_line(start), i);
+            start += line.len() + 1;
+        }
+    }
+
+    #[test]
+    fn insert_splits_and_extends_pieces() {
+        let mut buffer = Buffer::new("hello world".to_string());
+        buffer.insert(5, ",");
+        buffer.insert(6, " there");
+        buffer.insert(0, ">> ");
+        buffer.insert(buffer.len_bytes(), "\n");
+        assert_text(&buffer, ">> hello, there world\n");
+        // Past the end goes at the end
+        buffer.insert(1000, "!");
+        assert_text(&buffer, ">> hello, there world\n!");
+    }
+
+    #[test]
+    fn remove_across_pieces_returns_the_text() {
+        let mut buffer = Buffer::new("one\ntwo\nthree".to_string());
+        buffer.insert(4, "new\n");
+        assert_eq!(buffer.remove(2..10), "e\nnew\ntw");
+        assert_text(&buffer, "ono\nthree");
+        assert_eq!(buffer.remove(3..100), "\nthree");
+        assert_eq!(buffer.remove(5..6), "");
+        assert_text(&buffer, "ono");
+        buffer.remove(0..3);
+        assert!(buffer.is_empty());
+        assert_text(&buffer, "");
+    }
+
+    #[test]
+    fn slice_spans_pieces_and_clamps() {
+        let mut buffer = Buffer::new("abcdef".to_string());
+        buffer.insert(3, "XYZ");
+        assert_eq!(buffer.slice(2..7), "cXYZd");
+        assert_eq!(buffer.slice(0..0), "");
+        assert_eq!(buffer.slice(7..100), "ef");
+    }
+
+    #[test]
+    fn positions_map_to_bytes_and_back() {
+        let buffer = Buffer::new("ab\nçd\n".to_string());
+        let position = Pointer::new(2, 1);
+        assert_eq!(buffer.position_to_byte(position), 5);
+        assert_eq!(buffer.byte_to_position(5), position);
+        assert_eq!(buffer.char_to_byte(1, 1), 2);
+        assert_eq!(buffer.byte_to_char(1, 3), 2);
+        assert_eq!(buffer.line_to_byte(9), buffer.len_bytes());
+    }
+
+    #[test]
+    fn random_edits_match_a_string() {
+        // A small fixed generator, so a failure can be replayed
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = |bound: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % bound as u64) as usize
+        };
+
+        let mut text = String::from("first\nsecond\nthird");
+        let mut buffer = Buffer::new(text.clone());
+        for _ in 0..500 {
+            let at = next(text.len() + 1);
+            if next(3) == 0 && !text.is_empty() {
+                let end = std::cmp::min(at + next(8), text.len());
+                let removed: String = text.drain(at..end).collect();
+                assert_eq!(buffer.remove(at..end), removed);
+            } else {
+                let inserted = ["x", "\n", "yz", "a\nb", ""][next(5)];
+                text.insert_str(at, inserted);
+                buffer.insert(at, inserted);
+            }
+            assert_eq!(buffer.len_lines(), text.split('\n').count());
+        }
+        assert_text(&buffer, &text);
+    }
+
+    #[test]
+    fn mapped_file_is_indexed_as_lines_are_asked_for() {
+        let path = std::env::temp_dir().join(format!("nox-buffer-{}", std::process::id()));
+        let text = "line\n".repeat(1000);
+        std::fs::write(&path, format!("{}tail", text)).unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        let map = unsafe { Mmap::map(&file).unwrap() };
+        std::fs::remove_file(&path).unwrap();
+
+        // Leave the last bytes out, as for a file ending in something else
+        let mut buffer = Buffer::mapped(map, 0..text.len());
+        assert!(buffer.is_mapped());
+        assert_eq!(buffer.line(999), "line");
+        assert!(buffer.is_indexed());
+        assert_text(&buffer, &text);
+
+        buffer.insert(0, "new\n");
+        buffer.remove(4..9);
+        assert_text(&buffer, &format!("new\n{}", "line\n".repeat(999)));
+    }
+
+    // A buffer of `bytes` mapped from a temporary file
+    fn mapped(name: &str, bytes: &[u8]) -> Buffer {
+        let path = std::env::temp_dir().join(format!("nox-{}-{}", name, std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        let map = unsafe { Mmap::map(&file).unwrap() };
+        std::fs::remove_file(&path).unwrap();
+        Buffer::mapped(map, 0..bytes.len())
+    }
+
+    #[test]
+    fn bytes_that_arent_utf8_keep_their_offsets() {
+        let buffer = mapped("invalid", b"ok\n\xFFbad \xE2\x82\n\xC3\xA9");
+        assert_eq!(buffer.line(1), "?bad ??");
+        assert_eq!(buffer.line_range(1), 3..10);
+        assert_eq!(buffer.line(2), "é");
+        assert_eq!(buffer.take_invalid_utf8(), Some(3));
+        // Only reported once
+        assert_eq!(buffer.take_invalid_utf8(), None);
+        assert_eq!(
+            buffer.chunks().collect::<Vec<_>>().concat(),
+            b"ok\n\xFFbad \xE2\x82\n\xC3\xA9"
+        );
+    }
+
+    #[test]
+    fn characters_across_index_chunks_are_utf8() {
+        let mut bytes = "a".repeat(INDEX_CHUNK - 1).into_bytes();
+        bytes.extend_from_slice("é\nb".as_bytes());
+        let buffer = mapped("chunks", &bytes);
+        // The first chunk would end inside the 'é'
+        assert!(buffer.index_more());
+        assert!(!buffer.index_more());
+        assert_eq!(buffer.line(1), "b");
+        assert_eq!(buffer.take_invalid_utf8(), None);
+    }
+
+    #[test]
+    fn crlf_line_breaks_are_left_out_of_lines() {
+        let bytes = b"one\r\ntwo\nthr\ree\r\n\r\nend\r";
+        let buffer = mapped("crlf", bytes);
+        assert_eq!(buffer.lines(0..6), ["one", "two", "thr\ree", "", "end\r"]);
+        assert_eq!(buffer.line(0), "one");
+        assert_eq!(buffer.line_range(0), 0..3);
+        assert_eq!(buffer.line_break(0), 3..5);
+        assert_eq!(buffer.line_break(1), 8..9);
+        // Only a '\r' right before the '\n' is part of the line break
+        assert_eq!(buffer.line_range(2), 9..15);
+        assert_eq!(buffer.line_range(3), 17..17);
+        assert_eq!(buffer.line_break(3), 17..19);
+        // Nothing ends the last line, so its '\r' is text
+        assert_eq!(buffer.line(4), "end\r");
+        assert_eq!(buffer.line_break(4), 23..23);
+        assert_eq!(buffer.lines(1..3), ["two", "thr\ree"]);
+    }
+}
@@ -0,0 +1,712 @@
+use std::collections::BTreeMap;
+
+use nox_editor::encoding::{self, Encoding, FileFormat, LineEnding};
+use nox_editor::keymap::{self, Keymap};
+use nox_editor::picker::{Picker, PickerItem};
+use nox_editor::selection::SelectionMode;
+use nox_editor::{FileManager, InputAction, ToastType};
+
+use crate::fm;
+
+use crate::renderer;
+use crate::utils::get_terminal_size;
+
+// What the main loop does after a command ran
+#[derive(PartialEq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+// A named editor action that keys can be bound to
+pub struct Command {
+    pub name: &'static str,
+    pub title: &'static str,
+    // Bindings before the config has its say
+    pub keys: &'static [&'static str],
+    pub run: fn(&mut FileManager) -> Flow,
+}
+
+pub const COMMANDS: &[Command] = &[
+    // Movement, plain or extending the selection
+    Command {
+        name: "move_up",
+        title: "Move up",
+        keys: &["up"],
+        run: |fm| moving(fm, false, |fm| fm.move_row(-1, renderer::text_width(fm))),
+    },
+    Command {
+        name: "move_down",
+        title: "Move down",
+        keys: &["down"],
+        run: |fm| moving(fm, false, |fm| fm.move_row(1, renderer::text_width(fm))),
+    },
+    Command {
+        name: "move_left",
+        title: "Move left",
+        keys: &["left"],
+        run: |fm| moving(fm, false, |fm| fm.move_pointer(0, -1)),
+    },
+    Command {
+        name: "move_right",
+        title: "Move right",
+        keys: &["right"],
+        run: |fm| moving(fm, false, |fm| fm.move_pointer(0, 1)),
+    },
+    Command {
+        name: "move_word_left",
+        title: "Move to previous word",
+        keys: &["ctrl+left"],
+        run: |fm| moving(fm, false, |fm| fm.move_word(false)),
+    },
+    Command {
+        name: "move_word_right",
+        title: "Move to next word",
+        keys: &["ctrl+right"],
+        run: |fm| moving(fm, false, |fm| fm.move_word(true)),
+    },
+    Command {
+        name: "move_line_start",
+        title: "Move to start of line",
+        keys: &["home"],
+        run: |fm| moving(fm, false, |fm| fm.move_to_line_start()),
+    },
+    Command {
+        name: "move_line_end",
+        title: "Move to end of line",
+        keys: &["end"],
+        run: |fm| moving(fm, false, |fm| fm.move_to_line_end()),
+    },
+    Command {
+        name: "page_up",
+        title: "Page up",
+        keys: &["pageup"],
+        run: |fm| moving(fm, false, |fm| fm.move_pointer(-page_height(), 0)),
+    },
+    Command {
+        name: "page_down",
+        title: "Page down",
+        keys: &["pagedown"],
+        run: |fm| moving(fm, false, |fm| fm.move_pointer(page_height(), 0)),
+    },
+    Command {
+        name: "select_up",
+        title: "Select up",
+        keys: &["shift+up"],
+        run: |fm| moving(fm, true, |fm| fm.move_row(-1, renderer::text_width(fm))),
+    },
+    Command {
+        name: "select_down",
+        title: "Select down",
+        keys: &["shift+down"],
+        run: |fm| moving(fm, true, |fm| fm.move_row(1, renderer::text_width(fm))),
+    },
+    Command {
+        name: "select_left",
+        title: "Select left",
+        keys: &["shift+left"],
+        run: |fm| moving(fm, true, |fm| fm.move_pointer(0, -1)),
+    },
+    Command {
+        name: "select_right",
+        title: "Select right",
+        keys: &["shift+right"],
+        run: |fm| moving(fm, true, |fm| fm.move_pointer(0, 1)),
+    },
+    Command {
+        name: "select_word_left",
+        title: "Select to previous word",
+        keys: &["ctrl+shift+left"],
+        run: |fm| moving(fm, true, |fm| fm.move_word(false)),
+    },
+    Command {
+        name: "select_word_right",
+        title: "Select to next word",
+        keys: &["ctrl+shift+right"],
+        run: |fm| moving(fm, true, |fm| fm.move_word(true)),
+    },
+    Command {
+        name: "select_line_start",
+        title: "Select to start of line",
+        keys: &["shift+home"],
+        run: |fm| moving(fm, true, |fm| fm.move_to_line_start()),
+    },
+    Command {
+        name: "select_line_end",
+        title: "Select to end of line",
+        keys: &["shift+end"],
+        run: |fm| moving(fm, true, |fm| fm.move_to_line_end()),
+    },
+    Command {
+        name: "select_page_up",
+        title: "Select a page up",
+        keys: &["shift+pageup"],
+        run: |fm| moving(fm, true, |fm| fm.move_pointer(-page_height(), 0)),
+    },
+    Command {
+        name: "select_page_down",
+        title: "Select a page down",
+        keys: &["shift+pagedown"],
+        run: |fm| moving(fm, true, |fm| fm.move_pointer(page_height(), 0)),
+    },
+    Command {
+        name: "mark",
+        title: "Set or clear a characterwise mark",
+        keys: &["ctrl+space"],
+        run: |fm| {
+            fm.toggle_mark(SelectionMode::Characterwise);
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "mark_lines",
+        title: "Set or clear a linewise mark",
+        keys: &["ctrl+l"],
+        run: |fm| {
+            fm.toggle_mark(SelectionMode::Linewise);
+            Flow::Continue
+        },
+    },
+    // Editing
+    Command {
+        name: "new_line",
+        title: "Insert a new line",
+        keys: &["enter"],
+        run: |fm| {
+            fm.new_line();
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "indent",
+        title: "Insert a tab",
+        keys: &["tab"],
+        run: |fm| {
+            fm.tab();
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "delete_backward",
+        title: "Delete the character before the cursor",
+        keys: &["backspace"],
+        run: |fm| {
+            fm.delete_char();
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "delete_forward",
+        title: "Delete the character under the cursor",
+        keys: &["delete"],
+        run: |fm| {
+            fm.delete_forward();
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "undo",
+        title: "Undo",
+        keys: &["ctrl+z"],
+        run: |fm| {
+            if !fm.undo() {
+                fm.add_toast("Nothing to undo", 1500, ToastType::Info);
+            }
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "redo",
+        title: "Redo",
+        keys: &["ctrl+y"],
+        run: |fm| {
+            if !fm.redo() {
+                fm.add_toast("Nothing to redo", 1500, ToastType::Info);
+            }
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "copy",
+        title: "Copy selection or line",
+        keys: &["ctrl+c"],
+        run: |fm| {
+            fm.copy();
+            fm.add_toast("Copied", 1500, ToastType::Info);
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "cut",
+        title: "Cut selection or line",
+        keys: &["ctrl+x"],
+        run: |fm| {
+            fm.cut();
+            fm.add_toast("Cut", 1500, ToastType::Info);
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "paste",
+        title: "Paste",
+        keys: &["ctrl+v"],
+        run: |fm| {
+            if !fm.paste() {
+                fm.add_toast("Nothing to paste", 1500, ToastType::Info);
+            }
+            Flow::Continue
+        },
+    },
+    // Search
+    Command {
+        name: "find",
+        title: "Find",
+        keys: &["ctrl+f"],
+        run: |fm| {
+            fm.start_search();
+            fm.input_handler
+                .start_input_with_prompt("Find", InputAction::Find);
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "replace",
+        title: "Find and replace",
+        keys: &["ctrl+r"],
+        run: |fm| {
+            fm.start_search();
+            fm.input_handler
+                .start_input_with_prompt("Replace", InputAction::Replace);
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "find_next",
+        title: "Next match",
+        keys: &["f3"],
+        run: |fm| {
+            if !fm.search_next(true) {
+                fm.add_toast("No matches", 1500, ToastType::Info);
+            }
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "find_previous",
+        title: "Previous match",
+        keys: &["shift+f3"],
+        run: |fm| {
+            if !fm.search_next(false) {
+                fm.add_toast("No matches", 1500, ToastType::Info);
+            }
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "cancel",
+        title: "Clear search and selection",
+        keys: &["esc"],
+        run: |fm| {
+            fm.clear_search();
+            fm.clear_selection();
+            Flow::Continue
+        },
+    },
+    // View
+    Command {
+        name: "toggle_wrap",
+        title: "Toggle word wrap",
+        keys: &["alt+w"],
+        run: |fm| {
+            fm.toggle_soft_wrap();
+            let state = if fm.doc().soft_wrap { "on" } else { "off" };
+            fm.add_toast(&format!("Word wrap {}", state), 1500, ToastType::Info);
+            Flow::Continue
+        },
+    },
+    // Files and tabs
+    Command {
+        name: "toggle_tree",
+        title: "Toggle file tree",
+        keys: &["ctrl+b"],
+        run: |fm| {
+            fm.toggle_tree();
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "focus_tree",
+        title: "Switch focus between file tree and editor",
+        keys: &["ctrl+e"],
+        run: |fm| {
+            fm.toggle_tree_focus();
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "change_format",
+        title: "Change encoding or line endings",
+        keys: &[],
+        run: |fm| {
+            open_format_choice(fm);
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "hex_edit",
+        title: "Toggle byte editing in hex view",
+        keys: &["alt+e"],
+        run: |fm| {
+            toggle_hex_editing(fm);
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "save",
+        title: "Save",
+        keys: &["ctrl+s"],
+        run: |fm| {
+            match fm.save() {
+                Ok(_) => {
+                    fm.add_toast("File saved successfully!", 3000, ToastType::Success);
+                }
+                Err(e) => {
+                    fm.add_toast(&format!("Error saving file: {}", e), 5000, ToastType::Error);
+                }
+            }
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "save_as",
+        title: "Save as",
+        keys: &["alt+s"],
+        run: |fm| {
+            fm.input_handler
+                .start_input_with_prompt("Save As", InputAction::SaveAs);
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "open_browser",
+        title: "Open file browser",
+        keys: &["ctrl+o"],
+        run: |fm| {
+            open_browser(fm);
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "next_tab",
+        title: "Next tab",
+        keys: &["ctrl+pagedown", "alt+right"],
+        run: |fm| {
+            fm.cycle_tab(true);
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "prev_tab",
+        title: "Previous tab",
+        keys: &["ctrl+pageup", "alt+left"],
+        run: |fm| {
+            fm.cycle_tab(false);
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "close_tab",
+        title: "Close tab",
+        keys: &["ctrl+w"],
+        run: |fm| {
+            // Ask first if there are unsaved changes
+            if fm.is_modified() {
+                let prompt = format!("Save changes to {}?", fm.doc().file_info.name);
+                fm.input_handler
+                    .start_input_with_prompt(&prompt, InputAction::ConfirmClose);
+            } else {
+                fm.close_tab();
+            }
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "find_file",
+        title: "Find file in project",
+        keys: &["ctrl+t"],
+        run: |fm| {
+            open_finder(fm);
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "command_palette",
+        title: "Show all commands",
+        keys: &["ctrl+p", "f1"],
+        run: |fm| {
+            open_palette(fm);
+            Flow::Continue
+        },
+    },
+    Command {
+        name: "quit",
+        title: "Quit",
+        keys: &["ctrl+q"],
+        run: |fm| {
+            // Once every unsaved document is dealt with
+            if request_quit(fm) {
+                Flow::Quit
+            } else {
+                Flow::Continue
+            }
+        },
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| command.name == name)
+}
+
+// The default bindings with `[keybindings]` from the config on top, and
+// what was wrong with the config ones
+pub fn keymap(bindings: &BTreeMap<String, String>) -> (Keymap, Vec<String>) {
+    let mut keymap = Keymap::new();
+    for command in COMMANDS {
+        for keys in command.keys {
+            let keys = keymap::parse_sequence(keys).expect("default bindings parse");
+            keymap.bind(keys, command.name);
+        }
+    }
+
+    let names: Vec<&str> = COMMANDS.iter().map(|command| command.name).collect();
+    let errors = keymap.configure(bindings, &names);
+    (keymap, errors)
+}
+
+// Run a movement, with the selection following along when `select` is set
+// and dropped otherwise
+fn moving(fm: &mut FileManager, select: bool, motion: impl FnOnce(&mut FileManager)) -> Flow {
+    fm.update_selection(select);
+    motion(fm);
+    Flow::Continue
+}
+
+// Lines to jump for Page Up / Page Down
+fn page_height() -> isize {
+    let (rows, _) = get_terminal_size().unwrap_or((24, 80));
+    std::cmp::max(1, rows as isize - 8)
+}
+
+// Open the file browser in the directory of the current file, or the
+// working directory for an untitled one
+fn open_browser(fm: &mut FileManager) {
+    let start_path = if fm.doc().file_info.is_untitled() {
+        std::env::current_dir()
+            .unwrap_or_else(|_| std::path::PathBuf::from("."))
+            .to_string_lossy()
+            .to_string()
+    } else {
+        // A bare file name has an empty parent, the working directory
+        std::path::Path::new(&fm.doc().file_info.path)
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or_else(|| std::path::Path::new("."))
+            .to_string_lossy()
+            .to_string()
+    };
+
+    match fm.file_browser.open_browser(&start_path) {
+        Ok(_) => {
+            fm.add_toast(
+                "File browser opened - Use ↑/↓ to navigate, Enter to select, Backspace to go up, ESC to close",
+                4000,
+                ToastType::Info,
+            );
+        }
+        Err(e) => {
+            fm.add_toast(&e, 5000, ToastType::Error);
+        }
+    }
+}
+
+// List every command with its binding, to be picked by typing part of
+// its title. Items are in the same order as `COMMANDS`.
+fn open_palette(fm: &mut FileManager) {
+    let items = COMMANDS
+        .iter()
+        .map(|command| PickerItem {
+            label: command.title.to_string(),
+            detail: fm.keymap.binding_for(command.name).unwrap_or_default(),
+        })
+        .collect();
+    fm.picker = Some(Picker::new(items));
+    fm.input_handler
+        .start_input_with_prompt("Command", InputAction::CommandPalette);
+}
+
+// Run the command picked in the palette
+pub fn run_picked(fm: &mut FileManager) -> Flow {
+    let picked = fm.picker.take().and_then(|picker| picker.selected_item());
+    match picked {
+        Some(index) => (COMMANDS[index].run)(fm),
+        None => Flow::Continue,
+    }
+}
+
+// List the files of the project, the tree under the working directory, to
+// be picked by typing part of their path
+fn open_finder(fm: &mut FileManager) {
+    let root = std::env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from("."));
+    let files = fm::project_files(&root);
+    if files.is_empty() {
+        fm.add_toast("No files found in project", 3000, ToastType::Warning);
+        return;
+    }
+
+    let items = files
+        .into_iter()
+        .map(|path| PickerItem {
+            label: path,
+            detail: String::new(),
+        })
+        .collect();
+    fm.picker = Some(Picker::new(items));
+    fm.input_handler
+        .start_input_with_prompt("Open file", InputAction::FindFile);
+}
+
+// Open the file picked in the finder
+pub fn open_picked_file(fm: &mut FileManager) {
+    let Some(picker) = fm.picker.take() else {
+        return;
+    };
+    if let Some(index) = picker.selected_item() {
+        let path = picker.items[index].label.clone();
+        fm::open_in_editor(fm, &path);
+    }
+}
+
+// Changes one aspect of how a file is written
+type Conversion = fn(&mut FileFormat);
+
+// Ways to change how a file is written, as offered by `change_format`
+const FORMAT_CHOICES: &[(&str, Conversion)] = &[
+    ("Line endings: LF", |f| {
+        f.line_ending = LineEnding::Lf;
+        f.mixed_endings = false;
+    }),
+    ("Line endings: CRLF", |f| {
+        f.line_ending = LineEnding::Crlf;
+        f.mixed_endings = false;
+    }),
+    ("Line endings: CR", |f| {
+        f.line_ending = LineEnding::Cr;
+        f.mixed_endings = false;
+    }),
+    ("Encoding: UTF-8", |f| {
+        f.encoding = Encoding::Utf8;
+        f.bom = false;
+    }),
+    ("Encoding: UTF-8 with BOM", |f| {
+        f.encoding = Encoding::Utf8;
+        f.bom = true;
+    }),
+    ("Encoding: UTF-16LE", |f| {
+        f.encoding = Encoding::Utf16Le;
+        f.bom = true;
+    }),
+    ("Encoding: UTF-16BE", |f| {
+        f.encoding = Encoding::Utf16Be;
+        f.bom = true;
+    }),
+    ("Encoding: Latin-1", |f| {
+        f.encoding = Encoding::Latin1;
+        f.bom = false;
+    }),
+    ("Final newline: add", |f| f.final_newline = true),
+    ("Final newline: remove", |f| f.final_newline = false),
+];
+
+// Let hex digits overwrite bytes in a binary file, or make it read-only
+// again
+fn toggle_hex_editing(fm: &mut FileManager) {
+    let Some(hex) = fm.doc_mut().hex.as_mut() else {
+        fm.add_toast(
+            "Byte editing only works on binary files",
+            3000,
+            ToastType::Info,
+        );
+        return;
+    };
+    hex.toggle_editing();
+    let message = if hex.editing {
+        "Byte editing on: type hex digits to overwrite"
+    } else {
+        "Byte editing off"
+    };
+    fm.add_toast(message, 2000, ToastType::Info);
+}
+
+// List the conversions, marking the ones that are already the case
+fn open_format_choice(fm: &mut FileManager) {
+    if fm.doc().hex.is_some() {
+        fm.add_toast("A binary file has no text encoding", 3000, ToastType::Info);
+        return;
+    }
+    let current = fm.doc().format;
+    let items = FORMAT_CHOICES
+        .iter()
+        .map(|(label, apply)| {
+            let mut format = current;
+            apply(&mut format);
+            PickerItem {
+                label: label.to_string(),
+                detail: if format == current { "current" } else { "" }.to_string(),
+            }
+        })
+        .collect();
+    fm.picker = Some(Picker::new(items));
+    fm.input_handler
+        .start_input_with_prompt("Convert to", InputAction::FileFormat);
+}
+
+// Switch the document to the format picked. Text the new encoding can't
+// hold is refused now rather than when saving.
+pub fn apply_picked_format(fm: &mut FileManager) {
+    let picked = fm.picker.take().and_then(|picker| picker.selected_item());
+    let Some(index) = picked else {
+        return;
+    };
+    let (label, apply) = FORMAT_CHOICES[index];
+    let mut format = fm.doc().format;
+    apply(&mut format);
+
+    let chunks = fm.doc().buffer.chunks();
+    if let Err(e) = encoding::write_encoded(chunks, &format, &mut std::io::sink()) {
+        fm.add_toast(&format!("Cannot convert: {}", e), 5000, ToastType::Error);
+        return;
+    }
+    fm.set_format(format);
+    fm.add_toast(
+        &format!("{}, save to write the file", label),
+        3000,
+        ToastType::Info,
+    );
+}
+
+// Ask about the next document with unsaved changes before quitting. Returns
+// true if there are none and the editor can exit right away.
+pub fn request_quit(fm: &mut FileManager) -> bool {
+    let Some(index) = fm.first_modified_tab() else {
+        return true;
+    };
+
+    fm.switch_tab(index);
+    let prompt = format!(
+        "Save changes to {} before quitting?",
+        fm.doc().file_info.name
+    );
+    fm.input_handler
+        .start_input_with_prompt(&prompt, InputAction::ConfirmQuit);
+    false
+}
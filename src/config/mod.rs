@@ -0,0 +1,250 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::highlight::{SYNTAX_SET, THEME_SET};
+
+// User settings from ~/.config/nox/config.toml (or $XDG_CONFIG_HOME/nox).
+// Every key is optional. Settings in a `[languages.<name>]` table apply to
+// files of that language only, where the name is a file extension ("md")
+// or a syntax name ("markdown"):
+//
+//     tab_width = 4
+//     theme = "base16-ocean.dark"
+//
+//     [languages.markdown]
+//     wrap = true
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub tab_width: usize,
+    // Insert a tab character instead of spaces
+    pub hard_tabs: bool,
+    pub theme: String,
+    pub line_numbers: bool,
+    pub wrap: bool,
+    // Keep the previous version of a saved file as `<name>~`
+    pub backup: bool,
+    // Files of at least this many megabytes are mapped into memory and
+    // read as they are scrolled through, without syntax highlighting.
+    // Zero turns it off.
+    pub large_file_mb: u64,
+    pub autosave: Autosave,
+    // Key chord → command name
+    pub keybindings: BTreeMap<String, String>,
+    pub languages: BTreeMap<String, LanguageSettings>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            tab_width: 4,
+            hard_tabs: false,
+            theme: "base16-ocean.dark".to_string(),
+            line_numbers: true,
+            wrap: false,
+            backup: false,
+            large_file_mb: 64,
+            autosave: Autosave::default(),
+            keybindings: BTreeMap::new(),
+            languages: BTreeMap::new(),
+        }
+    }
+}
+
+// The settings that apply to one file
+#[derive(Clone, Debug)]
+pub struct EditorSettings {
+    pub tab_width: usize,
+    pub hard_tabs: bool,
+    pub theme: String,
+    pub line_numbers: bool,
+    pub wrap: bool,
+}
+
+// Overrides for one language, unset ones fall back to the top level
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LanguageSettings {
+    pub tab_width: Option<usize>,
+    pub hard_tabs: Option<bool>,
+    pub theme: Option<String>,
+    pub line_numbers: Option<bool>,
+    pub wrap: Option<bool>,
+}
+
+// When to save on its own. Zero turns a trigger off.
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Autosave {
+    // Seconds without input
+    pub idle: u64,
+    // Number of edits since the last save
+    pub edits: usize,
+    // When the terminal loses focus
+    pub focus_lost: bool,
+}
+
+// Where the config file lives
+pub fn config_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("nox").join("config.toml"))
+}
+
+impl Config {
+    // Read the config file. A missing file is the default config; one that
+    // can't be read or doesn't make sense is an error saying why.
+    pub fn load() -> Result<Config, String> {
+        let Some(path) = config_path() else {
+            return Ok(Config::default());
+        };
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(format!("Cannot read {}: {}", path.display(), e)),
+        };
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let config: Config = toml::from_str(text).map_err(|e| match e.span() {
+            Some(span) => {
+                let line = text[..span.start].matches('\n').count() + 1;
+                format!("line {}: {}", line, e.message())
+            }
+            None => e.message().to_string(),
+        })?;
+
+        let themes = std::iter::once(&config.theme).chain(
+            config
+                .languages
+                .values()
+                .filter_map(|language| language.theme.as_ref()),
+        );
+        for theme in themes {
+            if !THEME_SET.themes.contains_key(theme) {
+                return Err(format!("unknown theme \"{}\"", theme));
+            }
+        }
+        if config.tab_width == 0 || config.languages.values().any(|l| l.tab_width == Some(0)) {
+            return Err("tab_width must be at least 1".to_string());
+        }
+        Ok(config)
+    }
+
+    // Settings for a file called `name`, with the overrides for its
+    // language applied
+    pub fn for_file(&self, name: &str) -> EditorSettings {
+        let extension = name.split('.').next_back().unwrap_or("");
+        let syntax = SYNTAX_SET
+            .find_syntax_by_extension(extension)
+            .map(|syntax| syntax.name.as_str());
+
+        let mut settings = EditorSettings {
+            tab_width: self.tab_width,
+            hard_tabs: self.hard_tabs,
+            theme: self.theme.clone(),
+            line_numbers: self.line_numbers,
+            wrap: self.wrap,
+        };
+        for (language, overrides) in &self.languages {
+            let matches = language.eq_ignore_ascii_case(extension)
+                || syntax.is_some_and(|syntax| language.eq_ignore_ascii_case(syntax));
+            if !matches {
+                continue;
+            }
+            if let Some(tab_width) = overrides.tab_width {
+                settings.tab_width = tab_width;
+            }
+            if let Some(hard_tabs) = overrides.hard_tabs {
+                settings.hard_tabs = hard_tabs;
+            }
+            if let Some(theme) = &overrides.theme {
+                settings.theme = theme.clone();
+            }
+            if let Some(line_numbers) = overrides.line_numbers {
+                settings.line_numbers = line_numbers;
+            }
+            if let Some(wrap) = overrides.wrap {
+                settings.wrap = wrap;
+            }
+        }
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_config_is_the_default() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.tab_width, 4);
+        assert_eq!(config.theme, "base16-ocean.dark");
+        assert!(config.line_numbers);
+        assert!(config.languages.is_empty());
+    }
+
+    #[test]
+    fn parses_settings_tables_and_keybindings() {
+        let config = Config::parse(
+            "tab_width = 2\nhard_tabs = true\n\n[autosave]\nidle = 30\n\n\
+             [keybindings]\n\"ctrl+k\" = \"delete_line\"\n\n\
+             [languages.md]\nwrap = true\n",
+        )
+        .unwrap();
+        assert_eq!(config.tab_width, 2);
+        assert!(config.hard_tabs);
+        assert_eq!(config.autosave.idle, 30);
+        assert_eq!(config.keybindings["ctrl+k"], "delete_line");
+        assert_eq!(config.languages["md"].wrap, Some(true));
+        assert_eq!(config.languages["md"].tab_width, None);
+    }
+
+    #[test]
+    fn errors_say_which_line() {
+        let error = Config::parse("wrap = true\ntab_width = \"four\"\n").unwrap_err();
+        assert!(error.starts_with("line 2: "), "{}", error);
+        let error = Config::parse("wrap = true\n\ntabwidth = 4\n").unwrap_err();
+        assert!(error.starts_with("line 3: "), "{}", error);
+        assert!(error.contains("tabwidth"), "{}", error);
+        let error = Config::parse("[languages.rs]\nwarp = true\n").unwrap_err();
+        assert!(error.starts_with("line 2: "), "{}", error);
+    }
+
+    #[test]
+    fn rejects_unknown_themes() {
+        let error = Config::parse("theme = \"nope\"").unwrap_err();
+        assert_eq!(error, "unknown theme \"nope\"");
+        let error = Config::parse("[languages.rs]\ntheme = \"nope\"").unwrap_err();
+        assert_eq!(error, "unknown theme \"nope\"");
+        assert!(Config::parse("theme = \"InspiredGitHub\"").is_ok());
+    }
+
+    #[test]
+    fn rejects_a_zero_tab_width() {
+        assert!(Config::parse("tab_width = 0").is_err());
+        assert!(Config::parse("[languages.go]\ntab_width = 0").is_err());
+        assert!(Config::parse("tab_width = -1").is_err());
+    }
+
+    #[test]
+    fn language_overrides_match_extension_or_syntax_name() {
+        let config = Config::parse(
+            "tab_width = 4\n\n[languages.markdown]\nwrap = true\n\n\
+             [languages.RS]\ntab_width = 2\n",
+        )
+        .unwrap();
+        assert!(config.for_file("README.md").wrap);
+        assert!(config.for_file("README.md").line_numbers);
+        assert_eq!(config.for_file("main.rs").tab_width, 2);
+        assert!(!config.for_file("main.rs").wrap);
+        assert_eq!(config.for_file("notes.txt").tab_width, 4);
+        assert_eq!(config.for_file("").tab_width, 4);
+    }
+}
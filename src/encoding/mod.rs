@@ -0,0 +1,529 @@
+use std::io::{self, Write};
+use std::ops::Range;
+
+// How a file's text is laid out on disk: its character encoding, line
+// endings and whether it ends in a line break. Files are decoded into text
+// with plain '\n' line breaks when opened and written back the way they
+// came, byte for byte.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Latin1 => "Latin-1",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+    Cr,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::Crlf => "CRLF",
+            LineEnding::Cr => "CR",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FileFormat {
+    pub encoding: Encoding,
+    // Starts with a byte order mark
+    pub bom: bool,
+    pub line_ending: LineEnding,
+    // The file mixes line endings, so the text keeps its '\r's and only
+    // '\n' is a line break. `line_ending` is then Lf.
+    pub mixed_endings: bool,
+    // The last line ends in a line break
+    pub final_newline: bool,
+}
+
+// What new files are written as
+impl Default for FileFormat {
+    fn default() -> Self {
+        FileFormat {
+            encoding: Encoding::Utf8,
+            bom: false,
+            line_ending: LineEnding::Lf,
+            mixed_endings: false,
+            final_newline: true,
+        }
+    }
+}
+
+impl FileFormat {
+    // "UTF-8 BOM · CRLF", with "no final newline" when it's missing
+    pub fn describe(&self) -> String {
+        let mut text = self.encoding.name().to_string();
+        if self.bom && self.encoding == Encoding::Utf8 {
+            text.push_str(" BOM");
+        }
+        text.push_str(" · ");
+        if self.mixed_endings {
+            text.push_str("mixed line endings");
+        } else {
+            text.push_str(self.line_ending.name());
+        }
+        if !self.final_newline {
+            text.push_str(" · no final newline");
+        }
+        text
+    }
+}
+
+// Work out how `bytes` are encoded and decode them. The text that comes
+// back has '\n' line breaks and no break after the last line; nothing is
+// dropped, bytes that aren't valid UTF-8 or UTF-16 make the file Latin-1,
+// where every byte is a character.
+pub fn decode(bytes: &[u8]) -> (String, FileFormat) {
+    let mut format = FileFormat::default();
+
+    let utf8_with_bom = bytes
+        .strip_prefix(b"\xEF\xBB\xBF")
+        .and_then(|rest| std::str::from_utf8(rest).ok());
+    let utf16 = |rest: &[u8], encoding| match encoding {
+        Encoding::Utf16Be => decode_utf16(rest, u16::from_be_bytes),
+        _ => decode_utf16(rest, u16::from_le_bytes),
+    };
+    let text = if let Some(text) = utf8_with_bom {
+        format.bom = true;
+        text.to_string()
+    } else if let Some(text) = bytes
+        .strip_prefix(b"\xFF\xFE")
+        .and_then(|rest| utf16(rest, Encoding::Utf16Le))
+    {
+        format.bom = true;
+        format.encoding = Encoding::Utf16Le;
+        text
+    } else if let Some(text) = bytes
+        .strip_prefix(b"\xFE\xFF")
+        .and_then(|rest| utf16(rest, Encoding::Utf16Be))
+    {
+        format.bom = true;
+        format.encoding = Encoding::Utf16Be;
+        text
+    } else if let Some((encoding, text)) =
+        guess_utf16(bytes).and_then(|encoding| Some((encoding, utf16(bytes, encoding)?)))
+    {
+        format.encoding = encoding;
+        text
+    } else {
+        decode_utf8_or_latin1(bytes, &mut format)
+    };
+
+    // A file with one kind of line break is edited with '\n' and written
+    // back with its own. One that mixes them keeps them as they are.
+    let crlf = text.matches("\r\n").count();
+    let kinds = [
+        (LineEnding::Crlf, crlf),
+        (LineEnding::Cr, text.matches('\r').count() - crlf),
+        (LineEnding::Lf, text.matches('\n').count() - crlf),
+    ];
+    let mut used = kinds.iter().filter(|(_, count)| *count > 0);
+    let mut text = match (used.next(), used.next()) {
+        (Some((LineEnding::Crlf, _)), None) => {
+            format.line_ending = LineEnding::Crlf;
+            text.replace("\r\n", "\n")
+        }
+        (Some((LineEnding::Cr, _)), None) => {
+            format.line_ending = LineEnding::Cr;
+            text.replace('\r', "\n")
+        }
+        (Some(_), Some(_)) => {
+            format.mixed_endings = true;
+            text
+        }
+        _ => text,
+    };
+
+    format.final_newline = text.ends_with('\n');
+    if format.final_newline {
+        text.pop();
+    }
+    (text, format)
+}
+
+// Text with mixed line endings as plain '\n' line breaks, for when the file
+// is converted to one kind
+pub fn unify_line_endings(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+// Turn text with '\n' line breaks back into the bytes of a file in `format`
+pub fn encode(text: &str, format: &FileFormat) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() + 3);
+    write_encoded([text.as_bytes()], format, &mut bytes)?;
+    Ok(bytes)
+}
+
+// Write text with '\n' line breaks to `out` as a file in `format`, taking
+// it in `chunks` of UTF-8 that each end on a character boundary, so the
+// text is never copied whole.
+pub fn write_encoded<'a>(
+    chunks: impl IntoIterator<Item = &'a [u8]>,
+    format: &FileFormat,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    if format.bom {
+        match format.encoding {
+            Encoding::Utf8 => out.write_all(b"\xEF\xBB\xBF")?,
+            Encoding::Utf16Le | Encoding::Utf16Be => write_chars(&['\u{FEFF}'], format, out)?,
+            Encoding::Latin1 => {}
+        }
+    }
+    for chunk in chunks {
+        write_chunk(chunk, format, out)?;
+    }
+    if format.final_newline {
+        write_chunk(b"\n", format, out)?;
+    }
+    Ok(())
+}
+
+fn write_chunk(chunk: &[u8], format: &FileFormat, out: &mut dyn Write) -> io::Result<()> {
+    let ending = format.line_ending.as_str();
+    if format.encoding == Encoding::Utf8 {
+        if format.line_ending == LineEnding::Lf {
+            return out.write_all(chunk);
+        }
+        for (i, line) in chunk.split(|&b| b == b'\n').enumerate() {
+            if i > 0 {
+                out.write_all(ending.as_bytes())?;
+            }
+            out.write_all(line)?;
+        }
+        return Ok(());
+    }
+
+    let text =
+        std::str::from_utf8(chunk).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    // Characters are encoded a batch at a time
+    let mut batch = Vec::with_capacity(std::cmp::min(text.len(), BATCH) + 2);
+    for c in text.chars() {
+        if c == '\n' {
+            batch.extend(ending.chars());
+        } else {
+            batch.push(c);
+        }
+        if batch.len() >= BATCH {
+            write_chars(&batch, format, out)?;
+            batch.clear();
+        }
+    }
+    write_chars(&batch, format, out)
+}
+
+// Characters encoded at a time when writing UTF-16 or Latin-1
+const BATCH: usize = 1 << 16;
+
+fn write_chars(chars: &[char], format: &FileFormat, out: &mut dyn Write) -> io::Result<()> {
+    match format.encoding {
+        Encoding::Latin1 => {
+            let mut bytes = Vec::with_capacity(chars.len());
+            for &c in chars {
+                let byte = u8::try_from(c as u32).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("'{}' can't be written as Latin-1", c),
+                    )
+                })?;
+                bytes.push(byte);
+            }
+            out.write_all(&bytes)
+        }
+        _ => write_utf16(chars, format, out),
+    }
+}
+
+fn write_utf16(chars: &[char], format: &FileFormat, out: &mut dyn Write) -> io::Result<()> {
+    let big_endian = format.encoding == Encoding::Utf16Be;
+    let mut bytes = Vec::with_capacity(chars.len() * 2);
+    let mut units = [0; 2];
+    for c in chars {
+        for unit in c.encode_utf16(&mut units) {
+            if big_endian {
+                bytes.extend_from_slice(&unit.to_be_bytes());
+            } else {
+                bytes.extend_from_slice(&unit.to_le_bytes());
+            }
+        }
+    }
+    out.write_all(&bytes)
+}
+
+// A large file is used as it is on disk rather than decoded into a copy,
+// which only works for UTF-8, with line breaks kept as they are. Returns the
+// range of `bytes` holding the text, without a UTF-8 byte order mark or the
+// final line break, and the format to write it back in.
+pub fn undecoded_text(bytes: &[u8]) -> (Range<usize>, FileFormat) {
+    let mut format = FileFormat::default();
+    let mut text = 0..bytes.len();
+    if bytes.starts_with(b"\xEF\xBB\xBF") {
+        format.bom = true;
+        text.start = 3;
+    }
+    format.final_newline = bytes[text.clone()].ends_with(b"\n");
+    if format.final_newline {
+        text.end -= 1;
+    }
+    (text, format)
+}
+
+// Whether a file of `bytes` can be used undecoded: UTF-8 text, as far as
+// its start shows. Only the start is looked at, so this doesn't read much
+// of a mapped file.
+pub fn starts_as_utf8(bytes: &[u8]) -> bool {
+    if looks_binary(bytes) || guess_utf16(bytes).is_some() {
+        return false;
+    }
+    let head = &bytes[..std::cmp::min(bytes.len(), 8192)];
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        // A character cut off by the end of the sample is fine
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+// Whether `bytes` look like something other than text: a NUL byte outside
+// of UTF-16, control characters, or so many bytes that aren't valid UTF-8
+// that it can't be Latin-1 text either. Only the start of the file is
+// looked at.
+pub fn looks_binary(bytes: &[u8]) -> bool {
+    let sample = &bytes[..std::cmp::min(bytes.len(), 8192)];
+    // A byte order mark on something with an odd length isn't UTF-16
+    let utf16 = ((sample.starts_with(b"\xFF\xFE") || sample.starts_with(b"\xFE\xFF"))
+        && bytes.len().is_multiple_of(2))
+        || guess_utf16(bytes).is_some();
+    if utf16 {
+        return false;
+    }
+    if sample.contains(&0) {
+        return true;
+    }
+
+    // Control characters other than the usual whitespace, escape and form
+    // feed, counting bytes of broken UTF-8 sequences in the range that is
+    // control characters in Latin-1 too
+    let mut controls = 0;
+    let mut invalid = 0;
+    let mut rest = sample;
+    while !rest.is_empty() {
+        match std::str::from_utf8(rest) {
+            Ok(text) => {
+                controls += count_controls(text);
+                break;
+            }
+            Err(e) => {
+                let (valid, after) = rest.split_at(e.valid_up_to());
+                controls += count_controls(std::str::from_utf8(valid).unwrap_or(""));
+                // A sequence cut off by the end of the sample isn't broken
+                let Some(bad) = e.error_len() else {
+                    break;
+                };
+                invalid += bad;
+                controls += after[..bad]
+                    .iter()
+                    .filter(|byte| (0x80..0xA0).contains(*byte))
+                    .count();
+                rest = &after[bad..];
+            }
+        }
+    }
+    controls * 10 > sample.len() || invalid * 10 > sample.len() * 3
+}
+
+fn count_controls(text: &str) -> usize {
+    text.chars()
+        .filter(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t' | '\x0C' | '\x1B'))
+        .count()
+}
+
+fn decode_utf8_or_latin1(bytes: &[u8], format: &mut FileFormat) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => {
+            format.encoding = Encoding::Latin1;
+            bytes.iter().map(|&byte| byte as char).collect()
+        }
+    }
+}
+
+// None unless `bytes` are whole, valid UTF-16 code units, which are the
+// only ones that can be written back the same
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> Option<String> {
+    if !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let units = bytes.chunks_exact(2).map(|pair| unit([pair[0], pair[1]]));
+    char::decode_utf16(units).collect::<Result<_, _>>().ok()
+}
+
+// UTF-16 without a byte order mark: mostly ASCII text has a zero byte in
+// every other position
+fn guess_utf16(bytes: &[u8]) -> Option<Encoding> {
+    if bytes.len() < 2 || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let sample = &bytes[..std::cmp::min(bytes.len(), 1024)];
+    let pairs = sample.len() / 2;
+    let zero_at = |offset: usize| {
+        sample
+            .chunks_exact(2)
+            .filter(|pair| pair[offset] == 0 && pair[1 - offset] != 0)
+            .count()
+    };
+    // Nearly every pair, to not mistake binary data for text
+    if zero_at(1) * 10 >= pairs * 9 {
+        Some(Encoding::Utf16Le)
+    } else if zero_at(0) * 10 >= pairs * 9 {
+        Some(Encoding::Utf16Be)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Decoding and encoding again gives back the same bytes
+    fn round_trip(bytes: &[u8]) -> (String, FileFormat) {
+        let (text, format) = decode(bytes);
+        assert_eq!(encode(&text, &format).unwrap(), bytes, "{:?}", format);
+        (text, format)
+    }
+
+    #[test]
+    fn line_endings_round_trip() {
+        let (text, format) = round_trip(b"a\r\nb\r\n");
+        assert_eq!(text, "a\nb");
+        assert_eq!(format.line_ending, LineEnding::Crlf);
+        assert!(format.final_newline);
+
+        let (text, format) = round_trip(b"a\rb");
+        assert_eq!(text, "a\nb");
+        assert_eq!(format.line_ending, LineEnding::Cr);
+        assert!(!format.final_newline);
+
+        let (text, format) = round_trip(b"a\nb\n");
+        assert_eq!(text, "a\nb");
+        assert_eq!(format.line_ending, LineEnding::Lf);
+    }
+
+    #[test]
+    fn mixed_line_endings_are_kept() {
+        let (text, format) = round_trip(b"a\r\nb\nc\rd\r\n");
+        assert!(format.mixed_endings);
+        assert_eq!(text, "a\r\nb\nc\rd\r");
+        assert_eq!(unify_line_endings(&text), "a\nb\nc\nd\n");
+    }
+
+    #[test]
+    fn empty_file_stays_empty() {
+        let (text, format) = round_trip(b"");
+        assert_eq!(text, "");
+        assert!(!format.final_newline);
+        assert_eq!(round_trip(b"\n").0, "");
+    }
+
+    #[test]
+    fn byte_order_marks_round_trip() {
+        let (text, format) = round_trip("\u{FEFF}héllo\n".as_bytes());
+        assert_eq!(text, "héllo");
+        assert_eq!((format.encoding, format.bom), (Encoding::Utf8, true));
+        assert_eq!(format.describe(), "UTF-8 BOM · LF");
+
+        let (text, format) = round_trip(b"\xFF\xFEh\x00i\x00\r\x00\n\x00");
+        assert_eq!(text, "hi");
+        assert_eq!((format.encoding, format.bom), (Encoding::Utf16Le, true));
+        assert_eq!(format.line_ending, LineEnding::Crlf);
+
+        let (text, format) = round_trip(b"\xFE\xFF\x00h\xD8\x3D\xDE\x00");
+        assert_eq!(text, "h\u{1F600}");
+        assert_eq!(format.encoding, Encoding::Utf16Be);
+    }
+
+    #[test]
+    fn utf16_without_a_bom_is_guessed() {
+        let (text, format) = round_trip(b"a\x00b\x00\n\x00");
+        assert_eq!(text, "ab");
+        assert_eq!((format.encoding, format.bom), (Encoding::Utf16Le, false));
+        let (_, format) = round_trip(b"\x00a\x00b");
+        assert_eq!(format.encoding, Encoding::Utf16Be);
+    }
+
+    #[test]
+    fn broken_utf16_falls_back_to_latin1_byte_for_byte() {
+        // An odd length, and an unpaired surrogate
+        let (_, format) = round_trip(b"\xFF\xFEa\x00b");
+        assert_eq!(format.encoding, Encoding::Latin1);
+        let (_, format) = round_trip(b"\xFF\xFEa\x00\x00\xD8");
+        assert_eq!(format.encoding, Encoding::Latin1);
+    }
+
+    #[test]
+    fn invalid_utf8_is_latin1() {
+        let (text, format) = round_trip(b"caf\xE9\r\n");
+        assert_eq!(text, "café");
+        assert_eq!(format.encoding, Encoding::Latin1);
+        assert_eq!(format.describe(), "Latin-1 · CRLF");
+
+        let format = FileFormat {
+            encoding: Encoding::Latin1,
+            ..FileFormat::default()
+        };
+        assert!(encode("\u{20AC}", &format).is_err());
+    }
+
+    #[test]
+    fn chunks_encode_like_the_whole_text() {
+        let format = FileFormat {
+            encoding: Encoding::Utf16Le,
+            line_ending: LineEnding::Crlf,
+            ..FileFormat::default()
+        };
+        let mut out = Vec::new();
+        write_encoded(["a\nb".as_bytes(), "\nc".as_bytes()], &format, &mut out).unwrap();
+        assert_eq!(out, encode("a\nb\nc", &format).unwrap());
+    }
+
+    #[test]
+    fn only_utf8_text_is_used_undecoded() {
+        assert!(starts_as_utf8("héllo\r\n".as_bytes()));
+        assert!(starts_as_utf8(b""));
+        // Cut off in the middle of a character by the end of the sample
+        let mut bytes = "a".repeat(8191).into_bytes();
+        bytes.extend_from_slice("é".as_bytes());
+        assert!(starts_as_utf8(&bytes));
+
+        assert!(!starts_as_utf8(b"caf\xE9\n"));
+        assert!(!starts_as_utf8(b"\xFF\xFEh\x00i\x00"));
+        assert!(!starts_as_utf8(b"h\x00i\x00"));
+        assert!(!starts_as_utf8(b"\x7FELF\x02\x01\x00\x00"));
+    }
+}
@@ -1,13 +1,390 @@
 use std::{
-    fs::{self, DirEntry, File, ReadDir},
-    io::{self, BufRead},
+    fs, io,
+    path::{Component, Path},
 };
 
-use nox_editor::FileManager;
+use memmap2::Mmap;
+use nox_editor::encoding::{self, FileFormat};
+use nox_editor::swap;
+use nox_editor::{Buffer, FileInfo, FileManager, InputAction, ToastType};
 
-pub fn open_file(path: &str) -> io::Result<Vec<String>> {
+// Walking stops here so huge trees don't stall the finder
+const MAX_PROJECT_FILES: usize = 50_000;
+
+pub enum FileContents {
+    Text(Buffer, FileFormat),
+    // Not text, shown as a hex dump
+    Binary(Vec<u8>),
+}
+
+// Read a file along with how it is encoded, so it can be written back the
+// same way. Text files of `large_file_mb` megabytes or more are mapped
+// instead, to show them without reading them whole; those have to be UTF-8,
+// anything else that large isn't opened.
+pub fn open_file(path: &str, large_file_mb: u64) -> io::Result<FileContents> {
     let file = fs::File::open(path)?;
-    let reader = io::BufReader::new(file);
-    let lines: Vec<String> = reader.lines().filter_map(Result::ok).collect();
-    Ok(lines)
+    let size = file.metadata()?.len();
+    if large_file_mb > 0 && size >= large_file_mb << 20 {
+        // The map goes bad if another program truncates the file while it
+        // is open, the same risk every editor that maps files takes
+        let map = unsafe { Mmap::map(&file)? };
+        // Only the start is checked here, the rest as it is indexed
+        if !encoding::starts_as_utf8(&map) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not UTF-8 text, and too large to read whole (see large_file_mb)",
+            ));
+        }
+        let (text, format) = encoding::undecoded_text(&map);
+        return Ok(FileContents::Text(Buffer::mapped(map, text), format));
+    }
+
+    let bytes = fs::read(path)?;
+    if encoding::looks_binary(&bytes) {
+        return Ok(FileContents::Binary(bytes));
+    }
+    let (text, format) = encoding::decode(&bytes);
+    Ok(FileContents::Text(Buffer::new(text), format))
+}
+
+// Load what `open_file` read into a new tab
+pub fn open_contents(file_manager: &mut FileManager, contents: FileContents, file_info: FileInfo) {
+    match contents {
+        FileContents::Text(buffer, format) => file_manager.open_document(buffer, file_info, format),
+        FileContents::Binary(bytes) => file_manager.open_hex(bytes, file_info),
+    }
+    if file_manager.doc().format.mixed_endings {
+        let message = format!(
+            "{} mixes line endings, they are kept as they are (change_format unifies them)",
+            file_manager.doc().file_info.name
+        );
+        file_manager.add_toast(&message, 5000, ToastType::Warning);
+    }
+
+    match file_manager.claim_swap() {
+        Ok(None) => {}
+        Ok(Some(pid)) => {
+            let message = format!(
+                "{} is already open in another nox (pid {}), changes may overwrite each other",
+                file_manager.doc().file_info.name,
+                pid
+            );
+            file_manager.add_toast(&message, 6000, ToastType::Warning);
+        }
+        // A new file in a directory that doesn't exist yet
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => file_manager.add_toast(
+            &format!("Cannot write swap file: {}", e),
+            5000,
+            ToastType::Warning,
+        ),
+    }
+}
+
+// Ask what to do with unsaved changes a crashed nox left for the active
+// document
+pub fn ask_recovery(file_manager: &mut FileManager) {
+    let Some(recovery) = file_manager.recovery() else {
+        return;
+    };
+    let age = if recovery.newer {
+        "newer than the file"
+    } else {
+        "older than the file"
+    };
+    let prompt = format!(
+        "Unsaved changes to {} were left by a nox that didn't exit ({})",
+        file_manager.doc().file_info.name,
+        age
+    );
+    file_manager
+        .input_handler
+        .start_input_with_prompt(&prompt, InputAction::RecoverSwap);
+}
+
+// Show how the changes left in the swap file differ from the file, in a
+// tab of their own
+pub fn show_recovery_diff(file_manager: &mut FileManager) {
+    let Some(recovery) = file_manager.recovery() else {
+        return;
+    };
+    let name = file_manager.doc().file_info.name.clone();
+    let diff = swap::diff(
+        &file_manager.doc().buffer.contents(),
+        &recovery.text,
+        &format!("{} (on disk)", name),
+        &format!("{} (unsaved changes)", name),
+    );
+    let info = FileInfo {
+        name: format!("{}.diff", name),
+        path: "/".to_string(),
+    };
+    file_manager.open_document(Buffer::new(diff), info, FileFormat::default());
+}
+
+// Show `path` in the editor: switch to its tab if it's already open,
+// otherwise load it into a new one. Returns false if it couldn't be read.
+pub fn open_in_editor(file_manager: &mut FileManager, path: &str) -> bool {
+    if let Some(index) = file_manager.find_tab(path) {
+        // Already open, just bring it to the front
+        file_manager.switch_tab(index);
+        return true;
+    }
+
+    match open_file(path, file_manager.config.large_file_mb) {
+        Ok(contents) => {
+            open_contents(file_manager, contents, FileInfo::from_path(path));
+            let message = if file_manager.doc().hex.is_some() {
+                format!(
+                    "Opened: {} (binary, read-only, Alt+E to edit bytes)",
+                    file_manager.doc().file_info.name
+                )
+            } else {
+                format!("Opened: {}", file_manager.doc().file_info.name)
+            };
+            file_manager.add_toast(&message, 3000, ToastType::Success);
+            true
+        }
+        Err(e) => {
+            file_manager.add_toast(
+                &format!("Error opening file: {}", e),
+                5000,
+                ToastType::Error,
+            );
+            false
+        }
+    }
+}
+
+// Save every document with unsaved changes, as autosave does when input
+// stops or the terminal loses focus
+pub fn autosave(file_manager: &mut FileManager) {
+    let saved = file_manager.save_all();
+    report_autosave(file_manager, saved);
+}
+
+// Save the active document, as autosave does after enough edits. When that
+// fails it waits for as many edits again before retrying, instead of
+// failing again on every key.
+pub fn autosave_active(file_manager: &mut FileManager) {
+    let result = file_manager.save();
+    if result.is_err() {
+        file_manager.doc_mut().edits_since_save = 0;
+    }
+    let name = file_manager.doc().file_info.name.clone();
+    report_autosave(file_manager, vec![(name, result)]);
+}
+
+fn report_autosave(file_manager: &mut FileManager, saved: Vec<(String, io::Result<()>)>) {
+    let mut names = Vec::new();
+    for (name, result) in saved {
+        match result {
+            Ok(()) => names.push(name),
+            Err(e) => file_manager.add_toast(
+                &format!("Error autosaving {}: {}", name, e),
+                5000,
+                ToastType::Error,
+            ),
+        }
+    }
+    if !names.is_empty() {
+        file_manager.add_toast(
+            &format!("Autosaved {}", names.join(", ")),
+            1500,
+            ToastType::Success,
+        );
+    }
+}
+
+// Files under `root`, relative to it and sorted. Hidden files and whatever
+// .gitignore, .ignore or the global git excludes leave out are skipped.
+pub fn project_files(root: &Path) -> Vec<String> {
+    let walker = ignore::WalkBuilder::new(root)
+        .hidden(true)
+        .require_git(false)
+        .build();
+
+    let mut files: Vec<String> = walker
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_some_and(|ft| ft.is_file()))
+        .filter_map(|entry| {
+            let relative = entry.path().strip_prefix(root).ok()?;
+            Some(relative.to_string_lossy().to_string())
+        })
+        .take(MAX_PROJECT_FILES)
+        .collect();
+    files.sort();
+    files
+}
+
+// Run a file operation from the browser on its selected entry. `input` is
+// the name typed at the prompt, relative to the directory being shown.
+pub fn browser_operation(file_manager: &mut FileManager, action: InputAction, input: &str) {
+    let browser = &file_manager.file_browser;
+    let selected = browser
+        .get_selected_entry()
+        .filter(|entry| entry.name != "..")
+        .map(|entry| entry.path.clone());
+    let input = input.trim();
+    if input.is_empty() && action != InputAction::ConfirmDelete {
+        file_manager.add_toast("No name given", 3000, ToastType::Warning);
+        return;
+    }
+    let target = browser.dir.join(input);
+    let shown = match (&selected, action) {
+        (Some(source), InputAction::ConfirmDelete) => source.display().to_string(),
+        _ => target.display().to_string(),
+    };
+
+    let result = match (action, selected) {
+        (InputAction::NewFile, _) => create_file(&target).map(|_| "Created"),
+        (InputAction::NewDirectory, _) => fs::create_dir_all(&target).map(|_| "Created"),
+        (InputAction::RenamePath, Some(source)) => move_path(&source, &target).map(|_| {
+            file_manager.path_moved(&source, &target);
+            "Moved to"
+        }),
+        (InputAction::DuplicatePath, Some(source)) => {
+            copy_path(&source, &target).map(|_| "Duplicated as")
+        }
+        (InputAction::ConfirmDelete, Some(source)) => delete_path(&source).map(|_| "Deleted"),
+        _ => return,
+    };
+
+    match result {
+        Ok(done) => {
+            // Highlight what was made, or the directory it was made in
+            let name = match Path::new(input).components().next() {
+                Some(Component::Normal(name)) => Some(name.to_string_lossy().to_string()),
+                _ => None,
+            };
+            if let Err(e) = file_manager.file_browser.reload(name.as_deref()) {
+                file_manager.add_toast(&e, 5000, ToastType::Error);
+            }
+            if let Some(tree) = file_manager.tree.as_mut() {
+                tree.refresh();
+            }
+            file_manager.add_toast(&format!("{} {}", done, shown), 3000, ToastType::Success);
+        }
+        Err(e) => {
+            file_manager.add_toast(&format!("Error: {}", e), 5000, ToastType::Error);
+        }
+    }
+}
+
+fn create_file(path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map(|_| ())
+}
+
+// Rename or move, refusing to replace something already there
+fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    if to.exists() {
+        return Err(already_exists(to));
+    }
+    fs::rename(from, to)
+}
+
+// Copy a file, or a directory with everything in it. Symlinks inside the
+// directory are copied as links, so one that points back up the tree isn't
+// followed forever.
+fn copy_path(from: &Path, to: &Path) -> io::Result<()> {
+    if to.exists() {
+        return Err(already_exists(to));
+    }
+    if !from.is_dir() {
+        return fs::copy(from, to).map(|_| ());
+    }
+    if is_inside(to, from)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "cannot copy a directory into itself",
+        ));
+    }
+    fs::create_dir(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, &target)?;
+        } else {
+            copy_path(&entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+// Whether `to`, which doesn't exist yet, would be inside the directory
+// `dir`, with ".." and symlinks in either resolved
+fn is_inside(to: &Path, dir: &Path) -> io::Result<bool> {
+    let parent = match to.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    Ok(fs::canonicalize(parent)?.starts_with(fs::canonicalize(dir)?))
+}
+
+fn delete_path(path: &Path) -> io::Result<()> {
+    // A symlink to a directory is removed, not what it points to
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+fn already_exists(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("{} already exists", path.display()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An empty directory of its own for each test
+    fn scratch_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("nox-fm-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("dir/sub")).unwrap();
+        fs::write(dir.join("dir/file"), "text").unwrap();
+        dir
+    }
+
+    #[test]
+    fn directories_are_not_copied_into_themselves() {
+        let root = scratch_dir("into");
+        let dir = root.join("dir");
+        let roundabout = root.join("dir/../dir/sub/copy");
+        assert_eq!(
+            copy_path(&dir, &roundabout).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        std::os::unix::fs::symlink(&dir, root.join("link")).unwrap();
+        let through_link = root.join("link/sub/copy");
+        assert!(copy_path(&dir, &through_link).is_err());
+        assert!(!dir.join("sub/copy").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn symlinks_inside_are_copied_as_links() {
+        let root = scratch_dir("links");
+        let dir = root.join("dir");
+        std::os::unix::fs::symlink("..", dir.join("sub/up")).unwrap();
+        copy_path(&dir, &root.join("copy")).unwrap();
+
+        assert_eq!(fs::read_to_string(root.join("copy/file")).unwrap(), "text");
+        let link = root.join("copy/sub/up");
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new(".."));
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
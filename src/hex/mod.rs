@@ -0,0 +1,78 @@
+// Files that aren't text are shown as a hex dump: an offset, sixteen bytes
+// in hex and the same bytes as ASCII on each row. The view is read-only
+// until byte editing is turned on, then hex digits overwrite the byte
+// under the cursor one nibble at a time.
+
+pub const BYTES_PER_ROW: usize = 16;
+
+pub struct HexView {
+    pub bytes: Vec<u8>,
+    // Offset of the byte under the cursor
+    pub cursor: usize,
+    pub editing: bool,
+    // The next digit typed goes into the low half of the byte
+    pub low_nibble: bool,
+}
+
+impl HexView {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        HexView {
+            bytes,
+            cursor: 0,
+            editing: false,
+            low_nibble: false,
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.bytes.len().div_ceil(BYTES_PER_ROW).max(1)
+    }
+
+    // Move by `delta` bytes, stopping at either end
+    pub fn move_cursor(&mut self, delta: isize) {
+        let last = self.bytes.len().saturating_sub(1);
+        self.cursor = self.cursor.saturating_add_signed(delta).min(last);
+        self.low_nibble = false;
+    }
+
+    pub fn move_to_row_start(&mut self) {
+        self.cursor -= self.cursor % BYTES_PER_ROW;
+        self.low_nibble = false;
+    }
+
+    pub fn move_to_row_end(&mut self) {
+        let end = self.cursor - self.cursor % BYTES_PER_ROW + BYTES_PER_ROW - 1;
+        self.cursor = end.min(self.bytes.len().saturating_sub(1));
+        self.low_nibble = false;
+    }
+
+    pub fn toggle_editing(&mut self) {
+        self.editing = !self.editing;
+        self.low_nibble = false;
+    }
+
+    // Type a hex digit over the byte under the cursor, high half first,
+    // moving on to the next byte after the low half. Returns false if
+    // nothing changed.
+    pub fn type_digit(&mut self, digit: char) -> bool {
+        let Some(value) = digit.to_digit(16) else {
+            return false;
+        };
+        if !self.editing || self.cursor >= self.bytes.len() {
+            return false;
+        }
+
+        let byte = &mut self.bytes[self.cursor];
+        if self.low_nibble {
+            *byte = (*byte & 0xF0) | value as u8;
+            self.low_nibble = false;
+            if self.cursor + 1 < self.bytes.len() {
+                self.cursor += 1;
+            }
+        } else {
+            *byte = (*byte & 0x0F) | ((value as u8) << 4);
+            self.low_nibble = true;
+        }
+        true
+    }
+}
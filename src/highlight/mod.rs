@@ -0,0 +1,318 @@
+use syntect::highlighting::{HighlightIterator, HighlightState, Highlighter as ThemeHighlighter};
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxSet};
+use syntect::util::as_24_bit_terminal_escaped;
+
+use crate::Buffer;
+
+// Static for syntax/theme sets (load once)
+lazy_static::lazy_static! {
+    pub static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
+    pub static ref THEME_SET: ThemeSet = ThemeSet::load_defaults();
+}
+
+const DEFAULT_THEME: &str = "base16-ocean.dark";
+
+// Parser and highlighter state at the start of a line
+#[derive(Clone, PartialEq)]
+struct LineState {
+    parse: ParseState,
+    highlight: HighlightState,
+}
+
+// Syntax highlighting that carries state from line to line, so constructs
+// spanning several lines (block comments, docstrings, raw strings, code
+// fences) come out right. The state at the start of every line and the
+// escaped output of every line are cached; an edit only re-highlights from
+// the line it touched until the state matches what was cached again.
+pub struct Highlighter {
+    theme: &'static Theme,
+    // `states[i]` is the state at the start of line `i`
+    states: Vec<LineState>,
+    // Line text with terminal color escapes, for the first `spans.len()` lines
+    spans: Vec<String>,
+    // Lines that may be out of date. Lines after `stale.1` have the same text
+    // as when they were cached, so highlighting stops there as soon as the
+    // state going into one of them is what it was before.
+    stale: Option<(usize, usize)>,
+    // Leave the text plain
+    off: bool,
+}
+
+impl Highlighter {
+    // Highlighter for files with `extension`, plain text if none matches,
+    // in the colors of `theme`
+    pub fn new(extension: &str, theme: &str) -> Self {
+        let syntax = SYNTAX_SET
+            .find_syntax_by_extension(extension)
+            .unwrap_or_else(|| SYNTAX_SET.find_syntax_plain_text());
+        let theme = THEME_SET
+            .themes
+            .get(theme)
+            .unwrap_or(&THEME_SET.themes[DEFAULT_THEME]);
+
+        let highlighter = ThemeHighlighter::new(theme);
+        Highlighter {
+            theme,
+            states: vec![LineState {
+                parse: ParseState::new(syntax),
+                highlight: HighlightState::new(&highlighter, ScopeStack::new()),
+            }],
+            spans: Vec::new(),
+            stale: None,
+            off: false,
+        }
+    }
+
+    // Highlighter for a file called `name`. Names without a dot are looked
+    // up whole, which catches files like Makefile.
+    pub fn for_file(name: &str, theme: &str) -> Self {
+        Self::new(name.split('.').next_back().unwrap_or(""), theme)
+    }
+
+    // Highlighter for `buffer` holding the file called `name`. Large files
+    // stay plain: the state carries down from the first line, so showing
+    // the end of one would mean highlighting all of it.
+    pub fn for_buffer(name: &str, buffer: &Buffer, theme: &str) -> Self {
+        let mut highlighter = Self::for_file(name, theme);
+        highlighter.off = buffer.is_mapped();
+        highlighter
+    }
+
+    // Lines `line..=line + removed` were replaced by `line..=line + added`
+    pub fn edited(&mut self, line: usize, removed: usize, added: usize) {
+        let cached = self.spans.len();
+        if line < cached {
+            // Keep the cache lined up with the buffer so lines below the
+            // edit can be reused
+            let end = std::cmp::min(line + 1 + removed, cached);
+            let placeholder = self.states[line].clone();
+            self.spans
+                .splice(line + 1..end, std::iter::repeat_n(String::new(), added));
+            self.states
+                .splice(line + 1..end, std::iter::repeat_n(placeholder, added));
+        }
+
+        let changed = line + added;
+        self.stale = Some(match self.stale {
+            None => (line, changed),
+            Some((from, to)) if to >= line => (
+                std::cmp::min(from, line),
+                std::cmp::max((to + added).saturating_sub(removed), changed),
+            ),
+            Some((from, _)) => (std::cmp::min(from, line), changed),
+        });
+    }
+
+    // Forget everything from `line` on
+    pub fn invalidate_from(&mut self, line: usize) {
+        let line = match self.stale {
+            Some((from, _)) if from < line => from,
+            _ => line,
+        };
+        self.spans.truncate(line);
+        self.states.truncate(line + 1);
+        self.stale = None;
+    }
+
+    // Make sure the first `count` lines of `buffer` are highlighted
+    pub fn update(&mut self, buffer: &Buffer, count: usize) {
+        if self.off {
+            return;
+        }
+        let count = std::cmp::min(count, buffer.len_lines());
+        let cached = self.spans.len();
+        let mut line = match self.stale {
+            Some((from, _)) => std::cmp::min(from, cached),
+            None => cached,
+        };
+        if line >= count {
+            return;
+        }
+
+        let highlighter = ThemeHighlighter::new(self.theme);
+        let texts = buffer.lines(line..count);
+        for text in texts {
+            let mut state = self.states[line].clone();
+            let span = highlight(&mut state, &text, &highlighter);
+
+            if line < self.spans.len() {
+                self.spans[line] = span;
+            } else {
+                self.spans.push(span);
+            }
+
+            if let Some((_, to)) = self.stale {
+                // Unchanged text going in with the same state as before:
+                // everything from here on is already right
+                if line + 1 > to && line + 1 < self.spans.len() && self.states[line + 1] == state {
+                    // Carry on past the end of the cache if needed
+                    self.stale = None;
+                    self.update(buffer, count);
+                    return;
+                }
+            }
+
+            if line + 1 < self.states.len() {
+                self.states[line + 1] = state;
+            } else {
+                self.states.push(state);
+            }
+            line += 1;
+        }
+
+        // Stopped at `count` before catching up. What is cached past here
+        // follows from the old state, which no longer holds, so drop it.
+        if self.stale.take().is_some() {
+            self.invalidate_from(line);
+        }
+    }
+
+    // Highlighted `line`, if `update` has got to it
+    pub fn line(&self, line: usize) -> Option<&str> {
+        match self.stale {
+            Some((from, _)) if line >= from => None,
+            _ => self.spans.get(line).map(String::as_str),
+        }
+    }
+}
+
+// Highlight one line, advancing `state` to the start of the next
+fn highlight(state: &mut LineState, text: &str, highlighter: &ThemeHighlighter) -> String {
+    // The syntaxes expect every line to end in a newline
+    let line = format!("{}\n", text);
+    let Ok(ops) = state.parse.parse_line(&line, &SYNTAX_SET) else {
+        return text.to_string();
+    };
+
+    let regions: Vec<_> =
+        HighlightIterator::new(&mut state.highlight, &ops, &line, highlighter).collect();
+    let escaped = as_24_bit_terminal_escaped(&regions, false);
+    escaped.replacen('\n', "", 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str =
+        "fn main() {\n    let a = 1;\n}\n/* note\nstill */\nlet b = \"s\";\n// end";
+
+    fn rust() -> Highlighter {
+        Highlighter::for_file("main.rs", DEFAULT_THEME)
+    }
+
+    // Every line as highlighted by `highlighter`, after it is brought up to
+    // date
+    fn lines(highlighter: &mut Highlighter, buffer: &Buffer) -> Vec<String> {
+        highlighter.update(buffer, buffer.len_lines());
+        (0..buffer.len_lines())
+            .map(|line| highlighter.line(line).unwrap().to_string())
+            .collect()
+    }
+
+    // Apply edits one after another the way the editor reports them, then
+    // check the cache against highlighting the result from scratch
+    fn check_edits(edits: &[(usize, usize, &str)], update_between: bool) {
+        let mut buffer = Buffer::new(SOURCE.to_string());
+        let mut highlighter = rust();
+        lines(&mut highlighter, &buffer);
+
+        for &(at, removed, inserted) in edits {
+            let line = buffer.byte_to_line(at);
+            let removed_breaks = buffer.slice(at..at + removed).matches('\n').count();
+            buffer.remove(at..at + removed);
+            highlighter.edited(line, removed_breaks, 0);
+            buffer.insert(at, inserted);
+            highlighter.edited(line, 0, inserted.matches('\n').count());
+            if update_between {
+                lines(&mut highlighter, &buffer);
+            }
+        }
+
+        let expected = lines(&mut rust(), &buffer);
+        assert_eq!(lines(&mut highlighter, &buffer), expected, "{:?}", edits);
+    }
+
+    #[test]
+    fn plain_text_for_unknown_files() {
+        let buffer = Buffer::new("fn main() {}".to_string());
+        let mut highlighter = Highlighter::for_file("notes.unknown", DEFAULT_THEME);
+        let highlighted = lines(&mut highlighter, &buffer);
+        assert!(highlighted[0].contains("fn main() {}"));
+    }
+
+    #[test]
+    fn state_carries_over_lines() {
+        let buffer = Buffer::new(SOURCE.to_string());
+        let highlighted = lines(&mut rust(), &buffer);
+        // The second line of the block comment looks like the first one,
+        // not like the code after it
+        let color = |line: &str| line[..line.find('m').unwrap()].to_string();
+        assert_eq!(color(&highlighted[4]), color(&highlighted[3]));
+        assert_ne!(color(&highlighted[5]), color(&highlighted[4]));
+    }
+
+    #[test]
+    fn edited_lines_are_stale_until_updated() {
+        let buffer = Buffer::new(SOURCE.to_string());
+        let mut highlighter = rust();
+        lines(&mut highlighter, &buffer);
+        highlighter.edited(2, 0, 0);
+        assert!(highlighter.line(1).is_some());
+        assert!(highlighter.line(2).is_none());
+        // Everything after the edit may have changed with it
+        assert!(highlighter.line(6).is_none());
+        highlighter.update(&buffer, 3);
+        assert!(highlighter.line(2).is_some());
+    }
+
+    #[test]
+    fn edits_within_a_line() {
+        check_edits(&[(16, 3, "b")], true);
+        check_edits(&[(0, 2, "")], true);
+        check_edits(&[(SOURCE.len(), 0, " more")], true);
+    }
+
+    #[test]
+    fn edits_that_change_the_lines_after() {
+        // Opening and closing a comment above cached lines
+        check_edits(&[(0, 0, "/* ")], true);
+        check_edits(&[(0, 0, "/* "), (0, 3, "")], true);
+        check_edits(&[(27, 0, "*/")], true);
+        // Closing the block comment early, then removing it again
+        check_edits(&[(33, 0, " */"), (33, 3, "")], true);
+    }
+
+    #[test]
+    fn edits_that_add_and_remove_lines() {
+        check_edits(&[(12, 0, "\n\n/*\n")], true);
+        check_edits(&[(11, 15, "")], true);
+        check_edits(&[(11, 15, "{\n/*"), (0, 0, "\n")], true);
+        check_edits(&[(SOURCE.len(), 0, "\n/* open")], true);
+    }
+
+    #[test]
+    fn several_edits_before_an_update() {
+        check_edits(&[(0, 0, "/* "), (40, 0, "\nx\n"), (12, 0, "*/\n")], false);
+        check_edits(&[(40, 0, "\nx\n"), (0, 0, "\n\n"), (2, 0, "/*")], false);
+        check_edits(&[(12, 16, ""), (0, 0, "x\n")], false);
+    }
+
+    #[test]
+    fn partial_update_drops_what_followed_from_the_old_state() {
+        let mut buffer = Buffer::new(SOURCE.to_string());
+        let mut highlighter = rust();
+        lines(&mut highlighter, &buffer);
+
+        buffer.insert(0, "/* ");
+        highlighter.edited(0, 0, 0);
+        highlighter.update(&buffer, 2);
+        assert!(highlighter.line(1).is_some());
+        assert!(highlighter.line(2).is_none());
+        assert_eq!(
+            lines(&mut highlighter, &buffer),
+            lines(&mut rust(), &buffer)
+        );
+    }
+}
@@ -0,0 +1,418 @@
+use std::time::{Duration, Instant};
+
+use crate::{Buffer, Pointer};
+
+// Typing or deleting faster than this keeps extending the current undo step
+const COALESCE_TIMEOUT: Duration = Duration::from_millis(1500);
+// Oldest undo steps are dropped beyond this
+const HISTORY_LIMIT: usize = 1000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EditKind {
+    Insert,
+    Delete,
+}
+
+// A single reversible change to the buffer at byte offset `at`. `text` may
+// span several lines.
+#[derive(Clone, Debug)]
+pub struct Edit {
+    pub kind: EditKind,
+    pub at: usize,
+    pub text: String,
+}
+
+impl Edit {
+    pub fn insert(at: usize, text: &str) -> Self {
+        Edit {
+            kind: EditKind::Insert,
+            at,
+            text: text.to_string(),
+        }
+    }
+
+    pub fn delete(at: usize, text: &str) -> Self {
+        Edit {
+            kind: EditKind::Delete,
+            at,
+            text: text.to_string(),
+        }
+    }
+
+    // Byte offset just past `text`
+    pub fn end(&self) -> usize {
+        self.at + self.text.len()
+    }
+
+    pub fn apply(&self, buffer: &mut Buffer) {
+        match self.kind {
+            EditKind::Insert => buffer.insert(self.at, &self.text),
+            EditKind::Delete => {
+                buffer.remove(self.at..self.end());
+            }
+        }
+    }
+
+    pub fn revert(&self, buffer: &mut Buffer) {
+        match self.kind {
+            EditKind::Insert => {
+                buffer.remove(self.at..self.end());
+            }
+            EditKind::Delete => buffer.insert(self.at, &self.text),
+        }
+    }
+}
+
+// How an edit may be merged with the one before it
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EditGroup {
+    Typing,
+    Deleting,
+    Standalone,
+    // Always part of the step before it, e.g. the insert half of a replace
+    Joined,
+}
+
+// One undo step: every edit in it is undone or redone together
+struct Transaction {
+    // Identifies the buffer state this step leads to
+    id: u64,
+    edits: Vec<Edit>,
+    group: EditGroup,
+    cursor_before: Pointer,
+    cursor_after: Pointer,
+    updated_at: Instant,
+}
+
+impl Transaction {
+    fn accepts(&self, edit: &Edit, group: EditGroup) -> bool {
+        if group == EditGroup::Joined {
+            return true;
+        }
+        if group != self.group || self.updated_at.elapsed() > COALESCE_TIMEOUT {
+            return false;
+        }
+
+        let Some(last) = self.edits.last() else {
+            return false;
+        };
+
+        match group {
+            // Keep typing together while it is contiguous, but start a new
+            // step at each word so undo doesn't swallow a whole sentence
+            EditGroup::Typing => {
+                let starts_word = last.text.ends_with(char::is_whitespace)
+                    && !edit.text.starts_with(char::is_whitespace);
+                edit.at == last.end() && !starts_word
+            }
+            // Repeated backspaces eat the text right before the last deletion
+            EditGroup::Deleting => edit.end() == last.at,
+            EditGroup::Standalone | EditGroup::Joined => false,
+        }
+    }
+}
+
+pub struct History {
+    undo_stack: Vec<Transaction>,
+    redo_stack: Vec<Transaction>,
+    sealed: bool,
+    next_id: u64,
+    // State the oldest step on the undo stack starts from: 0, or the id of
+    // the last step dropped for the limit
+    floor: u64,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl History {
+    pub fn new() -> Self {
+        History {
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            sealed: false,
+            next_id: 1,
+            floor: 0,
+        }
+    }
+
+    // Record an edit that has already been applied to the buffer
+    pub fn record(&mut self, edit: Edit, group: EditGroup, before: Pointer, after: Pointer) {
+        self.redo_stack.clear();
+
+        if !self.sealed
+            && let Some(last) = self.undo_stack.last_mut()
+            && last.accepts(&edit, group)
+        {
+            last.edits.push(edit);
+            last.cursor_after = after;
+            last.updated_at = Instant::now();
+            return;
+        }
+
+        self.sealed = false;
+        self.undo_stack.push(Transaction {
+            id: self.next_id,
+            edits: vec![edit],
+            group,
+            cursor_before: before,
+            cursor_after: after,
+            updated_at: Instant::now(),
+        });
+        self.next_id += 1;
+
+        if self.undo_stack.len() > HISTORY_LIMIT {
+            // Undoing everything left now ends up after the dropped step,
+            // not back at the untouched buffer
+            self.floor = self.undo_stack.remove(0).id;
+        }
+    }
+
+    // Stop the current undo step from absorbing further edits, e.g. after the
+    // cursor was moved away
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+
+    // Identifies the current buffer state: it changes with every edit and
+    // returns to an earlier value when undo or redo get back to that state.
+    // The untouched buffer is 0.
+    pub fn state(&self) -> u64 {
+        self.undo_stack
+            .last()
+            .map_or(self.floor, |transaction| transaction.id)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    // Lowest byte offset the next undo or redo would change
+    pub fn undo_start(&self) -> Option<usize> {
+        Self::start(self.undo_stack.last()?)
+    }
+
+    pub fn redo_start(&self) -> Option<usize> {
+        Self::start(self.redo_stack.last()?)
+    }
+
+    fn start(transaction: &Transaction) -> Option<usize> {
+        transaction.edits.iter().map(|edit| edit.at).min()
+    }
+
+    // Revert the latest step and return where the cursor should go
+    pub fn undo(&mut self, buffer: &mut Buffer) -> Option<Pointer> {
+        let transaction = self.undo_stack.pop()?;
+        for edit in transaction.edits.iter().rev() {
+            edit.revert(buffer);
+        }
+        let cursor = transaction.cursor_before;
+        self.redo_stack.push(transaction);
+        self.sealed = true;
+        Some(cursor)
+    }
+
+    // Re-apply the latest undone step and return where the cursor should go
+    pub fn redo(&mut self, buffer: &mut Buffer) -> Option<Pointer> {
+        let transaction = self.redo_stack.pop()?;
+        for edit in &transaction.edits {
+            edit.apply(buffer);
+        }
+        let cursor = transaction.cursor_after;
+        self.undo_stack.push(transaction);
+        self.sealed = true;
+        Some(cursor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Apply `edit` to `buffer` and record it, as FileManager::edit does
+    fn record(history: &mut History, buffer: &mut Buffer, edit: Edit, group: EditGroup) {
+        edit.apply(buffer);
+        history.record(edit, group, Pointer::new(0, 0), Pointer::new(0, 0));
+    }
+
+    fn type_text(history: &mut History, buffer: &mut Buffer, at: usize, text: &str) {
+        for (i, c) in text.char_indices() {
+            let edit = Edit::insert(at + i, c.encode_utf8(&mut [0; 4]));
+            record(history, buffer, edit, EditGroup::Typing);
+        }
+    }
+
+    #[test]
+    fn typing_coalesces_into_one_step_per_word() {
+        let mut buffer = Buffer::new(String::new());
+        let mut history = History::new();
+        type_text(&mut history, &mut buffer, 0, "hello world");
+        assert_eq!(buffer.contents(), "hello world");
+
+        history.undo(&mut buffer);
+        assert_eq!(buffer.contents(), "hello ");
+        history.undo(&mut buffer);
+        assert_eq!(buffer.contents(), "");
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn backspaces_coalesce_and_seal_splits() {
+        let mut buffer = Buffer::new("abcd".to_string());
+        let mut history = History::new();
+        record(
+            &mut history,
+            &mut buffer,
+            Edit::delete(3, "d"),
+            EditGroup::Deleting,
+        );
+        record(
+            &mut history,
+            &mut buffer,
+            Edit::delete(2, "c"),
+            EditGroup::Deleting,
+        );
+        history.seal();
+        record(
+            &mut history,
+            &mut buffer,
+            Edit::delete(1, "b"),
+            EditGroup::Deleting,
+        );
+        assert_eq!(buffer.contents(), "a");
+
+        history.undo(&mut buffer);
+        assert_eq!(buffer.contents(), "ab");
+        history.undo(&mut buffer);
+        assert_eq!(buffer.contents(), "abcd");
+    }
+
+    #[test]
+    fn different_groups_and_gaps_start_new_steps() {
+        let mut buffer = Buffer::new(String::new());
+        let mut history = History::new();
+        type_text(&mut history, &mut buffer, 0, "ab");
+        // Not contiguous with the last insert
+        type_text(&mut history, &mut buffer, 0, "x");
+        record(
+            &mut history,
+            &mut buffer,
+            Edit::delete(0, "x"),
+            EditGroup::Deleting,
+        );
+        record(
+            &mut history,
+            &mut buffer,
+            Edit::insert(0, "\n"),
+            EditGroup::Standalone,
+        );
+        record(
+            &mut history,
+            &mut buffer,
+            Edit::insert(1, "y"),
+            EditGroup::Joined,
+        );
+        assert_eq!(buffer.contents(), "\nyab");
+
+        let mut states = vec![buffer.contents()];
+        while history.undo(&mut buffer).is_some() {
+            states.push(buffer.contents());
+        }
+        assert_eq!(states, ["\nyab", "ab", "xab", "ab", ""]);
+    }
+
+    #[test]
+    fn redo_replays_undone_steps_until_a_new_edit() {
+        let mut buffer = Buffer::new(String::new());
+        let mut history = History::new();
+        type_text(&mut history, &mut buffer, 0, "one two");
+        history.undo(&mut buffer);
+        history.undo(&mut buffer);
+        assert!(history.can_redo());
+
+        history.redo(&mut buffer);
+        assert_eq!(buffer.contents(), "one ");
+        type_text(&mut history, &mut buffer, 4, "three");
+        assert!(!history.can_redo());
+        assert_eq!(history.redo(&mut buffer), None);
+        assert_eq!(buffer.contents(), "one three");
+    }
+
+    #[test]
+    fn undo_returns_the_cursor_from_before_the_step() {
+        let mut buffer = Buffer::new("ab".to_string());
+        let mut history = History::new();
+        let edit = Edit::insert(1, "x\ny");
+        edit.apply(&mut buffer);
+        history.record(
+            edit,
+            EditGroup::Standalone,
+            Pointer::new(1, 0),
+            Pointer::new(1, 1),
+        );
+
+        assert_eq!(history.undo_start(), Some(1));
+        assert_eq!(history.undo(&mut buffer), Some(Pointer::new(1, 0)));
+        assert_eq!(history.redo(&mut buffer), Some(Pointer::new(1, 1)));
+        assert_eq!(buffer.contents(), "ax\nyb");
+    }
+
+    #[test]
+    fn state_returns_to_the_saved_one_through_undo_and_redo() {
+        let mut buffer = Buffer::new(String::new());
+        let mut history = History::new();
+        assert_eq!(history.state(), 0);
+        type_text(&mut history, &mut buffer, 0, "saved ");
+        let saved = history.state();
+        assert_ne!(saved, 0);
+
+        type_text(&mut history, &mut buffer, 6, "more");
+        assert_ne!(history.state(), saved);
+        history.undo(&mut buffer);
+        assert_eq!(history.state(), saved);
+        history.undo(&mut buffer);
+        assert_eq!(history.state(), 0);
+        history.redo(&mut buffer);
+        assert_eq!(history.state(), saved);
+
+        // A different edit from the same point is a different state
+        type_text(&mut history, &mut buffer, 6, "other");
+        history.undo(&mut buffer);
+        type_text(&mut history, &mut buffer, 6, "x");
+        assert_ne!(history.state(), saved);
+    }
+
+    #[test]
+    fn steps_dropped_for_the_limit_keep_the_bottom_unsaved() {
+        let mut buffer = Buffer::new(String::new());
+        let mut history = History::new();
+        for i in 0..HISTORY_LIMIT + 5 {
+            history.seal();
+            record(
+                &mut history,
+                &mut buffer,
+                Edit::insert(i, "x"),
+                EditGroup::Standalone,
+            );
+        }
+
+        let mut undone = 0;
+        while history.undo(&mut buffer).is_some() {
+            undone += 1;
+        }
+        assert_eq!(undone, HISTORY_LIMIT);
+        assert_eq!(buffer.contents(), "x".repeat(5));
+        // Neither the untouched buffer nor any state still reachable
+        assert_ne!(history.state(), 0);
+        let bottom = history.state();
+        history.redo(&mut buffer);
+        assert_ne!(history.state(), bottom);
+    }
+}
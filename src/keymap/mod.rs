@@ -0,0 +1,408 @@
+use crate::keys::{Key, KeyCode, Modifiers};
+
+// Which key sequences run which commands. A binding is one or more key
+// chords written like "ctrl+s" or "ctrl+k ctrl+w"; the keys of a sequence
+// are fed in one at a time as they arrive.
+
+// What a key press amounts to
+pub enum Lookup {
+    Command(String),
+    // The start of a longer sequence, wait for the next key
+    Pending,
+    // Keys that aren't bound to anything
+    Unbound(Vec<Key>),
+}
+
+#[derive(Default)]
+pub struct Keymap {
+    bindings: Vec<(Vec<Key>, String)>,
+    // Keys of a sequence typed so far
+    pending: Vec<Key>,
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        Keymap::default()
+    }
+
+    // Bind `keys` to `command`, replacing whatever it was bound to
+    pub fn bind(&mut self, keys: Vec<Key>, command: &str) {
+        self.unbind(&keys);
+        self.bindings.push((keys, command.to_string()));
+    }
+
+    pub fn unbind(&mut self, keys: &[Key]) {
+        self.bindings.retain(|(bound, _)| bound != keys);
+    }
+
+    // Apply `[keybindings]` from the config, where a command of "none"
+    // removes a binding. Commands not in `commands` and chords that don't
+    // parse are skipped and returned as errors.
+    pub fn configure<'a>(
+        &mut self,
+        bindings: impl IntoIterator<Item = (&'a String, &'a String)>,
+        commands: &[&str],
+    ) -> Vec<String> {
+        let mut errors = Vec::new();
+        for (chords, command) in bindings {
+            let keys = match parse_sequence(chords) {
+                Ok(keys) => keys,
+                Err(e) => {
+                    errors.push(e);
+                    continue;
+                }
+            };
+            if command == "none" {
+                self.unbind(&keys);
+            } else if commands.contains(&command.as_str()) {
+                self.bind(keys, command);
+            } else {
+                errors.push(format!("unknown command \"{}\"", command));
+            }
+        }
+        errors
+    }
+
+    // Take the next key press. A sequence that can still grow into a longer
+    // binding waits for more keys.
+    pub fn feed(&mut self, key: Key) -> Lookup {
+        self.pending.push(key);
+        let typed = &self.pending;
+
+        let longer = self
+            .bindings
+            .iter()
+            .any(|(keys, _)| keys.len() > typed.len() && keys.starts_with(typed));
+        if longer {
+            return Lookup::Pending;
+        }
+
+        let keys = std::mem::take(&mut self.pending);
+        match self.bindings.iter().find(|(bound, _)| *bound == keys) {
+            Some((_, command)) => Lookup::Command(command.clone()),
+            None => Lookup::Unbound(keys),
+        }
+    }
+
+    // Keys of an unfinished sequence, e.g. for the status bar
+    pub fn pending(&self) -> &[Key] {
+        &self.pending
+    }
+
+    // First binding of `command`, written out for display
+    pub fn binding_for(&self, command: &str) -> Option<String> {
+        self.bindings
+            .iter()
+            .find(|(_, bound)| bound == command)
+            .map(|(keys, _)| format_sequence(keys))
+    }
+}
+
+// Parse a space separated sequence of chords
+pub fn parse_sequence(text: &str) -> Result<Vec<Key>, String> {
+    let keys = text
+        .split_whitespace()
+        .map(parse_chord)
+        .collect::<Result<Vec<_>, _>>()?;
+    if keys.is_empty() {
+        return Err("empty key binding".to_string());
+    }
+    Ok(keys)
+}
+
+// Parse one chord like "ctrl+shift+left", "alt+s" or "f3"
+pub fn parse_chord(text: &str) -> Result<Key, String> {
+    let invalid = || format!("invalid key \"{}\"", text);
+
+    // Split at the last '+' so "ctrl++" is Ctrl with the plus key
+    let (modifiers, name) = match text.strip_suffix('+') {
+        Some(rest) if rest.is_empty() || rest.ends_with('+') => (rest, "+"),
+        _ => match text.rsplit_once('+') {
+            Some((modifiers, name)) => (modifiers, name),
+            None => ("", text),
+        },
+    };
+
+    let mut mods = Modifiers::NONE;
+    for modifier in modifiers.split('+').filter(|m| !m.is_empty()) {
+        mods = mods.union(match modifier.to_ascii_lowercase().as_str() {
+            "ctrl" | "control" => Modifiers::CTRL,
+            "alt" | "meta" => Modifiers::ALT,
+            "shift" => Modifiers::SHIFT,
+            _ => return Err(invalid()),
+        });
+    }
+
+    let code = match name.to_ascii_lowercase().as_str() {
+        "enter" | "return" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "backspace" => KeyCode::Backspace,
+        "esc" | "escape" => KeyCode::Escape,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" | "pgup" => KeyCode::PageUp,
+        "pagedown" | "pgdn" => KeyCode::PageDown,
+        "insert" | "ins" => KeyCode::Insert,
+        "delete" | "del" => KeyCode::Delete,
+        "space" => KeyCode::Char(' '),
+        lower => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => KeyCode::Char(c),
+                _ => match lower.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                    Some(n @ 1..=12) => KeyCode::F(n),
+                    _ => return Err(invalid()),
+                },
+            }
+        }
+    };
+
+    Ok(normalize(Key::new(code, mods)))
+}
+
+// Keys the terminal can't tell apart are stored the way they arrive:
+// Ctrl+letter is always lowercase, Shift on a character comes through as
+// the shifted character and Shift+Tab has a code of its own
+fn normalize(key: Key) -> Key {
+    let mut without_shift = Modifiers::NONE;
+    if key.mods.ctrl() {
+        without_shift = without_shift.union(Modifiers::CTRL);
+    }
+    if key.mods.alt() {
+        without_shift = without_shift.union(Modifiers::ALT);
+    }
+    match key.code {
+        KeyCode::Char(c) if key.mods.ctrl() => {
+            Key::new(KeyCode::Char(c.to_ascii_lowercase()), without_shift)
+        }
+        KeyCode::Char(c) if key.mods.shift() => {
+            Key::new(KeyCode::Char(c.to_ascii_uppercase()), without_shift)
+        }
+        KeyCode::Tab if key.mods.shift() => Key::new(KeyCode::BackTab, without_shift),
+        _ => key,
+    }
+}
+
+// "Ctrl+Shift+Left", "Alt+S", "F3"
+pub fn format_key(key: Key) -> String {
+    let mut text = String::new();
+    if key.mods.ctrl() {
+        text.push_str("Ctrl+");
+    }
+    if key.mods.alt() {
+        text.push_str("Alt+");
+    }
+    if key.mods.shift() {
+        text.push_str("Shift+");
+    }
+
+    let name = match key.code {
+        KeyCode::Char(' ') => "Space".to_string(),
+        KeyCode::Char(c) if c.is_uppercase() => format!("Shift+{}", c),
+        KeyCode::Char(c) => c.to_uppercase().to_string(),
+        KeyCode::Enter => "Enter".to_string(),
+        KeyCode::Tab => "Tab".to_string(),
+        KeyCode::BackTab => "Shift+Tab".to_string(),
+        KeyCode::Backspace => "Backspace".to_string(),
+        KeyCode::Escape => "Esc".to_string(),
+        KeyCode::Up => "↑".to_string(),
+        KeyCode::Down => "↓".to_string(),
+        KeyCode::Left => "←".to_string(),
+        KeyCode::Right => "→".to_string(),
+        KeyCode::Home => "Home".to_string(),
+        KeyCode::End => "End".to_string(),
+        KeyCode::PageUp => "PgUp".to_string(),
+        KeyCode::PageDown => "PgDn".to_string(),
+        KeyCode::Insert => "Ins".to_string(),
+        KeyCode::Delete => "Del".to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        KeyCode::FocusGained | KeyCode::FocusLost | KeyCode::Unknown => "?".to_string(),
+    };
+    text.push_str(&name);
+    text
+}
+
+pub fn format_sequence(keys: &[Key]) -> String {
+    keys.iter()
+        .map(|key| format_key(*key))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, mods: Modifiers) -> Key {
+        Key::new(code, mods)
+    }
+
+    fn ctrl(c: char) -> Key {
+        key(KeyCode::Char(c), Modifiers::CTRL)
+    }
+
+    fn command(lookup: Lookup) -> Option<String> {
+        match lookup {
+            Lookup::Command(command) => Some(command),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parses_modifiers_in_any_case_and_order() {
+        assert_eq!(parse_chord("ctrl+s"), Ok(ctrl('s')));
+        assert_eq!(parse_chord("Control+S"), Ok(ctrl('s')));
+        assert_eq!(
+            parse_chord("shift+ctrl+left"),
+            Ok(key(KeyCode::Left, Modifiers::CTRL_SHIFT))
+        );
+        assert_eq!(
+            parse_chord("meta+x"),
+            Ok(key(KeyCode::Char('x'), Modifiers::ALT))
+        );
+    }
+
+    #[test]
+    fn parses_named_keys() {
+        assert_eq!(parse_chord("f3"), Ok(key(KeyCode::F(3), Modifiers::NONE)));
+        assert_eq!(parse_chord("F12"), Ok(key(KeyCode::F(12), Modifiers::NONE)));
+        assert_eq!(
+            parse_chord("alt+space"),
+            Ok(key(KeyCode::Char(' '), Modifiers::ALT))
+        );
+        assert_eq!(
+            parse_chord("PgDn"),
+            Ok(key(KeyCode::PageDown, Modifiers::NONE))
+        );
+        assert_eq!(
+            parse_chord("ctrl+enter"),
+            Ok(key(KeyCode::Enter, Modifiers::CTRL))
+        );
+    }
+
+    #[test]
+    fn plus_is_a_key_too() {
+        assert_eq!(parse_chord("ctrl++"), Ok(ctrl('+')));
+        assert_eq!(
+            parse_chord("+"),
+            Ok(key(KeyCode::Char('+'), Modifiers::NONE))
+        );
+        assert_eq!(
+            parse_chord("ctrl+alt++"),
+            Ok(key(
+                KeyCode::Char('+'),
+                Modifiers::CTRL.union(Modifiers::ALT)
+            ))
+        );
+    }
+
+    #[test]
+    fn rejects_what_isnt_a_key() {
+        for text in ["f13", "f0", "hyper+a", "ctrl+", "ctrl+page", "ctrl+xy", ""] {
+            assert_eq!(
+                parse_chord(text),
+                Err(format!("invalid key \"{}\"", text)),
+                "{:?}",
+                text
+            );
+        }
+        assert_eq!(parse_sequence("  "), Err("empty key binding".to_string()));
+    }
+
+    #[test]
+    fn normalizes_keys_the_terminal_cannot_tell_apart() {
+        // Ctrl+letter arrives lowercase whether Shift was held or not
+        assert_eq!(parse_chord("ctrl+shift+s"), Ok(ctrl('s')));
+        assert_eq!(parse_chord("ctrl+S"), Ok(ctrl('s')));
+        // Shift on a character is the shifted character
+        assert_eq!(
+            parse_chord("shift+a"),
+            Ok(key(KeyCode::Char('A'), Modifiers::NONE))
+        );
+        assert_eq!(
+            parse_chord("alt+shift+a"),
+            Ok(key(KeyCode::Char('A'), Modifiers::ALT))
+        );
+        assert_eq!(
+            parse_chord("shift+tab"),
+            Ok(key(KeyCode::BackTab, Modifiers::NONE))
+        );
+        // Shift stays on keys that have no shifted form
+        assert_eq!(
+            parse_chord("shift+up"),
+            Ok(key(KeyCode::Up, Modifiers::SHIFT))
+        );
+    }
+
+    #[test]
+    fn feeds_sequences_one_key_at_a_time() {
+        let mut keymap = Keymap::new();
+        keymap.bind(vec![ctrl('k'), ctrl('w')], "close");
+        keymap.bind(vec![ctrl('k'), ctrl('k')], "delete_line");
+        keymap.bind(vec![ctrl('s')], "save");
+
+        assert!(matches!(keymap.feed(ctrl('k')), Lookup::Pending));
+        assert_eq!(keymap.pending(), [ctrl('k')]);
+        assert_eq!(command(keymap.feed(ctrl('w'))).as_deref(), Some("close"));
+        assert!(keymap.pending().is_empty());
+
+        assert!(matches!(keymap.feed(ctrl('k')), Lookup::Pending));
+        assert_eq!(
+            command(keymap.feed(ctrl('k'))).as_deref(),
+            Some("delete_line")
+        );
+        assert_eq!(command(keymap.feed(ctrl('s'))).as_deref(), Some("save"));
+    }
+
+    #[test]
+    fn a_sequence_that_goes_nowhere_is_unbound_whole() {
+        let mut keymap = Keymap::new();
+        keymap.bind(vec![ctrl('k'), ctrl('w')], "close");
+        keymap.feed(ctrl('k'));
+        match keymap.feed(ctrl('x')) {
+            Lookup::Unbound(keys) => assert_eq!(keys, [ctrl('k'), ctrl('x')]),
+            _ => panic!("expected the keys back"),
+        }
+        assert!(keymap.pending().is_empty());
+        assert!(matches!(keymap.feed(ctrl('x')), Lookup::Unbound(_)));
+    }
+
+    #[test]
+    fn configure_rebinds_unbinds_and_reports_errors() {
+        let mut keymap = Keymap::new();
+        keymap.bind(vec![ctrl('s')], "save");
+        keymap.bind(vec![ctrl('q')], "quit");
+        let bindings = [
+            ("ctrl+s".to_string(), "quit".to_string()),
+            ("ctrl+q".to_string(), "none".to_string()),
+            ("ctrl+e".to_string(), "explode".to_string()),
+            ("ctrl+nope".to_string(), "save".to_string()),
+        ];
+        let errors = keymap.configure(bindings.iter().map(|(k, v)| (k, v)), &["save", "quit"]);
+        assert_eq!(
+            errors,
+            [
+                "unknown command \"explode\"".to_string(),
+                "invalid key \"ctrl+nope\"".to_string()
+            ]
+        );
+        assert_eq!(command(keymap.feed(ctrl('s'))).as_deref(), Some("quit"));
+        assert!(matches!(keymap.feed(ctrl('q')), Lookup::Unbound(_)));
+        assert_eq!(keymap.binding_for("quit").as_deref(), Some("Ctrl+S"));
+        assert_eq!(keymap.binding_for("save"), None);
+    }
+
+    #[test]
+    fn formats_keys_for_display() {
+        let keys = parse_sequence("ctrl+k shift+a alt+space f3").unwrap();
+        assert_eq!(format_sequence(&keys), "Ctrl+K Shift+A Alt+Space F3");
+        assert_eq!(
+            format_key(parse_chord("ctrl+shift+left").unwrap()),
+            "Ctrl+Shift+←"
+        );
+    }
+}
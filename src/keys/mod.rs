@@ -0,0 +1,442 @@
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+// How long to wait for the rest of an escape sequence before treating the
+// ESC byte as a key press of its own
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(30);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum KeyCode {
+    Char(char),
+    Enter,
+    Tab,
+    BackTab,
+    Backspace,
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    F(u8),
+    // The terminal window gained or lost focus, reported once focus
+    // reporting is turned on
+    FocusGained,
+    FocusLost,
+    // A sequence we decoded but don't know, or bytes that aren't valid UTF-8
+    Unknown,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers(0);
+    pub const SHIFT: Modifiers = Modifiers(1);
+    pub const ALT: Modifiers = Modifiers(2);
+    pub const CTRL: Modifiers = Modifiers(4);
+    pub const CTRL_SHIFT: Modifiers = Modifiers(5);
+    pub const ALT_SHIFT: Modifiers = Modifiers(3);
+
+    pub const fn union(self, other: Modifiers) -> Modifiers {
+        Modifiers(self.0 | other.0)
+    }
+
+    pub fn contains(self, other: Modifiers) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn shift(self) -> bool {
+        self.contains(Modifiers::SHIFT)
+    }
+
+    pub fn alt(self) -> bool {
+        self.contains(Modifiers::ALT)
+    }
+
+    pub fn ctrl(self) -> bool {
+        self.contains(Modifiers::CTRL)
+    }
+
+    // xterm encodes modifiers in CSI parameters as 1 + bitmask
+    fn from_csi_param(param: u16) -> Modifiers {
+        Modifiers((param.saturating_sub(1) & 0x7) as u8)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Key {
+    pub code: KeyCode,
+    pub mods: Modifiers,
+}
+
+impl Key {
+    pub fn new(code: KeyCode, mods: Modifiers) -> Self {
+        Key { code, mods }
+    }
+
+    pub fn plain(code: KeyCode) -> Self {
+        Key::new(code, Modifiers::NONE)
+    }
+
+    // Character to insert for this key, if it is plain text input
+    pub fn text(&self) -> Option<char> {
+        match self.code {
+            KeyCode::Char(c) if !self.mods.ctrl() && !self.mods.alt() => Some(c),
+            _ => None,
+        }
+    }
+}
+
+pub enum Parsed {
+    Key(Key, usize),
+    // More bytes are needed to tell what this is
+    Incomplete,
+}
+
+// Decode the key at the start of `bytes`, returning it with the number of
+// bytes it used
+pub fn parse_key(bytes: &[u8]) -> Parsed {
+    let Some(&first) = bytes.first() else {
+        return Parsed::Incomplete;
+    };
+
+    match first {
+        0x1b => parse_escape(bytes),
+        0x0d | 0x0a => Parsed::Key(Key::plain(KeyCode::Enter), 1),
+        0x09 => Parsed::Key(Key::plain(KeyCode::Tab), 1),
+        0x7f | 0x08 => Parsed::Key(Key::plain(KeyCode::Backspace), 1),
+        0x00 => Parsed::Key(Key::new(KeyCode::Char(' '), Modifiers::CTRL), 1),
+        // Ctrl+A .. Ctrl+Z
+        0x01..=0x1a => {
+            let c = (b'a' + first - 1) as char;
+            Parsed::Key(Key::new(KeyCode::Char(c), Modifiers::CTRL), 1)
+        }
+        // Ctrl+\ Ctrl+] Ctrl+^ Ctrl+_
+        0x1c..=0x1f => {
+            let c = (first + 0x40) as char;
+            Parsed::Key(Key::new(KeyCode::Char(c), Modifiers::CTRL), 1)
+        }
+        0x20..=0x7e => Parsed::Key(Key::plain(KeyCode::Char(first as char)), 1),
+        _ => parse_utf8(bytes),
+    }
+}
+
+// Resolve what a prefix that never completed means, once we've given up
+// waiting for the rest of it
+pub fn parse_incomplete(bytes: &[u8]) -> (Key, usize) {
+    match bytes {
+        [0x1b] => (Key::plain(KeyCode::Escape), 1),
+        // ESC followed by a lone '[' or 'O' is Alt+[ / Alt+O
+        [0x1b, b @ (b'[' | b'O')] => (Key::new(KeyCode::Char(*b as char), Modifiers::ALT), 2),
+        // A cut-off sequence or character: drop it
+        _ => (Key::plain(KeyCode::Unknown), bytes.len()),
+    }
+}
+
+fn parse_utf8(bytes: &[u8]) -> Parsed {
+    let len = match bytes[0] {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Parsed::Key(Key::plain(KeyCode::Unknown), 1),
+    };
+
+    if bytes.len() < len {
+        return Parsed::Incomplete;
+    }
+
+    match std::str::from_utf8(&bytes[..len]) {
+        Ok(s) => {
+            let c = s.chars().next().unwrap_or('\u{fffd}');
+            Parsed::Key(Key::plain(KeyCode::Char(c)), len)
+        }
+        Err(_) => Parsed::Key(Key::plain(KeyCode::Unknown), 1),
+    }
+}
+
+fn parse_escape(bytes: &[u8]) -> Parsed {
+    match bytes.get(1) {
+        None => Parsed::Incomplete,
+        Some(b'[') => parse_csi(bytes),
+        Some(b'O') => parse_ss3(bytes),
+        // ESC ESC: the first one is a key of its own
+        Some(0x1b) => Parsed::Key(Key::plain(KeyCode::Escape), 1),
+        // ESC followed by a key is that key with Alt held
+        Some(_) => match parse_key(&bytes[1..]) {
+            Parsed::Key(key, len) => {
+                Parsed::Key(Key::new(key.code, key.mods.union(Modifiers::ALT)), len + 1)
+            }
+            Parsed::Incomplete => Parsed::Incomplete,
+        },
+    }
+}
+
+// ESC [ <params> <final>
+fn parse_csi(bytes: &[u8]) -> Parsed {
+    let body = &bytes[2..];
+    let Some(end) = body.iter().position(|b| (0x40..=0x7e).contains(b)) else {
+        // Only parameter and intermediate bytes so far
+        if body.iter().all(|b| (0x20..=0x3f).contains(b)) {
+            return Parsed::Incomplete;
+        }
+        return Parsed::Key(Key::plain(KeyCode::Unknown), 2);
+    };
+
+    let len = 2 + end + 1;
+    let params: Vec<u16> = std::str::from_utf8(&body[..end])
+        .unwrap_or("")
+        .split(';')
+        .map(|p| p.parse().unwrap_or(0))
+        .collect();
+    let param = |i: usize| params.get(i).copied().unwrap_or(0);
+    let mods = Modifiers::from_csi_param(param(1));
+
+    let code = match body[end] {
+        b'A' => KeyCode::Up,
+        b'B' => KeyCode::Down,
+        b'C' => KeyCode::Right,
+        b'D' => KeyCode::Left,
+        b'H' => KeyCode::Home,
+        b'F' => KeyCode::End,
+        b'P' => KeyCode::F(1),
+        b'Q' => KeyCode::F(2),
+        b'R' => KeyCode::F(3),
+        b'S' => KeyCode::F(4),
+        b'Z' => KeyCode::BackTab,
+        b'I' if end == 0 => KeyCode::FocusGained,
+        b'O' if end == 0 => KeyCode::FocusLost,
+        b'~' => match param(0) {
+            1 | 7 => KeyCode::Home,
+            2 => KeyCode::Insert,
+            3 => KeyCode::Delete,
+            4 | 8 => KeyCode::End,
+            5 => KeyCode::PageUp,
+            6 => KeyCode::PageDown,
+            n @ 11..=15 => KeyCode::F((n - 10) as u8),
+            n @ 17..=21 => KeyCode::F((n - 11) as u8),
+            n @ 23..=24 => KeyCode::F((n - 12) as u8),
+            _ => KeyCode::Unknown,
+        },
+        _ => KeyCode::Unknown,
+    };
+
+    Parsed::Key(Key::new(code, mods), len)
+}
+
+// ESC O <final>, sent for F1-F4 and by terminals in application cursor mode
+fn parse_ss3(bytes: &[u8]) -> Parsed {
+    let Some(&last) = bytes.get(2) else {
+        return Parsed::Incomplete;
+    };
+
+    let code = match last {
+        b'A' => KeyCode::Up,
+        b'B' => KeyCode::Down,
+        b'C' => KeyCode::Right,
+        b'D' => KeyCode::Left,
+        b'H' => KeyCode::Home,
+        b'F' => KeyCode::End,
+        b'M' => KeyCode::Enter,
+        b'P' => KeyCode::F(1),
+        b'Q' => KeyCode::F(2),
+        b'R' => KeyCode::F(3),
+        b'S' => KeyCode::F(4),
+        _ => KeyCode::Unknown,
+    };
+    Parsed::Key(Key::plain(code), 3)
+}
+
+// Reads keys from the terminal, buffering bytes until a whole key is in
+pub struct KeyReader {
+    fd: RawFd,
+    pending: Vec<u8>,
+}
+
+impl Default for KeyReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyReader {
+    pub fn new() -> Self {
+        KeyReader {
+            fd: io::stdin().as_raw_fd(),
+            pending: Vec::new(),
+        }
+    }
+
+    // Block until the next key press
+    pub fn read_key(&mut self) -> io::Result<Key> {
+        loop {
+            if self.pending.is_empty() {
+                self.fill(None)?;
+                continue;
+            }
+
+            match parse_key(&self.pending) {
+                Parsed::Key(key, len) => {
+                    self.pending.drain(..len);
+                    return Ok(key);
+                }
+                Parsed::Incomplete => {
+                    if !self.fill(Some(ESCAPE_TIMEOUT))? {
+                        let (key, len) = parse_incomplete(&self.pending);
+                        self.pending.drain(..len);
+                        return Ok(key);
+                    }
+                }
+            }
+        }
+    }
+
+    // Whether a key is waiting, giving it up to `timeout` to arrive
+    pub fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        if !self.pending.is_empty() {
+            return Ok(true);
+        }
+        self.fill(Some(timeout))
+    }
+
+    // Wait up to `timeout` (forever if None) for input and append whatever
+    // is available. Returns false if nothing arrived in time.
+    fn fill(&mut self, timeout: Option<Duration>) -> io::Result<bool> {
+        let mut poll_fd = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout_ms = timeout.map_or(-1, |t| t.as_millis() as libc::c_int);
+
+        let ready = unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) };
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+            // A signal such as a resize interrupted us, just try again
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok(false);
+            }
+            return Err(err);
+        }
+        if ready == 0 {
+            return Ok(false);
+        }
+
+        let mut chunk = [0u8; 64];
+        let read = unsafe { libc::read(self.fd, chunk.as_mut_ptr().cast(), chunk.len()) };
+        if read < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stdin closed"));
+        }
+
+        self.pending.extend_from_slice(&chunk[..read as usize]);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The key at the start of `bytes` and its length, None if incomplete
+    fn parse(bytes: &[u8]) -> Option<(Key, usize)> {
+        match parse_key(bytes) {
+            Parsed::Key(key, len) => Some((key, len)),
+            Parsed::Incomplete => None,
+        }
+    }
+
+    // Just the key, where its length doesn't matter
+    fn code(bytes: &[u8]) -> Option<Key> {
+        parse(bytes).map(|(key, _)| key)
+    }
+
+    fn key(code: KeyCode, mods: Modifiers) -> Option<Key> {
+        Some(Key::new(code, mods))
+    }
+
+    #[test]
+    fn control_bytes_and_text() {
+        assert_eq!(code(b"a"), key(KeyCode::Char('a'), Modifiers::NONE));
+        assert_eq!(code(b"\r"), key(KeyCode::Enter, Modifiers::NONE));
+        assert_eq!(code(b"\x7f"), key(KeyCode::Backspace, Modifiers::NONE));
+        assert_eq!(code(b"\x13"), key(KeyCode::Char('s'), Modifiers::CTRL));
+        assert_eq!(code(b"\x00"), key(KeyCode::Char(' '), Modifiers::CTRL));
+        assert_eq!(code(b"\x1d"), key(KeyCode::Char(']'), Modifiers::CTRL));
+        assert_eq!(
+            parse("é!".as_bytes()),
+            Some((Key::plain(KeyCode::Char('é')), 2))
+        );
+        // The rest of a character may still be on its way
+        assert_eq!(parse(&"€".as_bytes()[..2]), None);
+        assert_eq!(code(b"\xff"), key(KeyCode::Unknown, Modifiers::NONE));
+    }
+
+    #[test]
+    fn csi_sequences_with_modifiers() {
+        assert_eq!(parse(b"\x1b[A"), Some((Key::plain(KeyCode::Up), 3)));
+        assert_eq!(
+            parse(b"\x1b[1;5Cx"),
+            Some((Key::new(KeyCode::Right, Modifiers::CTRL), 6))
+        );
+        assert_eq!(code(b"\x1b[1;3D"), key(KeyCode::Left, Modifiers::ALT));
+        assert_eq!(code(b"\x1b[1;2H"), key(KeyCode::Home, Modifiers::SHIFT));
+        assert_eq!(code(b"\x1b[1;6F"), key(KeyCode::End, Modifiers::CTRL_SHIFT));
+        assert_eq!(code(b"\x1b[3~"), key(KeyCode::Delete, Modifiers::NONE));
+        assert_eq!(code(b"\x1b[5;5~"), key(KeyCode::PageUp, Modifiers::CTRL));
+        assert_eq!(code(b"\x1b[15~"), key(KeyCode::F(5), Modifiers::NONE));
+        assert_eq!(code(b"\x1b[24~"), key(KeyCode::F(12), Modifiers::NONE));
+        assert_eq!(code(b"\x1b[Z"), key(KeyCode::BackTab, Modifiers::NONE));
+        assert_eq!(code(b"\x1b[I"), key(KeyCode::FocusGained, Modifiers::NONE));
+        assert_eq!(code(b"\x1b[O"), key(KeyCode::FocusLost, Modifiers::NONE));
+        assert_eq!(code(b"\x1b[99~"), key(KeyCode::Unknown, Modifiers::NONE));
+        assert_eq!(parse(b"\x1b[1;5"), None);
+    }
+
+    #[test]
+    fn ss3_and_alt_prefix() {
+        assert_eq!(parse(b"\x1bOP"), Some((Key::plain(KeyCode::F(1)), 3)));
+        assert_eq!(code(b"\x1bOA"), key(KeyCode::Up, Modifiers::NONE));
+        assert_eq!(parse(b"\x1bO"), None);
+        assert_eq!(
+            parse(b"\x1bx"),
+            Some((Key::new(KeyCode::Char('x'), Modifiers::ALT), 2))
+        );
+        assert_eq!(
+            code(b"\x1b\x13"),
+            key(KeyCode::Char('s'), Modifiers::CTRL.union(Modifiers::ALT))
+        );
+        assert_eq!(parse(b"\x1b\x1b[A"), Some((Key::plain(KeyCode::Escape), 1)));
+        assert_eq!(parse(b"\x1b"), None);
+    }
+
+    #[test]
+    fn prefixes_that_never_complete() {
+        assert_eq!(parse_incomplete(b"\x1b"), (Key::plain(KeyCode::Escape), 1));
+        assert_eq!(
+            parse_incomplete(b"\x1b["),
+            (Key::new(KeyCode::Char('['), Modifiers::ALT), 2)
+        );
+        assert_eq!(
+            parse_incomplete(b"\x1b[1;"),
+            (Key::plain(KeyCode::Unknown), 4)
+        );
+    }
+
+    #[test]
+    fn text_is_only_unmodified_characters() {
+        assert_eq!(Key::plain(KeyCode::Char('a')).text(), Some('a'));
+        let shifted = Key::new(KeyCode::Char('A'), Modifiers::SHIFT);
+        assert_eq!(shifted.text(), Some('A'));
+        assert_eq!(Key::new(KeyCode::Char('a'), Modifiers::ALT).text(), None);
+        assert_eq!(Key::plain(KeyCode::Enter).text(), None);
+    }
+}
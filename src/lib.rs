@@ -1,17 +1,46 @@
-use std::fs::{self, DirEntry};
-use std::io::Write;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
+pub mod buffer;
+pub mod config;
+pub mod encoding;
+pub mod hex;
+pub mod highlight;
+pub mod history;
+pub mod keymap;
+pub mod keys;
+pub mod picker;
+pub mod save;
+pub mod search;
+pub mod selection;
+pub mod swap;
+pub mod text;
+pub mod tree;
+
+pub use buffer::Buffer;
+use config::{Config, EditorSettings};
+use encoding::FileFormat;
+use hex::HexView;
+use highlight::Highlighter;
+use history::{Edit, EditGroup, EditKind, History};
+use keymap::Keymap;
+use keys::{Key, KeyCode};
+use picker::Picker;
+use search::Search;
+use selection::{Register, Selection, SelectionMode};
+use swap::{Recovery, Swap};
+use tree::FileTree;
+
+// Cursor position: `y` is the line, `x` the byte offset into it, always on a
+// grapheme boundary
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Pointer {
     pub x: usize,
     pub y: usize,
 }
 
-pub struct Buffer {
-    pub data: Vec<String>,
-    pub current_line: usize,
-}
-
 // Toast notification system
 pub struct Toast {
     pub message: String,
@@ -43,15 +72,6 @@ impl Toast {
     }
 }
 
-impl Buffer {
-    pub fn new(data: Vec<String>) -> Self {
-        Buffer {
-            data,
-            current_line: 0,
-        }
-    }
-}
-
 impl Pointer {
     pub fn new(x: usize, y: usize) -> Self {
         Pointer { x, y }
@@ -63,11 +83,47 @@ pub struct FileInfo {
     pub path: String,
 }
 
+impl FileInfo {
+    pub fn from_path(path: &str) -> Self {
+        FileInfo {
+            name: path.split('/').next_back().unwrap_or("unknown").to_string(),
+            path: path.to_string(),
+        }
+    }
+
+    // Documents that were never saved have "/" as their path
+    pub fn untitled() -> Self {
+        FileInfo {
+            name: "Untitled".to_string(),
+            path: "/".to_string(),
+        }
+    }
+
+    pub fn is_untitled(&self) -> bool {
+        self.path == "/" || self.path.is_empty()
+    }
+}
+
 // Input action types
 #[derive(Clone, Copy, PartialEq)]
 pub enum InputAction {
     Generic,
     SaveAs,
+    Find,
+    Replace,
+    ReplaceWith,
+    ConfirmReplace,
+    ConfirmClose,
+    ConfirmQuit,
+    CommandPalette,
+    FindFile,
+    FileFormat,
+    NewFile,
+    NewDirectory,
+    RenamePath,
+    DuplicatePath,
+    ConfirmDelete,
+    RecoverSwap,
     // Add more action types as needed
 }
 
@@ -85,6 +141,12 @@ pub struct InputHandler {
     pub action_type: InputAction,
 }
 
+impl Default for InputHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl InputHandler {
     pub fn new() -> Self {
         InputHandler {
@@ -108,6 +170,12 @@ impl InputHandler {
         self.action_type = action;
     }
 
+    // Start with `text` already typed, e.g. the current name when renaming
+    pub fn start_input_with_text(&mut self, prompt: &str, action: InputAction, text: &str) {
+        self.start_input_with_prompt(prompt, action);
+        self.input_buffer.push_str(text);
+    }
+
     pub fn confirm_input(&mut self) -> InputResult {
         self.taking_input = false;
         InputResult::Confirmed(std::mem::take(&mut self.input_buffer))
@@ -132,29 +200,21 @@ impl InputHandler {
     }
 
     // Handle a key press during input
-    pub fn handle_key(&mut self, key: u8) -> InputResult {
+    pub fn handle_key(&mut self, key: Key) -> InputResult {
         if !self.taking_input {
             return InputResult::InProgress;
         }
 
-        match key {
-            0x0d => self.confirm_input(), // Enter key
-            0x0a => self.confirm_input(), // Enter key
-            0x1b => self.cancel_input(),  // Escape key
-            0x08 | 0x7f => {
-                // Backspace or Delete
+        match key.code {
+            KeyCode::Enter => self.confirm_input(),
+            KeyCode::Escape => self.cancel_input(),
+            KeyCode::Backspace => {
                 self.delete_char();
                 InputResult::InProgress
             }
-            key if key.is_ascii_graphic() || key == b' ' => {
-                self.add_char(key as char);
-                InputResult::InProgress
-            }
             _ => {
-                if let Some(c) = char::from_u32(key as u32) {
-                    if c.is_ascii_graphic() || c == ' ' {
-                        self.add_char(c);
-                    }
+                if let Some(c) = key.text() {
+                    self.add_char(c);
                 }
                 InputResult::InProgress
             }
@@ -162,26 +222,468 @@ impl InputHandler {
     }
 }
 
-pub struct FileManager {
-    pub pointer: Pointer,
+// An open file and its editing state
+pub struct Document {
     pub buffer: Buffer,
     pub file_info: FileInfo,
+    pub pointer: Pointer,
+    pub history: History,
+    pub selection: Option<Selection>,
+    // History state of the buffer when it was opened or last saved
+    pub saved_state: u64,
+    pub highlighter: Highlighter,
+    // Screen column of the text at the left edge of the content area, moved
+    // along with the cursor on lines wider than the terminal
+    pub scroll_x: usize,
+    // Wrap long lines onto extra rows instead of scrolling sideways
+    pub soft_wrap: bool,
+    // How the document is written to disk
+    pub format: FileFormat,
+    // Set when the document is a binary file shown as a hex dump, which
+    // then holds its bytes; the text fields stay empty
+    pub hex: Option<HexView>,
+    // Where unsaved changes are kept in case nox dies; None for documents
+    // without a file or with one too big to copy
+    pub swap: Option<Swap>,
+    // Edits since the document was opened or last saved
+    pub edits_since_save: usize,
+}
+
+// An empty untitled document
+impl Default for Document {
+    fn default() -> Self {
+        let settings = Config::default().for_file("");
+        Self::new(Buffer::new(String::new()), FileInfo::untitled(), &settings)
+    }
+}
+
+impl Document {
+    pub fn new(buffer: Buffer, file_info: FileInfo, settings: &EditorSettings) -> Self {
+        Document {
+            highlighter: Highlighter::for_buffer(&file_info.name, &buffer, &settings.theme),
+            buffer,
+            file_info,
+            pointer: Pointer::new(0, 0),
+            history: History::new(),
+            selection: None,
+            saved_state: 0,
+            scroll_x: 0,
+            soft_wrap: settings.wrap,
+            format: FileFormat::default(),
+            hex: None,
+            swap: None,
+            edits_since_save: 0,
+        }
+    }
+
+    // Edited since it was opened or last saved. Undoing back to the saved
+    // text counts as unmodified again.
+    pub fn is_modified(&self) -> bool {
+        self.history.state() != self.saved_state
+    }
+
+    // Write the document to its file and remember that text as saved
+    fn save(&mut self, backup: bool) -> std::io::Result<()> {
+        let path = Path::new(&self.file_info.path);
+        write_document(path, &self.buffer, &self.format, self.hex.as_ref(), backup)?;
+        self.mark_saved();
+        let state = self.saved_state;
+        if let Some(swap) = self.swap.as_mut().filter(|swap| swap.recovery.is_none())
+            && swap.write(None, state).is_err()
+        {
+            self.swap = None;
+        }
+        Ok(())
+    }
+
+    // Remember the current text as what is on disk
+    fn mark_saved(&mut self) {
+        // Edits after the save must not be merged into the saved step
+        self.history.seal();
+        self.saved_state = self.history.state();
+        self.edits_since_save = 0;
+    }
+
+    // Write the swap file now: the text while there are unsaved changes,
+    // otherwise only that the document is open. Nothing is tried again
+    // after a failure.
+    fn write_swap(&mut self) -> std::io::Result<()> {
+        let state = self.history.state();
+        let Some(swap) = self.swap.as_ref() else {
+            return Ok(());
+        };
+        if swap.recovery.is_some() || swap.written == state {
+            return Ok(());
+        }
+
+        // Edits after this must not be merged into the written step, or the
+        // swap would pass for current while missing them
+        self.history.seal();
+        let text = self.is_modified().then(|| self.buffer.contents());
+        let swap = self.swap.as_mut().expect("checked above");
+        let result = swap.write(text.as_deref(), state);
+        if result.is_err() {
+            self.swap = None;
+        }
+        result
+    }
+}
+
+pub struct FileManager {
     pub toasts: Vec<Toast>,
     pub input_handler: InputHandler,
     pub file_browser: FileBrowser,
+    pub search: Search,
+    pub register: Register,
+    // Every open document in tab order, the one being edited at `active_tab`
+    pub tabs: Vec<Document>,
+    pub active_tab: usize,
+    pub config: Config,
+    pub keymap: Keymap,
+    // The list shown while the command palette, file finder or format
+    // choice is open
+    pub picker: Option<Picker>,
+    // The sidebar file tree, read the first time it is shown
+    pub tree: Option<FileTree>,
+    pub tree_visible: bool,
+    // Keys go to the tree instead of the text
+    pub tree_focused: bool,
 }
 
 impl FileManager {
     pub fn new(buffer: Buffer, file_info: FileInfo) -> Self {
-        let pointer = Pointer::new(0, 0);
+        let config = Config::default();
+        let settings = config.for_file(&file_info.name);
+        let document = Document::new(buffer, file_info, &settings);
         FileManager {
-            pointer,
-            buffer,
-            file_info,
             toasts: Vec::new(),
             input_handler: InputHandler::new(),
             file_browser: FileBrowser::new(),
+            search: Search::new(),
+            register: Register::default(),
+            tabs: vec![document],
+            active_tab: 0,
+            config,
+            keymap: Keymap::new(),
+            picker: None,
+            tree: None,
+            tree_visible: false,
+            tree_focused: false,
+        }
+    }
+
+    // The document in the active tab
+    pub fn doc(&self) -> &Document {
+        &self.tabs[self.active_tab]
+    }
+
+    pub fn doc_mut(&mut self) -> &mut Document {
+        &mut self.tabs[self.active_tab]
+    }
+
+    // Use `config` from now on, and for the document already open
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+        self.apply_settings();
+    }
+
+    // Settings for the active document's language
+    pub fn settings(&self) -> EditorSettings {
+        self.config.for_file(&self.doc().file_info.name)
+    }
+
+    // Set up the active document the way its language is configured
+    fn apply_settings(&mut self) {
+        let settings = self.settings();
+        let doc = self.doc_mut();
+        doc.highlighter =
+            Highlighter::for_buffer(&doc.file_info.name, &doc.buffer, &settings.theme);
+        doc.soft_wrap = settings.wrap;
+    }
+
+    // File info of the document in tab `index`
+    pub fn tab_file_info(&self, index: usize) -> &FileInfo {
+        &self.tabs[index].file_info
+    }
+
+    // A file or directory was renamed or moved from `from` to `to`. Open
+    // documents at or below it follow along, so saving doesn't bring the
+    // old path back.
+    pub fn path_moved(&mut self, from: &Path, to: &Path) {
+        let absolute = |path: &Path| std::path::absolute(path).unwrap_or(path.to_path_buf());
+        let (from, to) = (absolute(from), absolute(to));
+
+        for index in 0..self.tabs.len() {
+            let info = self.tab_file_info(index);
+            if info.is_untitled() {
+                continue;
+            }
+            let Ok(rest) = absolute(Path::new(&info.path))
+                .strip_prefix(&from)
+                .map(Path::to_path_buf)
+            else {
+                continue;
+            };
+            let moved = if rest.as_os_str().is_empty() {
+                to.clone()
+            } else {
+                to.join(rest)
+            };
+            let info = FileInfo::from_path(&moved.to_string_lossy());
+
+            if let Some(swap) = &mut self.tabs[index].swap {
+                let to = swap::swap_path(&moved);
+                let _ = fs::rename(&swap.path, &to);
+                swap.path = to;
+                swap.file = moved.clone();
+            }
+
+            let settings = self.config.for_file(&info.name);
+            let document = &mut self.tabs[index];
+            document.highlighter =
+                Highlighter::for_buffer(&info.name, &document.buffer, &settings.theme);
+            if index == self.active_tab {
+                document.soft_wrap = settings.wrap;
+            }
+            document.file_info = info;
+        }
+    }
+
+    // The active document has unsaved changes
+    pub fn is_modified(&self) -> bool {
+        self.doc().is_modified()
+    }
+
+    pub fn tab_modified(&self, index: usize) -> bool {
+        self.tabs[index].is_modified()
+    }
+
+    // First tab, counting from the active one, with unsaved changes
+    pub fn first_modified_tab(&self) -> Option<usize> {
+        let count = self.tabs.len();
+        (0..count)
+            .map(|i| (self.active_tab + i) % count)
+            .find(|&i| self.tab_modified(i))
+    }
+
+    // Write the file differently from now on. Until it's saved the document
+    // no longer matches the file on disk.
+    pub fn set_format(&mut self, format: FileFormat) {
+        if format == self.doc().format {
+            return;
+        }
+        // Line breaks kept as they were in the text become plain ones once
+        // the file has a single kind, as one undoable step
+        if self.doc().format.mixed_endings && !format.mixed_endings {
+            let text = self.doc().buffer.contents();
+            let unified = encoding::unify_line_endings(&text);
+            if unified != text {
+                self.doc_mut().selection = None;
+                self.replace_range(0..text.len(), &unified, EditGroup::Standalone);
+                let doc = self.doc_mut();
+                doc.history.seal();
+                doc.pointer = Pointer::new(0, 0);
+            }
         }
+        let doc = self.doc_mut();
+        doc.format = format;
+        // No history state is ever this, so it counts as modified
+        doc.saved_state = u64::MAX;
+    }
+
+    // Tab already showing the file at `path`
+    pub fn find_tab(&self, path: &str) -> Option<usize> {
+        let wanted = fs::canonicalize(path).ok();
+        (0..self.tabs.len()).find(|&i| {
+            let info = self.tab_file_info(i);
+            !info.is_untitled()
+                && (info.path == path
+                    || (wanted.is_some() && fs::canonicalize(&info.path).ok() == wanted))
+        })
+    }
+
+    pub fn switch_tab(&mut self, index: usize) {
+        if index == self.active_tab || index >= self.tabs.len() {
+            return;
+        }
+        // Changes made just before switching away shouldn't wait until the
+        // document is active again
+        let _ = self.write_swap();
+        self.active_tab = index;
+        // Matches point into the previous buffer
+        self.clear_search();
+    }
+
+    // Cycle to the next or previous tab, wrapping around
+    pub fn cycle_tab(&mut self, forward: bool) {
+        let count = self.tabs.len();
+        let index = if forward {
+            (self.active_tab + 1) % count
+        } else {
+            (self.active_tab + count - 1) % count
+        };
+        self.switch_tab(index);
+    }
+
+    // Show `buffer` in a new tab after the current one. An untouched empty
+    // untitled document is replaced instead of being kept around.
+    pub fn open_document(&mut self, buffer: Buffer, file_info: FileInfo, format: FileFormat) {
+        let settings = self.config.for_file(&file_info.name);
+        let mut document = Document::new(buffer, file_info, &settings);
+        document.format = format;
+        let doc = self.doc();
+        if doc.file_info.is_untitled() && doc.buffer.is_empty() && !doc.is_modified() {
+            *self.doc_mut() = document;
+        } else {
+            let _ = self.write_swap();
+            self.active_tab += 1;
+            self.tabs.insert(self.active_tab, document);
+        }
+        self.clear_search();
+    }
+
+    // Open a binary file as a hex dump in a tab of its own
+    pub fn open_hex(&mut self, bytes: Vec<u8>, file_info: FileInfo) {
+        self.open_document(Buffer::new(String::new()), file_info, FileFormat::default());
+        self.doc_mut().hex = Some(HexView::new(bytes));
+    }
+
+    // Type a hex digit over the byte under the cursor when byte editing is
+    // on. Returns false if it wasn't taken.
+    pub fn hex_type_digit(&mut self, digit: char) -> bool {
+        let doc = self.doc_mut();
+        let typed = doc.hex.as_mut().is_some_and(|hex| hex.type_digit(digit));
+        if typed {
+            // The bytes have no undo history, so nothing matches the saved
+            // state until the next save
+            doc.saved_state = u64::MAX;
+            doc.edits_since_save += 1;
+        }
+        typed
+    }
+
+    // Keep a swap file for the active document from now on. Returns the pid
+    // of another nox that has the file open already, in which case none is
+    // kept. Text left behind by one that didn't exit cleanly waits in the
+    // swap's `recovery`.
+    pub fn claim_swap(&mut self) -> std::io::Result<Option<u32>> {
+        let doc = self.doc_mut();
+        doc.swap = None;
+        if doc.file_info.is_untitled() || doc.hex.is_some() || doc.buffer.is_mapped() {
+            return Ok(None);
+        }
+
+        let path = Path::new(&doc.file_info.path);
+        let mut swap = Swap::new(path);
+        if let Some(found) = swap::read(&swap.path) {
+            if found.pid != std::process::id() && swap::is_running(found.pid) {
+                return Ok(Some(found.pid));
+            }
+            if let Some(text) = found.text
+                && text != doc.buffer.contents()
+            {
+                let file_modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+                swap.recovery = Some(Recovery {
+                    text,
+                    newer: found.modified > file_modified,
+                });
+            }
+        }
+        doc.swap = Some(swap);
+        doc.write_swap()?;
+        Ok(None)
+    }
+
+    // Text left in the active document's swap file, waiting to be
+    // recovered or discarded
+    pub fn recovery(&self) -> Option<&Recovery> {
+        self.doc()
+            .swap
+            .as_ref()
+            .and_then(|swap| swap.recovery.as_ref())
+    }
+
+    // Put the text left in the swap file in place of the file's, as an edit
+    // that can be undone
+    pub fn recover_swap(&mut self) {
+        let doc = self.doc_mut();
+        let Some(recovery) = doc.swap.as_mut().and_then(|swap| swap.recovery.take()) else {
+            return;
+        };
+        doc.selection = None;
+        let end = doc.buffer.len_bytes();
+        self.replace_range(0..end, &recovery.text, EditGroup::Standalone);
+        let doc = self.doc_mut();
+        doc.history.seal();
+        doc.pointer = Pointer::new(0, 0);
+        let _ = doc.write_swap();
+    }
+
+    // Throw away the text left in the swap file
+    pub fn discard_swap(&mut self) {
+        if let Some(swap) = &mut self.doc_mut().swap {
+            swap.recovery = None;
+        }
+        let _ = self.write_swap();
+    }
+
+    // Leave the swap file as it is and stop keeping one for the active
+    // document, so its text can still be recovered later
+    pub fn keep_swap(&mut self) {
+        self.doc_mut().swap = None;
+    }
+
+    // Bring the swap file up to date once changes have waited for
+    // SWAP_DELAY. Returns how long until the next write is due, if any.
+    pub fn sync_swap(&mut self) -> std::io::Result<Option<Duration>> {
+        let doc = self.doc_mut();
+        let state = doc.history.state();
+        let Some(swap) = doc.swap.as_mut() else {
+            return Ok(None);
+        };
+        if swap.recovery.is_some() || swap.written == state {
+            return Ok(None);
+        }
+
+        let waited = swap.changed_at.get_or_insert_with(Instant::now).elapsed();
+        if waited < swap::SWAP_DELAY {
+            return Ok(Some(swap::SWAP_DELAY - waited));
+        }
+        doc.write_swap()?;
+        Ok(None)
+    }
+
+    fn write_swap(&mut self) -> std::io::Result<()> {
+        self.doc_mut().write_swap()
+    }
+
+    // Remove the swap files of every open document, when quitting
+    pub fn close_swaps(&mut self) {
+        for swap in self
+            .tabs
+            .iter()
+            .filter_map(|document| document.swap.as_ref())
+        {
+            swap.remove();
+        }
+    }
+
+    // Close the active tab, moving to the one before it. Closing the last
+    // tab leaves an empty untitled document.
+    pub fn close_tab(&mut self) {
+        if let Some(swap) = self.doc_mut().swap.take() {
+            swap.remove();
+        }
+        if self.tabs.len() == 1 {
+            let settings = self.config.for_file("");
+            *self.doc_mut() =
+                Document::new(Buffer::new(String::new()), FileInfo::untitled(), &settings);
+        } else {
+            self.tabs.remove(self.active_tab);
+            self.active_tab = self.active_tab.saturating_sub(1);
+        }
+        // Matches point into the closed buffer
+        self.clear_search();
     }
 
     pub fn add_toast(&mut self, message: &str, duration_ms: u64, toast_type: ToastType) {
@@ -194,114 +696,703 @@ impl FileManager {
     }
 
     pub fn move_pointer(&mut self, dy: isize, dx: isize) {
+        let tab_width = self.settings().tab_width;
+        let doc = self.doc_mut();
         let new_y = if dy < 0 {
-            self.pointer.y.saturating_sub(dy.abs() as usize)
+            doc.pointer.y.saturating_sub(dy.unsigned_abs())
         } else {
-            self.pointer.y.saturating_add(dy as usize)
+            doc.pointer.y.saturating_add(dy as usize)
         };
 
-        let max_y = self.buffer.data.len().saturating_sub(1);
+        let max_y = doc.buffer.len_lines().saturating_sub(1);
         let bounded_y = std::cmp::min(new_y, max_y);
 
-        let new_x = if dx < 0 {
-            self.pointer.x.saturating_sub(dx.abs() as usize)
+        let line = doc.buffer.line(bounded_y);
+        let mut new_x = if bounded_y != doc.pointer.y {
+            // Stay in the same screen column, not the same byte
+            let current = doc.buffer.line(doc.pointer.y);
+            let column = text::byte_to_column(&current, doc.pointer.x, tab_width);
+            text::column_to_byte(&line, column, tab_width)
         } else {
-            self.pointer.x.saturating_add(dx as usize)
+            text::snap_to_grapheme(&line, doc.pointer.x)
         };
 
-        let current_line_len = self.buffer.data.get(bounded_y).map_or(0, |line| line.len());
+        // Horizontal steps are whole grapheme clusters
+        for _ in 0..dx.unsigned_abs() {
+            new_x = if dx < 0 {
+                text::prev_grapheme(&line, new_x)
+            } else {
+                text::next_grapheme(&line, new_x)
+            };
+        }
 
-        let bounded_x = std::cmp::min(new_x, current_line_len);
+        doc.pointer.y = bounded_y;
+        doc.pointer.x = new_x;
 
-        self.pointer.y = bounded_y;
-        self.pointer.x = bounded_x;
+        // Moving away starts a fresh undo step for the next edit
+        doc.history.seal();
     }
 
-    pub fn insert_char(&mut self, c: char) {
-        if let Some(line) = self.buffer.data.get_mut(self.pointer.y) {
-            let safe_x = std::cmp::min(self.pointer.x, line.len());
-            line.insert(safe_x, c);
-            self.pointer.x += 1;
+    // Move up or down `dy` rows on screen. With soft wrap on that is by
+    // wrapped row in a content area `width` columns wide, otherwise by line.
+    pub fn move_row(&mut self, dy: isize, width: usize) {
+        if !self.doc().soft_wrap {
+            self.move_pointer(dy, 0);
+            return;
+        }
+
+        let tab_width = self.settings().tab_width;
+        let doc = self.doc_mut();
+        let mut line = doc.buffer.line(doc.pointer.y);
+        let mut rows = text::wrap_rows(&line, width, tab_width);
+        let mut row = text::row_of(&rows, doc.pointer.x);
+        // Stay in the same column of the row
+        let column = text::byte_to_column(&line, doc.pointer.x, tab_width)
+            - text::byte_to_column(&line, rows[row].start, tab_width);
+
+        let last_line = doc.buffer.len_lines().saturating_sub(1);
+        for _ in 0..dy.unsigned_abs() {
+            if dy < 0 && row > 0 {
+                row -= 1;
+            } else if dy > 0 && row + 1 < rows.len() {
+                row += 1;
+            } else if dy < 0 && doc.pointer.y > 0 {
+                doc.pointer.y -= 1;
+                line = doc.buffer.line(doc.pointer.y);
+                rows = text::wrap_rows(&line, width, tab_width);
+                row = rows.len() - 1;
+            } else if dy > 0 && doc.pointer.y < last_line {
+                doc.pointer.y += 1;
+                line = doc.buffer.line(doc.pointer.y);
+                rows = text::wrap_rows(&line, width, tab_width);
+                row = 0;
+            } else {
+                break;
+            }
+        }
+
+        let range = rows[row].clone();
+        let row_start = text::byte_to_column(&line, range.start, tab_width);
+        let mut x = text::column_to_byte(&line, row_start + column, tab_width);
+        // The end of a row other than the last is the start of the next one
+        if x >= range.end && row + 1 < rows.len() {
+            x = text::prev_grapheme(&line, range.end);
         }
+        doc.pointer.x = x;
+        doc.history.seal();
     }
-    pub fn delete_char(&mut self) {
-        if self.pointer.x > 0 && self.pointer.y < self.buffer.data.len() {
-            if let Some(line) = self.buffer.data.get_mut(self.pointer.y) {
-                line.remove(self.pointer.x - 1);
-                self.pointer.x -= 1;
+
+    // Scroll sideways just far enough that the cursor shows in a content
+    // area `width` columns wide, keeping it off the first and last column
+    // where the markers for clipped text go
+    pub fn scroll_to_cursor(&mut self, width: usize) {
+        let tab_width = self.settings().tab_width;
+        let doc = self.doc_mut();
+        let line = doc.buffer.line(doc.pointer.y);
+        let column = text::byte_to_column(&line, doc.pointer.x, tab_width);
+        // Just past the cursor, which is a column wide at the end of the line
+        let next = text::next_grapheme(&line, doc.pointer.x);
+        let end = text::byte_to_column(&line, next, tab_width);
+        let end = std::cmp::max(end, column + 1);
+
+        if column < doc.scroll_x + 1 {
+            doc.scroll_x = column.saturating_sub(1);
+        } else if width > 2 && end + 1 > doc.scroll_x + width {
+            doc.scroll_x = end + 1 - width;
+        }
+    }
+
+    // Show the sidebar with the current file selected and focused, or hide
+    // it again
+    pub fn toggle_tree(&mut self) {
+        if self.tree_visible {
+            self.tree_visible = false;
+            self.tree_focused = false;
+            return;
+        }
+        let tree = self.tree.get_or_insert_with(|| {
+            let root = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+            FileTree::new(&root)
+        });
+        tree.refresh();
+        let file_info = &self.tabs[self.active_tab].file_info;
+        if !file_info.is_untitled() {
+            tree.reveal(Path::new(&file_info.path));
+        }
+        self.tree_visible = true;
+        self.tree_focused = true;
+    }
+
+    // Move the keyboard between the sidebar and the text, showing the
+    // sidebar if it's hidden
+    pub fn toggle_tree_focus(&mut self) {
+        if self.tree_visible {
+            self.tree_focused = !self.tree_focused;
+        } else {
+            self.toggle_tree();
+        }
+    }
+
+    pub fn toggle_soft_wrap(&mut self) {
+        let doc = self.doc_mut();
+        doc.soft_wrap = !doc.soft_wrap;
+        doc.scroll_x = 0;
+    }
+
+    pub fn move_to_line_start(&mut self) {
+        let doc = self.doc_mut();
+        doc.pointer.x = 0;
+        doc.history.seal();
+    }
+
+    pub fn move_to_line_end(&mut self) {
+        let doc = self.doc_mut();
+        doc.pointer.x = doc.buffer.line_len(doc.pointer.y);
+        doc.history.seal();
+    }
+
+    // Jump to the start of the next word, or back to the start of the
+    // current one, wrapping across lines at either end
+    pub fn move_word(&mut self, forward: bool) {
+        let doc = self.doc_mut();
+        let line = doc.buffer.line(doc.pointer.y);
+        let last_line = doc.buffer.len_lines().saturating_sub(1);
+
+        if forward && doc.pointer.x >= line.len() && doc.pointer.y < last_line {
+            doc.pointer = Pointer::new(0, doc.pointer.y + 1);
+        } else if !forward && doc.pointer.x == 0 && doc.pointer.y > 0 {
+            doc.pointer.y -= 1;
+            doc.pointer.x = doc.buffer.line_len(doc.pointer.y);
+        } else if forward {
+            doc.pointer.x = text::next_word(&line, doc.pointer.x);
+        } else {
+            doc.pointer.x = text::prev_word(&line, doc.pointer.x);
+        }
+        doc.history.seal();
+    }
+
+    // Called before the cursor moves: Shift starts or extends a selection,
+    // plain movement drops one unless it was started with a mark
+    pub fn update_selection(&mut self, shift: bool) {
+        let doc = self.doc_mut();
+        match doc.selection {
+            None if shift => {
+                doc.selection = Some(Selection::new(
+                    doc.pointer,
+                    SelectionMode::Characterwise,
+                    false,
+                ))
+            }
+            Some(selection) if !shift && !selection.mark => doc.selection = None,
+            _ => {}
+        }
+    }
+
+    // Set a mark at the cursor, so moving selects from here; setting the same
+    // kind of mark again clears it
+    pub fn toggle_mark(&mut self, mode: SelectionMode) {
+        let doc = self.doc_mut();
+        doc.selection = match doc.selection {
+            Some(selection) if selection.mode == mode => None,
+            _ => Some(Selection::new(doc.pointer, mode, true)),
+        };
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.doc_mut().selection = None;
+    }
+
+    // Selected byte range, None when nothing or nothing visible is selected
+    pub fn selection_range(&self) -> Option<std::ops::Range<usize>> {
+        let doc = self.doc();
+        self.doc()
+            .selection
+            .map(|selection| selection.range(doc.pointer, &doc.buffer))
+            .filter(|range| {
+                !range.is_empty() || doc.selection.map(|s| s.mode) == Some(SelectionMode::Linewise)
+            })
+    }
+
+    // Remove the selected text as its own undo step. Returns false if there
+    // was nothing selected.
+    fn delete_selection(&mut self) -> bool {
+        let range = self.selection_range();
+        self.doc_mut().selection = None;
+        match range {
+            Some(range) if !range.is_empty() => {
+                self.replace_range(range, "", EditGroup::Standalone);
+                true
             }
-        } else if self.pointer.y > 0 && self.pointer.x == 0 {
-            let current_line = self.buffer.data.remove(self.pointer.y);
-            self.pointer.y -= 1;
+            _ => false,
+        }
+    }
+
+    // What copying would put in the register: the selection, or the cursor
+    // line when nothing is selected
+    fn selected_text(&self) -> Register {
+        let doc = self.doc();
+        let Some(range) = self.selection_range() else {
+            return Register {
+                text: doc.buffer.line(doc.pointer.y),
+                linewise: true,
+            };
+        };
 
-            if let Some(prev_line) = self.buffer.data.get_mut(self.pointer.y) {
-                self.pointer.x = prev_line.len();
-                prev_line.push_str(&current_line);
+        match doc.selection {
+            Some(selection) if selection.mode == SelectionMode::Linewise => {
+                let lines = selection.lines(doc.pointer);
+                let start = doc.buffer.line_to_byte(lines.start);
+                let end = doc.buffer.line_range(lines.end - 1).end;
+                Register {
+                    text: doc.buffer.slice(start..end),
+                    linewise: true,
+                }
             }
+            _ => Register {
+                text: doc.buffer.slice(range),
+                linewise: false,
+            },
         }
     }
 
-    pub fn new_line(&mut self) {
-        if self.pointer.x
-            < self
-                .buffer
-                .data
-                .get(self.pointer.y)
-                .map_or(0, |line| line.len())
-        {
-            let mut current_line = self.buffer.data.remove(self.pointer.y);
-            let new_line = current_line.split_off(self.pointer.x);
-            self.buffer.data.insert(self.pointer.y, current_line);
-            self.buffer.data.insert(self.pointer.y + 1, new_line);
+    pub fn copy(&mut self) {
+        self.register = self.selected_text();
+        self.doc_mut().selection = None;
+    }
+
+    pub fn cut(&mut self) {
+        self.register = self.selected_text();
+        if !self.delete_selection() {
+            self.remove_current_line();
+        }
+    }
+
+    // Put the register in at the cursor, or above the cursor line if it
+    // holds whole lines, replacing the selection. Returns false if there is
+    // nothing to paste.
+    pub fn paste(&mut self) -> bool {
+        if self.register.text.is_empty() && !self.register.linewise {
+            return false;
+        }
+
+        let register = self.register.clone();
+        let replaced = self
+            .selection_range()
+            .and(self.doc().selection)
+            .map(|selection| (selection.mode, selection.lines(self.doc().pointer)));
+        let last_line = self.doc().buffer.len_lines();
+        let group = if self.delete_selection() {
+            EditGroup::Joined
         } else {
-            self.buffer.data.insert(self.pointer.y + 1, String::new());
+            EditGroup::Standalone
+        };
+
+        let cursor = self.doc().buffer.position_to_byte(self.doc().pointer);
+        let (at, text) = match replaced {
+            _ if !register.linewise => (cursor, register.text),
+            Some((SelectionMode::Characterwise, _)) => (cursor, register.text),
+            // Lines removed from the end of the buffer took the newline
+            // before them, put it back in front
+            Some((SelectionMode::Linewise, lines)) if lines.end == last_line && lines.start > 0 => {
+                (cursor, format!("\n{}", register.text))
+            }
+            _ => (
+                self.doc().buffer.line_to_byte(self.doc().pointer.y),
+                format!("{}\n", register.text),
+            ),
+        };
+        self.edit(Edit::insert(at, &text), group);
+        true
+    }
+
+    // Apply an edit to the buffer, move the cursor past it and record it for undo
+    fn edit(&mut self, edit: Edit, group: EditGroup) {
+        let doc = &mut self.tabs[self.active_tab];
+        // A hex view has no text to edit
+        if doc.hex.is_some() {
+            return;
+        }
+        let before = doc.pointer;
+        doc.selection = None;
+
+        let line = doc.buffer.byte_to_line(edit.at);
+        let breaks = edit.text.bytes().filter(|&b| b == b'\n').count();
+        match edit.kind {
+            EditKind::Insert => doc.highlighter.edited(line, 0, breaks),
+            EditKind::Delete => doc.highlighter.edited(line, breaks, 0),
+        }
+
+        edit.apply(&mut doc.buffer);
+        // Keep match highlights in step with the text
+        if self.search.active {
+            let (removed, inserted) = match edit.kind {
+                EditKind::Insert => (0, edit.text.len()),
+                EditKind::Delete => (edit.text.len(), 0),
+            };
+            self.search.edited(&doc.buffer, edit.at, removed, inserted);
+        }
+        doc.pointer = doc.buffer.byte_to_position(match edit.kind {
+            EditKind::Insert => edit.end(),
+            EditKind::Delete => edit.at,
+        });
+        doc.history.record(edit, group, before, doc.pointer);
+        // The second half of a replacement is part of the same change
+        if group != EditGroup::Joined {
+            doc.edits_since_save += 1;
         }
-        self.pointer.y += 1;
-        self.pointer.x = 0;
     }
 
-    pub fn save(&self) -> std::io::Result<()> {
-        let mut file = std::fs::File::create(&self.file_info.path)?;
-        for line in &self.buffer.data {
-            writeln!(file, "{}", line)?;
+    // Replace the bytes in `range` with `text` as a single undo step, or as
+    // part of the previous one when `group` is Joined
+    fn replace_range(&mut self, range: std::ops::Range<usize>, text: &str, group: EditGroup) {
+        let removed = self.doc().buffer.slice(range.clone());
+        self.edit(Edit::delete(range.start, &removed), group);
+        if !text.is_empty() {
+            self.edit(Edit::insert(range.start, text), EditGroup::Joined);
         }
-        Ok(())
+    }
+
+    // Begin an incremental search from the cursor
+    pub fn start_search(&mut self) {
+        self.search.active = true;
+        self.search.origin = self.doc().pointer;
+        self.search.query.clear();
+        self.search.replaced = 0;
+        self.search.update(&self.tabs[self.active_tab].buffer);
+    }
+
+    // Called as the query is typed: jump to the first match at or after
+    // where the search started
+    pub fn update_search(&mut self, query: &str) {
+        self.search.query = query.to_string();
+        self.search.update(&self.tabs[self.active_tab].buffer);
+
+        let origin = self.doc().buffer.position_to_byte(self.search.origin);
+        match self.search.next_from(origin) {
+            Some(index) => self.jump_to_match(index),
+            None => self.doc_mut().pointer = self.search.origin,
+        }
+    }
+
+    pub fn toggle_search_case(&mut self) {
+        self.search.case_sensitive = !self.search.case_sensitive;
+        let query = self.search.query.clone();
+        self.update_search(&query);
+    }
+
+    pub fn toggle_search_regex(&mut self) {
+        self.search.regex = !self.search.regex;
+        let query = self.search.query.clone();
+        self.update_search(&query);
+    }
+
+    fn jump_to_match(&mut self, index: usize) {
+        self.search.current = Some(index);
+        let doc = &mut self.tabs[self.active_tab];
+        doc.pointer = doc
+            .buffer
+            .byte_to_position(self.search.matches[index].start);
+        doc.history.seal();
+    }
+
+    // Move to the next or previous match from the cursor, wrapping around
+    pub fn search_next(&mut self, forward: bool) -> bool {
+        let offset = self.doc().buffer.position_to_byte(self.doc().pointer);
+        let index = if forward {
+            self.search.next_from(offset + 1)
+        } else {
+            self.search.prev_from(offset)
+        };
+
+        match index {
+            Some(index) => {
+                self.jump_to_match(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Give up on the search and put the cursor back where it started
+    pub fn cancel_search(&mut self) {
+        self.doc_mut().pointer = self.search.origin;
+        self.clear_search();
+    }
+
+    // Stop highlighting matches
+    pub fn clear_search(&mut self) {
+        self.search.active = false;
+        self.search.matches.clear();
+        self.search.current = None;
+    }
+
+    // Replace the match under the cursor and move on to the next one.
+    // Returns false once there is nothing left to replace.
+    pub fn replace_current(&mut self) -> bool {
+        let Some(range) = self
+            .search
+            .current
+            .and_then(|i| self.search.matches.get(i).cloned())
+        else {
+            return false;
+        };
+
+        let replacement = self.search.replacement_for(&self.doc().buffer, &range);
+        self.replace_range(range.clone(), &replacement, EditGroup::Standalone);
+        self.search.replaced += 1;
+
+        match self.search.next_from(range.start + replacement.len()) {
+            Some(index) => {
+                self.jump_to_match(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Replace every match as one undo step and return how many there were
+    pub fn replace_all(&mut self) -> usize {
+        let matches = std::mem::take(&mut self.search.matches);
+        let replacements: Vec<String> = matches
+            .iter()
+            .map(|range| self.search.replacement_for(&self.doc().buffer, range))
+            .collect();
+
+        // Work from the bottom up so earlier offsets stay valid, and without
+        // re-scanning for matches after every single edit
+        self.search.active = false;
+        for (i, (range, text)) in matches.iter().zip(&replacements).enumerate().rev() {
+            let group = if i + 1 == matches.len() {
+                EditGroup::Standalone
+            } else {
+                EditGroup::Joined
+            };
+            self.replace_range(range.clone(), text, group);
+        }
+        self.search.active = true;
+        self.search.refresh(&self.tabs[self.active_tab].buffer);
+
+        self.search.replaced += matches.len();
+        matches.len()
+    }
+
+    pub fn undo(&mut self) -> bool {
+        let doc = &mut self.tabs[self.active_tab];
+        doc.selection = None;
+        if let Some(start) = doc.history.undo_start() {
+            doc.highlighter
+                .invalidate_from(doc.buffer.byte_to_line(start));
+        }
+        match doc.history.undo(&mut doc.buffer) {
+            Some(cursor) => {
+                doc.pointer = cursor;
+                // The matches moved along with the text
+                if self.search.active {
+                    self.search.refresh(&doc.buffer);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn redo(&mut self) -> bool {
+        let doc = &mut self.tabs[self.active_tab];
+        doc.selection = None;
+        if let Some(start) = doc.history.redo_start() {
+            doc.highlighter
+                .invalidate_from(doc.buffer.byte_to_line(start));
+        }
+        match doc.history.redo(&mut doc.buffer) {
+            Some(cursor) => {
+                doc.pointer = cursor;
+                // The matches moved along with the text
+                if self.search.active {
+                    self.search.refresh(&doc.buffer);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn insert_char(&mut self, c: char) {
+        self.insert_text(c.encode_utf8(&mut [0; 4]), EditGroup::Typing);
+    }
+
+    // Insert typed text at the cursor, replacing the selection if there is one
+    fn insert_text(&mut self, text: &str, group: EditGroup) {
+        let group = if self.delete_selection() {
+            EditGroup::Joined
+        } else {
+            group
+        };
+        if self.doc().pointer.y < self.doc().buffer.len_lines() {
+            let at = self.doc().buffer.position_to_byte(self.doc().pointer);
+            self.edit(Edit::insert(at, text), group);
+        }
+    }
+
+    pub fn delete_char(&mut self) {
+        if self.delete_selection() {
+            return;
+        }
+        let doc = self.doc();
+        if doc.pointer.y >= doc.buffer.len_lines() || (doc.pointer.x == 0 && doc.pointer.y == 0) {
+            return;
+        }
+
+        // Take the whole grapheme before the cursor, or at the start of a line
+        // the line break before it, "\r\n" included, joining the two lines
+        let end = doc.buffer.position_to_byte(doc.pointer);
+        let start = if doc.pointer.x > 0 {
+            let line = doc.buffer.line(doc.pointer.y);
+            end - doc.pointer.x + text::prev_grapheme(&line, doc.pointer.x)
+        } else {
+            doc.buffer.line_break(doc.pointer.y - 1).start
+        };
+        let removed = doc.buffer.slice(start..end);
+        self.edit(Edit::delete(start, &removed), EditGroup::Deleting);
+    }
+
+    // Delete the grapheme under the cursor, or the line break at the end of
+    // the line
+    pub fn delete_forward(&mut self) {
+        if self.delete_selection() {
+            return;
+        }
+        let doc = self.doc();
+        let start = doc.buffer.position_to_byte(doc.pointer);
+        if start >= doc.buffer.len_bytes() {
+            return;
+        }
+
+        let line = doc.buffer.line(doc.pointer.y);
+        let end = if doc.pointer.x < line.len() {
+            start - doc.pointer.x + text::next_grapheme(&line, doc.pointer.x)
+        } else {
+            doc.buffer.line_break(doc.pointer.y).end
+        };
+        let removed = doc.buffer.slice(start..end);
+        self.edit(Edit::delete(start, &removed), EditGroup::Standalone);
+    }
+
+    pub fn new_line(&mut self) {
+        self.insert_text("\n", EditGroup::Standalone);
+    }
+
+    pub fn save(&mut self) -> std::io::Result<()> {
+        let backup = self.config.backup;
+        self.doc_mut().save(backup)
     }
 
     pub fn save_as(&mut self, path: &str) -> std::io::Result<()> {
-        let mut file = std::fs::File::create(path)?;
-        for line in &self.buffer.data {
-            writeln!(file, "{}", line)?;
+        let backup = self.config.backup;
+        let doc = self.doc_mut();
+        write_document(
+            Path::new(path),
+            &doc.buffer,
+            &doc.format,
+            doc.hex.as_ref(),
+            backup,
+        )?;
+        if let Some(swap) = doc.swap.take() {
+            swap.remove();
         }
-        self.file_info.path = path.to_string();
-        self.file_info.name = path.split('/').last().unwrap_or("unknown").to_string();
+        doc.file_info = FileInfo::from_path(path);
+        // The new name may be another language, with its own syntax and settings
+        self.apply_settings();
+        self.doc_mut().mark_saved();
+        // Whatever a crashed nox left for the file is moot now that it has
+        // been overwritten
+        let _ = self.claim_swap();
+        self.discard_swap();
         Ok(())
     }
 
-    pub fn tab(&mut self) {
-        let spaces = "    "; // 4 spaces for a tab
-        if let Some(line) = self.buffer.data.get_mut(self.pointer.y) {
-            let safe_x = std::cmp::min(self.pointer.x, line.len());
-            line.insert_str(safe_x, spaces);
-            self.pointer.x += spaces.len();
+    // Save every document with unsaved changes and a file to go to, without
+    // switching tabs. Returns the name of each one tried and how it went.
+    pub fn save_all(&mut self) -> Vec<(String, std::io::Result<()>)> {
+        let mut saved = Vec::new();
+        for index in 0..self.tabs.len() {
+            if !self.tab_modified(index) || self.tab_file_info(index).is_untitled() {
+                continue;
+            }
+            let result = self.tabs[index].save(self.config.backup);
+            saved.push((self.tab_file_info(index).name.clone(), result));
         }
+        saved
+    }
+
+    pub fn tab(&mut self) {
+        let settings = self.settings();
+        let indent = if settings.hard_tabs {
+            "\t".to_string()
+        } else {
+            " ".repeat(settings.tab_width)
+        };
+        self.insert_text(&indent, EditGroup::Typing);
     }
 
     pub fn remove_current_line(&mut self) {
-        if self.pointer.y < self.buffer.data.len() {
-            self.buffer.data.remove(self.pointer.y);
-            if self.pointer.y >= self.buffer.data.len() {
-                self.pointer.y = self.buffer.data.len().saturating_sub(1);
-            }
-            self.pointer.x = 0;
+        let doc = self.doc();
+        let y = doc.pointer.y;
+        let line_count = doc.buffer.len_lines();
+        if y >= line_count {
+            return;
         }
+
+        let range = selection::line_span(&doc.buffer, y..y + 1);
+        let removed = doc.buffer.slice(range.clone());
+        self.edit(Edit::delete(range.start, &removed), EditGroup::Standalone);
+        self.doc_mut().pointer.x = 0;
     }
 }
 
+// Write a document to `path`: the bytes of a hex view as they are, text
+// with the encoding and line endings it was read with or has been
+// converted to since. The text goes out piece by piece, so a large file
+// isn't copied into memory to be saved.
+fn write_document(
+    path: &Path,
+    buffer: &Buffer,
+    format: &FileFormat,
+    hex: Option<&HexView>,
+    backup: bool,
+) -> std::io::Result<()> {
+    save::write_file_with(path, backup, |out| match hex {
+        Some(hex) => out.write_all(&hex.bytes),
+        None => encoding::write_encoded(buffer.chunks(), format, out),
+    })
+}
+
+// One row of the file browser
+pub struct BrowserEntry {
+    pub name: String,
+    pub path: PathBuf,
+    pub is_dir: bool,
+}
+
 pub struct FileBrowser {
     pub browser_open: bool,
     pub pointer: usize,
-    pub paths: Option<Vec<DirEntry>>,
+    // The entries shown: ".." first, then those matching the filter
+    pub paths: Option<Vec<BrowserEntry>>,
+    // The directory being shown
+    pub dir: PathBuf,
+    // Typed characters narrowing down the entries
+    pub filter: String,
+    pub show_hidden: bool,
+    // Everything in `dir`, directories first
+    entries: Vec<BrowserEntry>,
+    // Name of the entry last selected in each directory
+    selections: HashMap<PathBuf, String>,
+}
+
+impl Default for FileBrowser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl FileBrowser {
@@ -310,12 +1401,19 @@ impl FileBrowser {
             browser_open: false,
             pointer: 0,
             paths: None,
+            dir: PathBuf::new(),
+            filter: String::new(),
+            show_hidden: false,
+            entries: Vec::new(),
+            selections: HashMap::new(),
         }
     }
 
     pub fn open_browser(&mut self, path: &str) -> Result<(), String> {
-        let entries = fs::read_dir(path);
-        let mut files: Vec<DirEntry> = vec![];
+        let dir =
+            fs::canonicalize(path).map_err(|e| format!("Error opening file browser: {}", e))?;
+        let entries = fs::read_dir(&dir);
+        let mut files: Vec<BrowserEntry> = vec![];
 
         match entries {
             Err(e) => {
@@ -324,7 +1422,12 @@ impl FileBrowser {
             Ok(files_iterator) => {
                 for i in files_iterator {
                     match i {
-                        Ok(entry) => files.push(entry),
+                        Ok(entry) => files.push(BrowserEntry {
+                            name: entry.file_name().to_string_lossy().to_string(),
+                            // Follow symlinks so linked directories can be entered
+                            is_dir: entry.path().is_dir(),
+                            path: entry.path(),
+                        }),
                         Err(e) => return Err(format!("Error reading directory entry: {}", e)),
                     }
                 }
@@ -332,20 +1435,120 @@ impl FileBrowser {
         }
 
         // Sort files: directories first, then files alphabetically
-        files.sort_by(|a, b| {
-            let a_is_dir = a.file_type().map(|ft| ft.is_dir()).unwrap_or(false);
-            let b_is_dir = b.file_type().map(|ft| ft.is_dir()).unwrap_or(false);
-
-            match (a_is_dir, b_is_dir) {
-                (true, false) => std::cmp::Ordering::Less,
-                (false, true) => std::cmp::Ordering::Greater,
-                _ => a.file_name().cmp(&b.file_name()),
-            }
+        files.sort_by(|a, b| match (a.is_dir, b.is_dir) {
+            (true, false) => std::cmp::Ordering::Less,
+            (false, true) => std::cmp::Ordering::Greater,
+            _ => a.name.cmp(&b.name),
         });
 
-        self.paths = Some(files);
+        self.remember_selection();
+        // Coming back up, land on the directory we just left
+        if self.browser_open
+            && Some(dir.as_path()) == self.dir.parent()
+            && let Some(name) = self.dir.file_name()
+        {
+            self.selections
+                .insert(dir.clone(), name.to_string_lossy().to_string());
+        }
+
+        self.dir = dir;
+        self.entries = files;
+        self.filter.clear();
         self.browser_open = true;
         self.pointer = 0;
+        let selected = self.selections.get(&self.dir).cloned();
+        self.refresh(selected.as_deref());
+        Ok(())
+    }
+
+    // Show the directory above the current one
+    pub fn open_parent(&mut self) -> Result<(), String> {
+        match self.dir.parent() {
+            Some(parent) => {
+                let parent = parent.to_string_lossy().to_string();
+                self.open_browser(&parent)
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Rebuild the visible list, keeping `selected` highlighted if it's
+    // still there
+    fn refresh(&mut self, selected: Option<&str>) {
+        let filter = self.filter.to_lowercase();
+        let mut paths = Vec::new();
+        if filter.is_empty()
+            && let Some(parent) = self.dir.parent()
+        {
+            paths.push(BrowserEntry {
+                name: "..".to_string(),
+                path: parent.to_path_buf(),
+                is_dir: true,
+            });
+        }
+        for entry in &self.entries {
+            let hidden = entry.name.starts_with('.');
+            if (self.show_hidden || !hidden) && entry.name.to_lowercase().contains(&filter) {
+                paths.push(BrowserEntry {
+                    name: entry.name.clone(),
+                    path: entry.path.clone(),
+                    is_dir: entry.is_dir,
+                });
+            }
+        }
+
+        self.pointer = selected
+            .and_then(|name| paths.iter().position(|entry| entry.name == name))
+            .unwrap_or(0);
+        self.paths = Some(paths);
+    }
+
+    fn selected_name(&self) -> Option<String> {
+        self.get_selected_entry().map(|entry| entry.name.clone())
+    }
+
+    fn remember_selection(&mut self) {
+        if let Some(name) = self.selected_name() {
+            self.selections.insert(self.dir.clone(), name);
+        }
+    }
+
+    pub fn push_filter(&mut self, c: char) {
+        let selected = self.selected_name();
+        self.filter.push(c);
+        self.refresh(selected.as_deref());
+    }
+
+    // Remove the last filter character. Returns false if there was none.
+    pub fn pop_filter(&mut self) -> bool {
+        let selected = self.selected_name();
+        let popped = self.filter.pop().is_some();
+        if popped {
+            self.refresh(selected.as_deref());
+        }
+        popped
+    }
+
+    pub fn clear_filter(&mut self) {
+        let selected = self.selected_name();
+        self.filter.clear();
+        self.refresh(selected.as_deref());
+    }
+
+    pub fn toggle_hidden(&mut self) {
+        let selected = self.selected_name();
+        self.show_hidden = !self.show_hidden;
+        self.refresh(selected.as_deref());
+    }
+
+    // Read the directory again after changing it, highlighting `select`
+    // if given
+    pub fn reload(&mut self, select: Option<&str>) -> Result<(), String> {
+        let dir = self.dir.to_string_lossy().to_string();
+        self.open_browser(&dir)?;
+        if let Some(name) = select {
+            self.refresh(Some(name));
+        }
         Ok(())
     }
 
@@ -368,26 +1571,84 @@ impl FileBrowser {
     }
 
     pub fn close_browser(&mut self) {
+        self.remember_selection();
         self.browser_open = false;
         self.pointer = 0;
         self.paths = None;
+        self.entries.clear();
+        self.filter.clear();
     }
 
     pub fn get_selected_path(&self) -> Option<String> {
-        if let Some(paths) = &self.paths {
-            if self.pointer < paths.len() {
-                return Some(paths[self.pointer].path().to_string_lossy().to_string());
-            }
-        }
-        None
+        self.get_selected_entry()
+            .map(|entry| entry.path.to_string_lossy().to_string())
     }
 
-    pub fn get_selected_entry(&self) -> Option<&DirEntry> {
-        if let Some(paths) = &self.paths {
-            if self.pointer < paths.len() {
-                return Some(&paths[self.pointer]);
-            }
+    pub fn get_selected_entry(&self) -> Option<&BrowserEntry> {
+        if let Some(paths) = &self.paths
+            && self.pointer < paths.len()
+        {
+            return Some(&paths[self.pointer]);
         }
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Mixed line endings, as a file that has them is opened
+    fn mixed() -> FileManager {
+        let buffer = Buffer::new("one\r\ntwo\nthree\r\nfour".to_string());
+        FileManager::new(buffer, FileInfo::untitled())
+    }
+
+    #[test]
+    fn typing_after_a_swap_write_leaves_the_swap_behind() {
+        let path = std::env::temp_dir().join(format!("nox-swap-{}", std::process::id()));
+        let info = FileInfo::from_path(&path.to_string_lossy());
+        let mut file_manager = FileManager::new(Buffer::new(String::new()), info);
+        file_manager.claim_swap().unwrap();
+
+        file_manager.insert_char('a');
+        file_manager.write_swap().unwrap();
+        assert_eq!(file_manager.sync_swap().unwrap(), None);
+        // Still the same word, but no longer what the swap has
+        file_manager.insert_char('b');
+        assert!(file_manager.sync_swap().unwrap().is_some());
+        file_manager.close_swaps();
+    }
+
+    #[test]
+    fn backspace_at_line_start_removes_the_whole_line_break() {
+        let mut file_manager = mixed();
+        file_manager.doc_mut().pointer = Pointer::new(0, 1);
+        file_manager.delete_char();
+        assert_eq!(
+            file_manager.doc().buffer.contents(),
+            "onetwo\nthree\r\nfour"
+        );
+        assert_eq!(file_manager.doc().pointer, Pointer::new(3, 0));
+
+        file_manager.doc_mut().pointer = Pointer::new(0, 1);
+        file_manager.delete_char();
+        assert_eq!(file_manager.doc().buffer.contents(), "onetwothree\r\nfour");
+    }
+
+    #[test]
+    fn delete_at_line_end_removes_the_whole_line_break() {
+        let mut file_manager = mixed();
+        file_manager.doc_mut().pointer = Pointer::new(3, 0);
+        file_manager.delete_forward();
+        assert_eq!(
+            file_manager.doc().buffer.contents(),
+            "onetwo\nthree\r\nfour"
+        );
+
+        file_manager.doc_mut().pointer = Pointer::new(5, 1);
+        file_manager.delete_forward();
+        assert_eq!(file_manager.doc().buffer.contents(), "onetwo\nthreefour");
+        assert_eq!(file_manager.doc().buffer.len_lines(), 2);
+    }
+}
@@ -1,32 +1,40 @@
+mod commands;
 mod fm;
 mod renderer;
 mod utils;
 
-use std::{
-    env,
-    io::{self, Read},
-};
+use std::env;
+use std::time::{Duration, Instant};
 
-use nox_editor::{Buffer, InputAction, InputResult};
+use nox_editor::config::Config;
+use nox_editor::hex::BYTES_PER_ROW;
+use nox_editor::keymap::Lookup;
+use nox_editor::keys::{Key, KeyCode, KeyReader, Modifiers};
+use nox_editor::{Buffer, FileInfo, InputAction, InputResult};
 
+use crate::commands::Flow;
+use crate::fm::FileContents;
 use crate::{fm::open_file, utils::*};
 
-fn handle_save_as(file_manager: &mut nox_editor::FileManager, path: &str) {
+// Save under the name typed at the prompt. Returns true if it was saved.
+fn handle_save_as(file_manager: &mut nox_editor::FileManager, path: &str) -> bool {
     if path.is_empty() {
-        return;
+        return false;
     }
 
     match file_manager.save_as(path) {
         Ok(_) => {
             // Update file info to the new path
-            file_manager.file_info.path = path.to_string();
-            file_manager.file_info.name = path.split('/').last().unwrap_or("unknown").to_string();
+            let file_info = &mut file_manager.doc_mut().file_info;
+            file_info.path = path.to_string();
+            file_info.name = path.split('/').next_back().unwrap_or("unknown").to_string();
 
             file_manager.add_toast(
                 &format!("File saved as: {}", path),
                 3000,
                 nox_editor::ToastType::Success,
             );
+            true
         }
         Err(e) => {
             file_manager.add_toast(
@@ -34,270 +42,709 @@ fn handle_save_as(file_manager: &mut nox_editor::FileManager, path: &str) {
                 5000,
                 nox_editor::ToastType::Error,
             );
+            false
         }
     }
 }
 
-fn main() {
-    let mut stdin = io::stdin();
+// Keys for the Find / Replace prompts that aren't text. Returns true if the
+// key was used.
+fn handle_search_key(file_manager: &mut nox_editor::FileManager, key: Key) -> bool {
+    match (key.code, key.mods) {
+        (KeyCode::Down, _) => {
+            file_manager.search_next(true);
+        }
+        (KeyCode::Up, _) => {
+            file_manager.search_next(false);
+        }
+        (KeyCode::Char('c'), Modifiers::ALT) => file_manager.toggle_search_case(),
+        (KeyCode::Char('r'), Modifiers::ALT) => file_manager.toggle_search_regex(),
+        _ => return false,
+    }
+    true
+}
 
-    set_terminal_raw_mode().expect("Failed to set terminal to raw mode");
-    clear_screen();
+// Keys that move through the command palette or file finder list. Returns true if the key
+// was used.
+fn handle_picker_key(file_manager: &mut nox_editor::FileManager, key: Key) -> bool {
+    let Some(picker) = file_manager.picker.as_mut() else {
+        return false;
+    };
+    match key.code {
+        KeyCode::Up => picker.move_selection(-1),
+        KeyCode::Down => picker.move_selection(1),
+        KeyCode::PageUp => picker.move_selection(-10),
+        KeyCode::PageDown => picker.move_selection(10),
+        _ => return false,
+    }
+    true
+}
 
-    let args: Vec<String> = env::args().collect();
+// One key per match: replace it, skip it, replace everything or stop
+fn handle_confirm_replace(file_manager: &mut nox_editor::FileManager, key: Key) {
+    let more = match key.code {
+        KeyCode::Char('y') | KeyCode::Enter => file_manager.replace_current(),
+        KeyCode::Char('n') => file_manager.search_next(true),
+        KeyCode::Char('a') => {
+            file_manager.replace_all();
+            false
+        }
+        KeyCode::Char('q') | KeyCode::Escape => false,
+        _ => true,
+    };
 
-    let mut data: Vec<String>;
-    let name: String;
-    let path: String;
-
-    if args.len() < 2 {
-        data = vec![String::new()];
-        name = "Untitled".to_string();
-        path = "/".to_string();
-    } else {
-        let file_result = open_file(&args[1]);
-        match file_result {
-            Ok(content) => data = content,
-            Err(e) => {
-            eprintln!("Error opening file: {}", e);
-            data = vec![String::new()];
+    if !more {
+        file_manager.input_handler.cancel_input();
+        file_manager.clear_search();
+        let replaced = file_manager.search.replaced;
+        file_manager.add_toast(
+            &format!(
+                "Replaced {} occurrence{}",
+                replaced,
+                if replaced == 1 { "" } else { "s" }
+            ),
+            3000,
+            nox_editor::ToastType::Success,
+        );
+    }
+}
+
+// Keys for a hex view: the arrows move a byte or a row at a time, and with
+// byte editing on, hex digits overwrite the byte under the cursor. Returns
+// true if the key was used.
+fn handle_hex_key(file_manager: &mut nox_editor::FileManager, key: Key) -> bool {
+    if key.mods.ctrl() || key.mods.alt() {
+        return false;
+    }
+    if let KeyCode::Char(c) = key.code {
+        return file_manager.hex_type_digit(c);
+    }
+    // Roughly a screen of rows
+    let (rows, _) = get_terminal_size().unwrap_or((24, 80));
+    let page = rows.saturating_sub(8).max(1) as isize * BYTES_PER_ROW as isize;
+    let Some(hex) = file_manager.doc_mut().hex.as_mut() else {
+        return false;
+    };
+    match key.code {
+        KeyCode::Left => hex.move_cursor(-1),
+        KeyCode::Right => hex.move_cursor(1),
+        KeyCode::Up => hex.move_cursor(-(BYTES_PER_ROW as isize)),
+        KeyCode::Down => hex.move_cursor(BYTES_PER_ROW as isize),
+        KeyCode::PageUp => hex.move_cursor(-page),
+        KeyCode::PageDown => hex.move_cursor(page),
+        KeyCode::Home => hex.move_to_row_start(),
+        KeyCode::End => hex.move_to_row_end(),
+        _ => return false,
+    }
+    true
+}
+
+// Keys that move around the sidebar tree. Enter opens a file in the
+// editor and hands the focus back to it. Returns true if the key was used.
+fn handle_tree_key(file_manager: &mut nox_editor::FileManager, key: Key) -> bool {
+    let Some(tree) = file_manager.tree.as_mut() else {
+        return false;
+    };
+    if key.mods.ctrl() || key.mods.alt() {
+        return false;
+    }
+    match key.code {
+        KeyCode::Up => tree.move_pointer(-1),
+        KeyCode::Down => tree.move_pointer(1),
+        KeyCode::PageUp => tree.move_pointer(-10),
+        KeyCode::PageDown => tree.move_pointer(10),
+        KeyCode::Home => tree.pointer = 0,
+        KeyCode::End => tree.move_pointer(isize::MAX),
+        KeyCode::Left => tree.collapse(),
+        KeyCode::Right => tree.expand(),
+        KeyCode::Escape => file_manager.tree_focused = false,
+        KeyCode::Enter => match tree.selected() {
+            Some(node) if node.is_dir => tree.toggle(),
+            Some(node) => {
+                let path = node.path.to_string_lossy().to_string();
+                if fm::open_in_editor(file_manager, &path) {
+                    file_manager.tree_focused = false;
+                }
             }
+            None => {}
+        },
+        _ => return false,
+    }
+    true
+}
+
+// Ask for what to do with the entry selected in the browser: a new name to
+// rename or copy it to, or whether to really delete it
+fn start_browser_prompt(file_manager: &mut nox_editor::FileManager, action: InputAction) {
+    let Some(entry) = file_manager.file_browser.get_selected_entry() else {
+        return;
+    };
+    if entry.name == ".." {
+        file_manager.add_toast(
+            "Select a file or directory first",
+            3000,
+            nox_editor::ToastType::Warning,
+        );
+        return;
+    }
+
+    let name = entry.name.clone();
+    let input = &mut file_manager.input_handler;
+    match action {
+        InputAction::RenamePath => {
+            input.start_input_with_text(&format!("Rename {} to", name), action, &name)
+        }
+        InputAction::DuplicatePath => {
+            input.start_input_with_text(&format!("Copy {} to", name), action, &copy_name(&name))
+        }
+        _ => input.start_input_with_prompt(&format!("Delete {}? (y/n)", name), action),
+    }
+}
+
+// "main.rs" → "main_copy.rs"
+fn copy_name(name: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => format!("{}_copy.{}", stem, extension),
+        _ => format!("{}_copy", name),
+    }
+}
+
+fn handle_confirm_delete(file_manager: &mut nox_editor::FileManager, key: Key) {
+    match key.code {
+        KeyCode::Char('y') => {
+            file_manager.input_handler.cancel_input();
+            fm::browser_operation(file_manager, InputAction::ConfirmDelete, "");
+        }
+        KeyCode::Char('n') | KeyCode::Escape => {
+            file_manager.input_handler.cancel_input();
+        }
+        _ => {}
+    }
+}
+
+// Answer to finding unsaved changes a crashed nox left behind: take them,
+// throw them away, look at how they differ first, or decide another time
+fn handle_recover_swap(file_manager: &mut nox_editor::FileManager, key: Key) {
+    let name = file_manager.doc().file_info.name.clone();
+    match key.code {
+        KeyCode::Char('r') => {
+            file_manager.input_handler.cancel_input();
+            file_manager.recover_swap();
+            file_manager.add_toast(
+                &format!("Recovered unsaved changes to {}", name),
+                3000,
+                nox_editor::ToastType::Success,
+            );
         }
-        name = args[1].split('/').last().unwrap_or("unknown").to_string();
-        path = args[1].to_string();
+        KeyCode::Char('d') => {
+            file_manager.input_handler.cancel_input();
+            file_manager.discard_swap();
+            file_manager.add_toast(
+                &format!("Discarded unsaved changes to {}", name),
+                3000,
+                nox_editor::ToastType::Info,
+            );
+        }
+        // Asked again on coming back to the file's tab
+        KeyCode::Char('v') => {
+            file_manager.input_handler.cancel_input();
+            fm::show_recovery_diff(file_manager);
+        }
+        KeyCode::Escape => {
+            file_manager.input_handler.cancel_input();
+            file_manager.keep_swap();
+            file_manager.add_toast(
+                &format!("Kept the changes, open {} again to recover them", name),
+                4000,
+                nox_editor::ToastType::Info,
+            );
+        }
+        _ => {}
     }
+}
 
-    if data.len() == 0 {
-        data = vec![String::new()];
+// Answer to closing a tab with unsaved changes: save it, drop the changes or
+// keep the tab open. While quitting every tab is closed this way in turn;
+// returns true once there is nothing left to ask about and the editor can
+// exit. An untitled tab is asked a name for first, and `after_save_as` holds
+// the close or quit to carry on with once it is saved.
+fn handle_confirm_close(
+    file_manager: &mut nox_editor::FileManager,
+    key: Key,
+    quitting: bool,
+    after_save_as: &mut Option<InputAction>,
+) -> bool {
+    match key.code {
+        KeyCode::Char('y') | KeyCode::Char('s') => {
+            let action = file_manager.input_handler.action_type;
+            file_manager.input_handler.cancel_input();
+            if file_manager.doc().file_info.is_untitled() {
+                // Nowhere to save to yet, ask for a name first
+                file_manager
+                    .input_handler
+                    .start_input_with_prompt("Save As", InputAction::SaveAs);
+                *after_save_as = Some(action);
+                return false;
+            }
+            match file_manager.save() {
+                Ok(_) => file_manager.close_tab(),
+                Err(e) => {
+                    file_manager.add_toast(
+                        &format!("Error saving file: {}", e),
+                        5000,
+                        nox_editor::ToastType::Error,
+                    );
+                    return false;
+                }
+            }
+        }
+        KeyCode::Char('n') | KeyCode::Char('d') => {
+            file_manager.input_handler.cancel_input();
+            file_manager.close_tab();
+        }
+        KeyCode::Char('c') | KeyCode::Escape => {
+            file_manager.input_handler.cancel_input();
+            return false;
+        }
+        _ => return false,
     }
 
-    let buf = Buffer::new(data);
+    quitting && commands::request_quit(file_manager)
+}
 
-    let file_info = nox_editor::FileInfo { name, path };
+fn main() {
+    // Put back when main returns or anything panics
+    let _terminal = TerminalGuard::new().expect("Failed to set terminal to raw mode");
 
-    let mut file_manager = nox_editor::FileManager::new(buf, file_info);
+    let args: Vec<String> = env::args().collect();
+
+    let mut file_manager =
+        nox_editor::FileManager::new(Buffer::new(String::new()), FileInfo::untitled());
 
     file_manager.add_toast("Welcome to Nox Editor!", 5000, nox_editor::ToastType::Info);
 
-    renderer::render(&file_manager);
+    // A broken config leaves the defaults in place
+    let config = Config::load().unwrap_or_else(|e| {
+        file_manager.add_toast(
+            &format!("Config error: {}", e),
+            8000,
+            nox_editor::ToastType::Error,
+        );
+        Config::default()
+    });
+    let (keymap, errors) = commands::keymap(&config.keybindings);
+    for e in errors {
+        file_manager.add_toast(
+            &format!("Keybinding error: {}", e),
+            8000,
+            nox_editor::ToastType::Error,
+        );
+    }
+    file_manager.keymap = keymap;
+    file_manager.set_config(config);
+    let autosave = file_manager.config.autosave.clone();
+    if autosave.focus_lost {
+        set_focus_reporting(true);
+    }
 
-    let mut buffer = [0; 1];
+    // Every file named on the command line gets a tab
+    for path in args.iter().skip(1) {
+        let contents = match open_file(path, file_manager.config.large_file_mb) {
+            Ok(content) => content,
+            Err(e) => {
+                // Shown as a toast, anything printed is hidden by the
+                // alternate screen
+                file_manager.add_toast(
+                    &format!("Error opening file: {}", e),
+                    5000,
+                    nox_editor::ToastType::Error,
+                );
+                FileContents::Text(Buffer::new(String::new()), Default::default())
+            }
+        };
+        fm::open_contents(&mut file_manager, contents, FileInfo::from_path(path));
+    }
+    file_manager.switch_tab(0);
+
+    renderer::render(&mut file_manager);
+
+    let mut keys = KeyReader::new();
+    // When the last key came in, and whether the idle autosave has happened
+    // since
+    let mut last_input = Instant::now();
+    let mut idle_saved = false;
+    // Closing or quitting that waits for an untitled tab to be saved
+    let mut after_save_as = None;
     loop {
         // Update toasts before handling input (remove expired toasts)
         file_manager.update_toasts();
 
-        if file_manager.file_browser.browser_open {
-            if stdin.read(&mut buffer).is_ok() {
-                match buffer[0] {
-                    // Handle Arrow Keys and Escape
-                    b'\x1b' => {
-                        let mut arrow_buffer = [0; 2];
-                        if stdin.read(&mut arrow_buffer).is_ok() {
-                            match arrow_buffer {
-                                [b'[', b'A'] => file_manager.file_browser.move_pointer(-1), // Up arrow - decrease pointer
-                                [b'[', b'B'] => file_manager.file_browser.move_pointer(1), // Down arrow - increase pointer
-                                _ => {
-                                    // This might be just an escape key
-                                    file_manager.file_browser.close_browser();
-                                    file_manager.add_toast(
-                                        "File browser closed",
-                                        2000,
-                                        nox_editor::ToastType::Info,
-                                    );
+        // Indexing a large file came across bytes that aren't UTF-8
+        if let Some(offset) = file_manager.doc().buffer.take_invalid_utf8() {
+            let message = format!(
+                "{} isn't all UTF-8 (byte {}), bytes that aren't show as '?' and are saved unchanged",
+                file_manager.doc().file_info.name,
+                offset
+            );
+            file_manager.add_toast(&message, 8000, nox_editor::ToastType::Warning);
+            renderer::render(&mut file_manager);
+        }
+
+        // Untitled documents have nowhere to go and are left alone
+        if autosave.edits > 0
+            && file_manager.doc().edits_since_save >= autosave.edits
+            && !file_manager.doc().file_info.is_untitled()
+        {
+            fm::autosave_active(&mut file_manager);
+            renderer::render(&mut file_manager);
+        }
+
+        // Unsaved changes a crashed nox left for the file just shown
+        if file_manager.recovery().is_some()
+            && !file_manager.input_handler.taking_input
+            && !file_manager.file_browser.browser_open
+        {
+            fm::ask_recovery(&mut file_manager);
+            renderer::render(&mut file_manager);
+        }
+
+        // Changes go to the swap file once typing pauses for a moment
+        let swap_due = file_manager.sync_swap().unwrap_or_else(|e| {
+            file_manager.add_toast(
+                &format!("Cannot write swap file: {}", e),
+                5000,
+                nox_editor::ToastType::Warning,
+            );
+            renderer::render(&mut file_manager);
+            None
+        });
+        let idle_due = (autosave.idle > 0 && !idle_saved)
+            .then(|| Duration::from_secs(autosave.idle).saturating_sub(last_input.elapsed()));
+        if idle_due == Some(Duration::ZERO) {
+            idle_saved = true;
+            fm::autosave(&mut file_manager);
+            renderer::render(&mut file_manager);
+            continue;
+        }
+        if let Some(wait) = swap_due.into_iter().chain(idle_due).min() {
+            match keys.poll(wait) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(_) => break,
+            }
+        }
+
+        // A large file is indexed a chunk at a time while no keys come in,
+        // counting its lines in the status bar as it goes
+        if !file_manager.doc().buffer.is_indexed() {
+            match keys.poll(Duration::ZERO) {
+                Ok(true) => {}
+                Ok(false) => {
+                    file_manager.doc().buffer.index_more();
+                    renderer::render(&mut file_manager);
+                    continue;
+                }
+                Err(_) => break,
+            }
+        }
+
+        let key = match keys.read_key() {
+            Ok(key) => key,
+            Err(_) => break,
+        };
+
+        // Focus changes aren't keys, they only matter to autosave
+        match key.code {
+            KeyCode::FocusLost => {
+                if autosave.focus_lost {
+                    fm::autosave(&mut file_manager);
+                    renderer::render(&mut file_manager);
+                }
+                continue;
+            }
+            KeyCode::FocusGained => continue,
+            _ => {}
+        }
+        last_input = Instant::now();
+        idle_saved = false;
+
+        // While a browser prompt is up, keys go to the prompt
+        if file_manager.file_browser.browser_open && !file_manager.input_handler.taking_input {
+            let browser = &mut file_manager.file_browser;
+            match key.code {
+                KeyCode::Up => browser.move_pointer(-1), // Up arrow - decrease pointer
+                KeyCode::Down => browser.move_pointer(1), // Down arrow - increase pointer
+                // ESC drops the filter first, then closes the browser
+                KeyCode::Escape if !browser.filter.is_empty() => browser.clear_filter(),
+                KeyCode::Escape => {
+                    browser.close_browser();
+                    file_manager.add_toast(
+                        "File browser closed",
+                        2000,
+                        nox_editor::ToastType::Info,
+                    );
+                }
+                // Backspace edits the filter, or goes up a directory
+                KeyCode::Backspace => {
+                    if !browser.pop_filter()
+                        && let Err(e) = browser.open_parent()
+                    {
+                        file_manager.add_toast(&e, 5000, nox_editor::ToastType::Error);
+                    }
+                }
+                KeyCode::Char('h') if key.mods.alt() => {
+                    browser.toggle_hidden();
+                    let message = if browser.show_hidden {
+                        "Showing hidden files"
+                    } else {
+                        "Hiding hidden files"
+                    };
+                    file_manager.add_toast(message, 2000, nox_editor::ToastType::Info);
+                }
+                KeyCode::Char('n') if key.mods.alt() => file_manager
+                    .input_handler
+                    .start_input_with_prompt("New file", InputAction::NewFile),
+                KeyCode::Char('m') if key.mods.alt() => file_manager
+                    .input_handler
+                    .start_input_with_prompt("New directory", InputAction::NewDirectory),
+                KeyCode::Char('r') if key.mods.alt() => {
+                    start_browser_prompt(&mut file_manager, InputAction::RenamePath)
+                }
+                KeyCode::F(2) => start_browser_prompt(&mut file_manager, InputAction::RenamePath),
+                KeyCode::Char('c') if key.mods.alt() => {
+                    start_browser_prompt(&mut file_manager, InputAction::DuplicatePath)
+                }
+                KeyCode::Delete => {
+                    start_browser_prompt(&mut file_manager, InputAction::ConfirmDelete)
+                }
+                // Enter key - open selected file/directory
+                KeyCode::Enter => {
+                    if let Some(entry) = browser.get_selected_entry() {
+                        let path = entry.path.to_string_lossy().to_string();
+                        if entry.is_dir {
+                            // Navigate into directory
+                            match browser.open_browser(&path) {
+                                Ok(_) => {}
+                                Err(e) => {
+                                    file_manager.add_toast(&e, 5000, nox_editor::ToastType::Error);
                                 }
                             }
-                        } else {
-                            // Single escape key press
+                        } else if fm::open_in_editor(&mut file_manager, &path) {
                             file_manager.file_browser.close_browser();
-                            file_manager.add_toast(
-                                "File browser closed",
-                                2000,
-                                nox_editor::ToastType::Info,
-                            );
                         }
                     }
-                    // Enter key - open selected file/directory
-                    0x0d | 0x0a => {
-                        if let Some(entry) = file_manager.file_browser.get_selected_entry() {
-                            let is_dir = entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false);
-
-                            if is_dir {
-                                // Navigate into directory
-                                let path = entry.path().to_string_lossy().to_string();
-                                match file_manager.file_browser.open_browser(&path) {
-                                    Ok(_) => {}
-                                    Err(e) => {
-                                        file_manager.add_toast(
-                                            &e,
-                                            5000,
-                                            nox_editor::ToastType::Error,
-                                        );
-                                    }
-                                }
-                            } else {
-                                // Open file - replace current buffer
-                                let path = entry.path().to_string_lossy().to_string();
-                                match fm::open_file(&path) {
-                                    Ok(new_data) => {
-                                        file_manager.buffer.data = if new_data.is_empty() {
-                                            vec![String::new()]
-                                        } else {
-                                            new_data
-                                        };
-                                        file_manager.file_info.path = path.clone();
-                                        file_manager.file_info.name =
-                                            path.split('/').last().unwrap_or("unknown").to_string();
-                                        file_manager.pointer.x = 0;
-                                        file_manager.pointer.y = 0;
-                                        file_manager.file_browser.close_browser();
-                                        file_manager.add_toast(
-                                            &format!("Opened: {}", file_manager.file_info.name),
-                                            3000,
-                                            nox_editor::ToastType::Success,
-                                        );
-                                    }
-                                    Err(e) => {
-                                        file_manager.add_toast(
-                                            &format!("Error opening file: {}", e),
-                                            5000,
-                                            nox_editor::ToastType::Error,
-                                        );
-                                    }
-                                }
-                            }
-                        }
+                }
+                // Anything typed narrows down the list
+                _ => {
+                    if let Some(c) = key.text() {
+                        browser.push_filter(c);
                     }
-                    _ => {}
                 }
-                renderer::render(&file_manager);
             }
+            renderer::render(&mut file_manager);
             continue; // Don't process other inputs while browser is open
         }
 
         if file_manager.input_handler.taking_input {
-            if stdin.read(&mut buffer).is_ok() {
-                // Handle input mode keys
-                match file_manager.input_handler.handle_key(buffer[0]) {
-                    InputResult::Confirmed(input) => {
-                        // Process the confirmed input based on action type
-                        match file_manager.input_handler.action_type {
-                            InputAction::SaveAs => handle_save_as(&mut file_manager, &input),
-                            InputAction::Generic => {
+            let action = file_manager.input_handler.action_type;
+
+            // Search prompts take navigation and option keys on top of text
+            if action == InputAction::ConfirmReplace {
+                handle_confirm_replace(&mut file_manager, key);
+                renderer::render(&mut file_manager);
+                continue;
+            }
+            if matches!(action, InputAction::ConfirmClose | InputAction::ConfirmQuit) {
+                let quitting = action == InputAction::ConfirmQuit;
+                if handle_confirm_close(&mut file_manager, key, quitting, &mut after_save_as) {
+                    break;
+                }
+                renderer::render(&mut file_manager);
+                continue;
+            }
+            if action == InputAction::RecoverSwap {
+                handle_recover_swap(&mut file_manager, key);
+                renderer::render(&mut file_manager);
+                continue;
+            }
+            if action == InputAction::ConfirmDelete {
+                handle_confirm_delete(&mut file_manager, key);
+                renderer::render(&mut file_manager);
+                continue;
+            }
+            if matches!(action, InputAction::Find | InputAction::Replace)
+                && handle_search_key(&mut file_manager, key)
+            {
+                renderer::render(&mut file_manager);
+                continue;
+            }
+            if matches!(
+                action,
+                InputAction::CommandPalette | InputAction::FindFile | InputAction::FileFormat
+            ) && handle_picker_key(&mut file_manager, key)
+            {
+                renderer::render(&mut file_manager);
+                continue;
+            }
+
+            // Handle input mode keys
+            match file_manager.input_handler.handle_key(key) {
+                InputResult::Confirmed(input) => {
+                    // Process the confirmed input based on action type
+                    match action {
+                        InputAction::SaveAs => {
+                            let saved = handle_save_as(&mut file_manager, &input);
+                            match after_save_as.take() {
+                                Some(pending) if saved => {
+                                    file_manager.close_tab();
+                                    if pending == InputAction::ConfirmQuit
+                                        && commands::request_quit(&mut file_manager)
+                                    {
+                                        break;
+                                    }
+                                }
+                                _ => {}
+                            }
+                        }
+                        InputAction::Find => {
+                            if file_manager.search.matches.is_empty() {
+                                file_manager.clear_search();
+                                if !input.is_empty() {
+                                    file_manager.add_toast(
+                                        &format!("No matches for: {}", input),
+                                        3000,
+                                        nox_editor::ToastType::Warning,
+                                    );
+                                }
+                            }
+                        }
+                        InputAction::Replace => {
+                            if file_manager.search.matches.is_empty() {
+                                file_manager.cancel_search();
                                 file_manager.add_toast(
-                                    &format!("Received input: {}", input),
+                                    "Nothing to replace",
                                     3000,
-                                    nox_editor::ToastType::Info,
+                                    nox_editor::ToastType::Warning,
+                                );
+                            } else {
+                                file_manager.input_handler.start_input_with_prompt(
+                                    &format!("Replace \"{}\" with", input),
+                                    InputAction::ReplaceWith,
                                 );
                             }
                         }
-                    }
-                    InputResult::Cancelled => {
-                        file_manager.add_toast(
-                            "Operation cancelled",
-                            2000,
-                            nox_editor::ToastType::Info,
-                        );
-                    }
-                    InputResult::InProgress => {}
-                }
-                renderer::render(&file_manager);
-            }
-            continue;
-        }
-
-        if stdin.read(&mut buffer).is_ok() {
-            match buffer[0] {
-                // Handle Arrow Keys
-                b'\x1b' => {
-                    let mut arrow_buffer = [0; 2];
-                    if stdin.read(&mut arrow_buffer).is_ok() {
-                        match arrow_buffer {
-                            [b'[', b'A'] => file_manager.move_pointer(-1, 0), // Up arrow - decrease y
-                            [b'[', b'B'] => file_manager.move_pointer(1, 0), // Down arrow - increase y
-                            [b'[', b'C'] => file_manager.move_pointer(0, 1), // Right arrow - increase x
-                            [b'[', b'D'] => file_manager.move_pointer(0, -1), // Left arrow - decrease x
-
-                            // Alt+S for Save As
-                            [b's', _] => {
-                                file_manager
-                                    .input_handler
-                                    .start_input_with_prompt("Save As", InputAction::SaveAs);
-                                renderer::render(&file_manager);
+                        InputAction::ReplaceWith => {
+                            file_manager.search.replacement = input;
+                            if file_manager.search.current.is_none() {
+                                file_manager.search_next(true);
                             }
-                            _ => {}
-                        }
-                    }
-                }
-                //CTRL+O to open file
-                0x0f => {
-                    // Open file dialog - start from current working directory or file's directory
-                    let start_path = if file_manager.file_info.path == "/"
-                        || file_manager.file_info.path.is_empty()
-                    {
-                        std::env::current_dir()
-                            .unwrap_or_else(|_| std::path::PathBuf::from("."))
-                            .to_string_lossy()
-                            .to_string()
-                    } else {
-                        // Use the directory of the current file
-                        std::path::Path::new(&file_manager.file_info.path)
-                            .parent()
-                            .unwrap_or_else(|| std::path::Path::new("."))
-                            .to_string_lossy()
-                            .to_string()
-                    };
-
-                    match file_manager.file_browser.open_browser(&start_path) {
-                        Ok(_) => {
-                            file_manager.add_toast(
-                                "File browser opened - Use ↑/↓ to navigate, Enter to select, ESC to close",
-                                4000,
-                                nox_editor::ToastType::Info,
+                            file_manager.input_handler.start_input_with_prompt(
+                                "Replace this match?",
+                                InputAction::ConfirmReplace,
                             );
                         }
-                        Err(e) => {
-                            file_manager.add_toast(&e, 5000, nox_editor::ToastType::Error);
+                        InputAction::CommandPalette => {
+                            if commands::run_picked(&mut file_manager) == Flow::Quit {
+                                break;
+                            }
                         }
-                    }
-                    renderer::render(&file_manager);
-                }
-                0x13 => {
-                    // Ctrl+S to save
-                    match file_manager.save() {
-                        Ok(_) => {
-                            file_manager.add_toast(
-                                "File saved successfully!",
-                                3000,
-                                nox_editor::ToastType::Success,
-                            );
+                        InputAction::FindFile => commands::open_picked_file(&mut file_manager),
+                        InputAction::FileFormat => commands::apply_picked_format(&mut file_manager),
+                        InputAction::NewFile
+                        | InputAction::NewDirectory
+                        | InputAction::RenamePath
+                        | InputAction::DuplicatePath => {
+                            fm::browser_operation(&mut file_manager, action, &input)
                         }
-                        Err(e) => {
+                        InputAction::ConfirmReplace
+                        | InputAction::ConfirmClose
+                        | InputAction::ConfirmQuit
+                        | InputAction::ConfirmDelete
+                        | InputAction::RecoverSwap => {}
+                        InputAction::Generic => {
                             file_manager.add_toast(
-                                &format!("Error saving file: {}", e),
-                                5000,
-                                nox_editor::ToastType::Error,
+                                &format!("Received input: {}", input),
+                                3000,
+                                nox_editor::ToastType::Info,
                             );
                         }
                     }
                 }
-                0x11 => break,                   // Ctrl+Q to quit
-                0x0a => file_manager.new_line(), // Line Feed (LF) - Unix style
-                0x0d => file_manager.new_line(), // Carriage Return (CR) - Mac style
-                0x09 => file_manager.tab(),
-                key => {
-                    // Handle other keys
-                    if key.is_ascii_graphic() || key == b' ' {
-                        file_manager.insert_char(key as char);
-                    } else if key == 0x7f || key == 0x08 {
-                        file_manager.delete_char();
+                InputResult::Cancelled => {
+                    after_save_as = None;
+                    if matches!(action, InputAction::Find | InputAction::Replace) {
+                        file_manager.cancel_search();
+                    } else if action == InputAction::ReplaceWith {
+                        file_manager.clear_search();
+                    } else if file_manager.picker.is_some() {
+                        // The palette, file finder or format list
+                        file_manager.picker = None;
                     }
+                    file_manager.add_toast(
+                        "Operation cancelled",
+                        2000,
+                        nox_editor::ToastType::Info,
+                    );
+                }
+                InputResult::InProgress => {
+                    // Search as the query is typed
+                    if matches!(action, InputAction::Find | InputAction::Replace) {
+                        let query = file_manager.input_handler.input_buffer.clone();
+                        if query != file_manager.search.query {
+                            file_manager.update_search(&query);
+                        }
+                    }
+                    if let Some(picker) = file_manager.picker.as_mut() {
+                        picker.filter(&file_manager.input_handler.input_buffer);
+                    }
+                }
+            }
+            renderer::render(&mut file_manager);
+            continue;
+        }
+
+        // A hex view moves by bytes and takes hex digits while editing;
+        // other keys reach the commands, which can't change its text
+        if file_manager.doc().hex.is_some()
+            && !file_manager.tree_focused
+            && file_manager.keymap.pending().is_empty()
+            && handle_hex_key(&mut file_manager, key)
+        {
+            renderer::render(&mut file_manager);
+            continue;
+        }
+
+        // The sidebar takes navigation keys while focused, anything else
+        // still reaches the commands
+        if file_manager.tree_focused
+            && file_manager.keymap.pending().is_empty()
+            && handle_tree_key(&mut file_manager, key)
+        {
+            renderer::render(&mut file_manager);
+            continue;
+        }
+
+        match file_manager.keymap.feed(key) {
+            Lookup::Command(name) => {
+                let command = commands::find(&name).expect("keymap only holds known commands");
+                if (command.run)(&mut file_manager) == Flow::Quit {
+                    break;
+                }
+            }
+            Lookup::Pending => {}
+            Lookup::Unbound(keys) => {
+                // Typing, unless it was part of a sequence that went nowhere
+                // or the sidebar has the focus
+                if let ([_], Some(c), false) =
+                    (keys.as_slice(), key.text(), file_manager.tree_focused)
+                {
+                    file_manager.insert_char(c);
                 }
             }
-            renderer::render(&file_manager);
         }
+        renderer::render(&mut file_manager);
     }
 
     //Exit code
-    disable_terminal_raw_mode().expect("Failed to disable raw mode");
-    clear_screen();
+    file_manager.close_swaps();
 }
@@ -0,0 +1,233 @@
+// A list to choose from by typing part of an entry: the command palette and
+// the file finder. Entries are fuzzy matched, so the typed characters have
+// to appear in order but not next to each other.
+
+pub struct PickerItem {
+    pub label: String,
+    // Shown next to the label, e.g. a command's key binding
+    pub detail: String,
+}
+
+pub struct PickerMatch {
+    // Index into `Picker::items`
+    pub item: usize,
+    // Byte offsets of the matched characters in the label
+    pub positions: Vec<usize>,
+}
+
+pub struct Picker {
+    pub items: Vec<PickerItem>,
+    // Items matching the query, best first
+    pub matches: Vec<PickerMatch>,
+    // Index into `matches`
+    pub selected: usize,
+    query: String,
+}
+
+impl Picker {
+    pub fn new(items: Vec<PickerItem>) -> Self {
+        let mut picker = Picker {
+            items,
+            matches: Vec::new(),
+            selected: 0,
+            query: String::new(),
+        };
+        picker.refresh();
+        picker
+    }
+
+    // Keep the items matching `query`, best matches first. An empty query
+    // keeps everything in the original order.
+    pub fn filter(&mut self, query: &str) {
+        if query != self.query {
+            self.query = query.to_string();
+            self.refresh();
+        }
+    }
+
+    fn refresh(&mut self) {
+        let query = &self.query;
+        let mut scored: Vec<(i64, PickerMatch)> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(item, entry)| {
+                let (score, positions) = fuzzy_match(query, &entry.label)?;
+                Some((score, PickerMatch { item, positions }))
+            })
+            .collect();
+        // Stable, so equal scores stay in item order
+        scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+
+        self.matches = scored.into_iter().map(|(_, m)| m).collect();
+        self.selected = 0;
+    }
+
+    pub fn move_selection(&mut self, delta: isize) {
+        let last = self.matches.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+    }
+
+    // Index into `items` of the highlighted entry
+    pub fn selected_item(&self) -> Option<usize> {
+        self.matches.get(self.selected).map(|m| m.item)
+    }
+}
+
+// Score `candidate` against `pattern`, ignoring case, and say where the
+// pattern's characters were found. Matches at the start of words and runs
+// of consecutive characters score higher; gaps and long candidates lower.
+pub fn fuzzy_match(pattern: &str, candidate: &str) -> Option<(i64, Vec<usize>)> {
+    let pattern: Vec<char> = pattern
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect();
+    if pattern.is_empty() {
+        return Some((0, Vec::new()));
+    }
+
+    let chars: Vec<(usize, char)> = candidate.char_indices().collect();
+    let lower: Vec<char> = chars
+        .iter()
+        .map(|(_, c)| c.to_lowercase().next().unwrap_or(*c))
+        .collect();
+
+    // Try every place the first character occurs and keep the best run
+    let mut best: Option<(i64, Vec<usize>)> = None;
+    for start in 0..chars.len() {
+        if lower[start] != pattern[0] {
+            continue;
+        }
+
+        let mut found = vec![start];
+        let mut at = start + 1;
+        for &wanted in &pattern[1..] {
+            match (at..chars.len()).find(|&i| lower[i] == wanted) {
+                Some(i) => {
+                    found.push(i);
+                    at = i + 1;
+                }
+                None => break,
+            }
+        }
+        if found.len() < pattern.len() {
+            // Later starts won't find more
+            break;
+        }
+
+        let score = score_positions(&chars, &found);
+        if best.as_ref().is_none_or(|(b, _)| score > *b) {
+            let positions = found.iter().map(|&i| chars[i].0).collect();
+            best = Some((score, positions));
+        }
+    }
+
+    best
+}
+
+fn score_positions(chars: &[(usize, char)], found: &[usize]) -> i64 {
+    let mut score = 0;
+    for (n, &i) in found.iter().enumerate() {
+        score += 1;
+
+        let word_start = match i.checked_sub(1).map(|p| chars[p].1) {
+            None => true,
+            Some(previous) => {
+                !previous.is_alphanumeric()
+                    || (previous.is_lowercase() && chars[i].1.is_uppercase())
+            }
+        };
+        if word_start {
+            score += 8;
+        }
+
+        if n > 0 {
+            let gap = i - found[n - 1] - 1;
+            if gap == 0 {
+                score += 5;
+            } else {
+                score -= std::cmp::min(gap, 5) as i64;
+            }
+        }
+    }
+    score - (chars.len() / 8) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(pattern: &str, candidate: &str) -> i64 {
+        fuzzy_match(pattern, candidate).expect("should match").0
+    }
+
+    fn picker(labels: &[&str]) -> Picker {
+        let items = labels
+            .iter()
+            .map(|label| PickerItem {
+                label: label.to_string(),
+                detail: String::new(),
+            })
+            .collect();
+        Picker::new(items)
+    }
+
+    fn labels(picker: &Picker) -> Vec<&str> {
+        picker
+            .matches
+            .iter()
+            .map(|m| picker.items[m.item].label.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn characters_match_in_order_ignoring_case() {
+        assert_eq!(fuzzy_match("sv", "Save File").unwrap().1, [0, 2]);
+        assert_eq!(fuzzy_match("SF", "save file").unwrap().1, [0, 5]);
+        // Whitespace in the pattern is ignored
+        assert_eq!(fuzzy_match("s f", "save file").unwrap().1, [0, 5]);
+        assert!(fuzzy_match("fs", "save file").is_none());
+        assert!(fuzzy_match("x", "save file").is_none());
+        assert_eq!(fuzzy_match("", "anything"), Some((0, Vec::new())));
+    }
+
+    #[test]
+    fn positions_are_byte_offsets() {
+        assert_eq!(fuzzy_match("éb", "aébc").unwrap().1, [1, 3]);
+    }
+
+    #[test]
+    fn word_starts_and_runs_score_higher() {
+        assert!(score("of", "open file") > score("of", "profile"));
+        assert!(score("sa", "save") > score("sa", "sofa"));
+        // Camel case humps count as word starts
+        assert!(score("gf", "goToFile") > score("gf", "gruff"));
+        // Shorter candidates win a tie
+        assert!(score("save", "save") > score("save", "save all the things now"));
+    }
+
+    #[test]
+    fn the_best_start_is_kept() {
+        // The second "f" starts a word and a run
+        assert_eq!(fuzzy_match("fi", "of file").unwrap().1, [3, 4]);
+    }
+
+    #[test]
+    fn filter_sorts_best_first_and_keeps_order_for_ties() {
+        let mut picker = picker(&["profile", "open file", "other", "open folder"]);
+        assert_eq!(
+            labels(&picker),
+            ["profile", "open file", "other", "open folder"]
+        );
+
+        picker.filter("of");
+        assert_eq!(labels(&picker), ["open file", "open folder", "profile"]);
+        picker.move_selection(5);
+        assert_eq!(picker.selected_item(), Some(0));
+
+        picker.filter("zz");
+        assert!(picker.matches.is_empty());
+        assert_eq!(picker.selected_item(), None);
+    }
+}
@@ -1,40 +1,118 @@
+mod screen;
+
 use std::io::{Write, stdout};
+use std::ops::Range;
+use std::sync::Mutex;
+
+use unicode_segmentation::UnicodeSegmentation;
 
-use nox_editor::{FileManager, InputAction, ToastType};
-use syntect::easy::HighlightLines;
-use syntect::highlighting::ThemeSet;
-use syntect::parsing::SyntaxSet;
-use syntect::util::as_24_bit_terminal_escaped;
+use nox_editor::hex::BYTES_PER_ROW;
+use nox_editor::picker::Picker;
+use nox_editor::{FileManager, InputAction, ToastType, keymap, text};
 
+use crate::commands;
 use crate::utils::colors::*;
-use crate::utils::{clear_screen, get_terminal_size};
+use crate::utils::get_terminal_size;
+use screen::Screen;
 
-// Static for syntax/theme sets (load once)
 lazy_static::lazy_static! {
-    static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
-    static ref THEME_SET: ThemeSet = ThemeSet::load_defaults();
+    // What is on the terminal now, to diff the next frame against
+    static ref PREVIOUS_FRAME: Mutex<Option<Screen>> = Mutex::new(None);
 }
 
 fn center_text(text: &str, width: usize) -> String {
-    if text.len() >= width {
+    let text_width = text::display_width(text);
+    if text_width >= width {
         return text.to_string();
     }
 
-    let padding = (width - text.len()) / 2;
+    let padding = (width - text_width) / 2;
     format!("{}{}", " ".repeat(padding), text)
 }
 
-pub fn render(file_manager: &FileManager) {
-    clear_screen();
+// Pad `text` with spaces to `width` columns
+fn fill_width(text: &str, width: usize) -> String {
+    let padding = width.saturating_sub(text::display_width(text));
+    format!("{}{}", text, " ".repeat(padding))
+}
 
-    //TODO: Improve this function
-    if (file_manager.file_browser.browser_open) {
-        render_browser(file_manager);
-        return;
+// Columns taken by the line numbers and the `│ ` after them, none when
+// line numbers are turned off
+fn gutter_width(file_manager: &FileManager) -> usize {
+    if !file_manager.settings().line_numbers {
+        return 0;
     }
+    let line_count = file_manager.doc().buffer.len_lines();
+    std::cmp::max(3, line_count.to_string().len()) + 2
+}
 
+// Columns taken by the file tree and the line to its right, none when it's
+// hidden or the terminal is too narrow to leave room for the text
+fn sidebar_width(file_manager: &FileManager, terminal_cols: usize) -> usize {
+    if !file_manager.tree_visible || file_manager.tree.is_none() {
+        return 0;
+    }
+    let width = (terminal_cols / 4).clamp(16, 32);
+    if terminal_cols < width + 20 {
+        return 0;
+    }
+    width + 1
+}
+
+// Columns of the content area next to the gutter
+pub fn text_width(file_manager: &FileManager) -> usize {
+    let (_, terminal_cols) = get_terminal_size().unwrap_or((24, 80));
+    let gutter = gutter_width(file_manager);
+    let sidebar = sidebar_width(file_manager, terminal_cols as usize);
+    (terminal_cols as usize).saturating_sub(gutter + sidebar)
+}
+
+// Draw the editor, or the file browser when it is open. Each part pushes its
+// rows onto the frame, which is then diffed against what is on screen.
+pub fn render(file_manager: &mut FileManager) {
     let (terminal_rows, terminal_cols) = get_terminal_size().unwrap_or((24, 80));
+    let mut frame = Vec::new();
+
+    // Everything up to the bottom of the screen needs highlighting, the
+    // lines above the screen too since their state carries down
+    let doc = file_manager.doc_mut();
+    let bottom = doc.pointer.y + terminal_rows as usize;
+    doc.highlighter.update(&doc.buffer, bottom);
 
+    if file_manager.file_browser.browser_open {
+        render_browser(file_manager, &mut frame);
+    } else {
+        if !file_manager.doc().soft_wrap {
+            file_manager.scroll_to_cursor(text_width(file_manager));
+        }
+        render_editor(file_manager, &mut frame, terminal_rows, terminal_cols);
+    }
+
+    draw(&frame, terminal_rows as usize, terminal_cols as usize);
+}
+
+// Send the terminal only the cells that changed since the last frame, in a
+// single write
+fn draw(frame: &[String], rows: usize, cols: usize) {
+    let screen = Screen::from_lines(frame, rows, cols);
+    let mut previous = PREVIOUS_FRAME
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let output = screen.diff(previous.as_ref());
+
+    let mut out = stdout().lock();
+    out.write_all(output.as_bytes())
+        .and_then(|_| out.flush())
+        .expect("Failed to write to stdout");
+    *previous = Some(screen);
+}
+
+fn render_editor(
+    file_manager: &FileManager,
+    frame: &mut Vec<String>,
+    terminal_rows: u16,
+    terminal_cols: u16,
+) {
     // Calculate used lines BEFORE rendering content
     let mut used_lines = 4; // title bar (2) + status bar (1) + separator (1)
 
@@ -49,34 +127,76 @@ pub fn render(file_manager: &FileManager) {
         used_lines += 1; // footer
     }
 
-    render_title_bar(file_manager, terminal_cols);
-    render_content(file_manager, terminal_rows, used_lines);
-    render_status_bar(file_manager, terminal_cols);
+    render_title_bar(file_manager, frame, terminal_cols);
+    match &file_manager.picker {
+        // The list takes the place of the text while picking
+        Some(picker) if file_manager.input_handler.taking_input => {
+            let rows = terminal_rows.saturating_sub(used_lines) as usize;
+            render_picker(picker, frame, rows, terminal_cols as usize);
+        }
+        _ => {
+            // Binary files are shown as a hex dump instead of text
+            let render_text = if file_manager.doc().hex.is_some() {
+                render_hex
+            } else {
+                render_content
+            };
+            let sidebar = sidebar_width(file_manager, terminal_cols as usize);
+            if sidebar == 0 {
+                render_text(
+                    file_manager,
+                    frame,
+                    terminal_rows,
+                    terminal_cols,
+                    used_lines,
+                );
+            } else {
+                // The tree and the text side by side, row by row
+                let mut content = Vec::new();
+                render_text(
+                    file_manager,
+                    &mut content,
+                    terminal_rows,
+                    terminal_cols - sidebar as u16,
+                    used_lines,
+                );
+                let rows = std::cmp::max(1, terminal_rows.saturating_sub(used_lines) as usize);
+                let tree = render_tree(file_manager, rows, sidebar - 1);
+                for (i, tree_row) in tree.iter().enumerate() {
+                    let text_row = content.get(i).map(String::as_str).unwrap_or("");
+                    frame.push(format!(
+                        "{}{}│{}{}",
+                        tree_row, BRIGHT_BLACK, RESET, text_row
+                    ));
+                }
+            }
+        }
+    }
+    render_status_bar(file_manager, frame, terminal_cols);
 
     // Only show toasts if not taking input
     if file_manager.input_handler.taking_input {
-        render_input_prompt(file_manager);
+        render_input_prompt(file_manager, frame);
     } else if !file_manager.toasts.is_empty() {
-        render_toasts(file_manager);
+        render_toasts(file_manager, frame);
     }
 
     if show_footer {
-        render_footer();
+        render_footer(file_manager, frame, terminal_cols);
     }
-
-    stdout().flush().expect("Failed to flush stdout");
 }
 
-fn render_title_bar(file_manager: &FileManager, terminal_cols: u16) {
-    let filename = if file_manager.file_info.name.is_empty() {
+fn render_title_bar(file_manager: &FileManager, frame: &mut Vec<String>, terminal_cols: u16) {
+    let doc = file_manager.doc();
+    let filename = if doc.file_info.name.is_empty() {
         "Untitled".to_string()
     } else {
-        file_manager.file_info.name.clone()
+        doc.file_info.name.clone()
     };
 
     //scroll position
-    let buffer_len = file_manager.buffer.data.len();
-    let current_pos = file_manager.pointer.y;
+    let buffer_len = doc.buffer.len_lines();
+    let current_pos = doc.pointer.y;
     let scroll_indicator = if buffer_len > 0 {
         let percentage = (current_pos * 100) / std::cmp::max(1, buffer_len - 1);
         format!(" [{}%]", percentage)
@@ -86,216 +206,810 @@ fn render_title_bar(file_manager: &FileManager, terminal_cols: u16) {
 
     let term_cols_usize = terminal_cols as usize;
 
-    let display_title = format!("{}{}", filename, scroll_indicator);
-
-    let mut title_line = String::new();
-    title_line.push_str(BG_BLUE);
-    title_line.push_str(BRIGHT_WHITE);
-    title_line.push_str(BOLD);
-
-    for _ in 0..term_cols_usize {
-        title_line.push(' ');
+    if file_manager.tabs.len() > 1 {
+        render_tab_strip(file_manager, frame, &scroll_indicator, term_cols_usize);
+        frame.push(format!("{}{}", RESET, "═".repeat(term_cols_usize)));
+        return;
     }
 
-    println!(
-        "{}\r{}{}{}{}",
-        title_line,
+    let modified = if file_manager.is_modified() {
+        " [+]"
+    } else {
+        ""
+    };
+    let display_title = format!("{}{}{}", filename, modified, scroll_indicator);
+
+    frame.push(format!(
+        "{}{}{}{}{}",
         BG_BLUE,
         BRIGHT_WHITE,
         BOLD,
-        center_text(&display_title, term_cols_usize)
-    );
+        fill_width(
+            &center_text(&display_title, term_cols_usize),
+            term_cols_usize
+        ),
+        RESET
+    ));
+
+    frame.push(format!("{}{}", RESET, "═".repeat(term_cols_usize)));
+}
 
-    println!("{}{}", RESET, "═".repeat(term_cols_usize));
+// One label per open document, the active one highlighted. Tabs scroll off
+// to the left when they don't all fit, so the active one is always shown.
+fn render_tab_strip(
+    file_manager: &FileManager,
+    frame: &mut Vec<String>,
+    scroll_indicator: &str,
+    width: usize,
+) {
+    let labels: Vec<String> = (0..file_manager.tabs.len())
+        .map(|i| {
+            let info = file_manager.tab_file_info(i);
+            let modified = if file_manager.tab_modified(i) {
+                " [+]"
+            } else {
+                ""
+            };
+            if i == file_manager.active_tab {
+                format!(" {}{}{} ", info.name, modified, scroll_indicator)
+            } else {
+                format!(" {}{} ", info.name, modified)
+            }
+        })
+        .collect();
+
+    let active = file_manager.active_tab;
+    let mut first = 0;
+    while first < active
+        && labels[first..=active]
+            .iter()
+            .map(|label| text::display_width(label) + 1)
+            .sum::<usize>()
+            > width
+    {
+        first += 1;
+    }
+
+    let mut line = String::new();
+    let mut used = 0;
+    for (i, label) in labels.iter().enumerate().skip(first) {
+        let label = text::truncate_to_width(label, width.saturating_sub(used));
+        if label.is_empty() {
+            break;
+        }
+        if i == active {
+            line.push_str(&format!("{}{}{}{}", BG_BLUE, BRIGHT_WHITE, BOLD, label));
+        } else {
+            line.push_str(&format!("{}{}{}", BG_BLACK, BRIGHT_BLACK, label));
+        }
+        line.push_str(RESET);
+        used += text::display_width(label);
+
+        if used < width {
+            line.push(' ');
+            used += 1;
+        }
+    }
+
+    frame.push(format!("{}{}{}", line, " ".repeat(width - used), RESET));
 }
 
-fn highlight_line(line: &str, extension: &str) -> String {
-    let syntax = match extension {
-        //Cached Lookup for common file types
-        "rs" => SYNTAX_SET.find_syntax_by_extension("rs"),
-        "js" => SYNTAX_SET.find_syntax_by_extension("js"),
-        "py" => SYNTAX_SET.find_syntax_by_extension("py"),
-        "md" => SYNTAX_SET.find_syntax_by_extension("md"),
-        "txt" => Some(SYNTAX_SET.find_syntax_plain_text()),
-        "" => Some(SYNTAX_SET.find_syntax_plain_text()),
-        _ => SYNTAX_SET.find_syntax_by_extension(extension),
+// Highlights for the search matches on buffer line `line`, as byte ranges
+// local to the line
+fn search_overlays(file_manager: &FileManager, line: usize) -> Vec<(Range<usize>, String)> {
+    let search = &file_manager.search;
+    if !search.active || search.matches.is_empty() {
+        return Vec::new();
     }
-    .unwrap_or_else(|| SYNTAX_SET.find_syntax_plain_text());
 
-    let mut highlighter = HighlightLines::new(syntax, &THEME_SET.themes["base16-ocean.dark"]);
+    let line_range = file_manager.doc().buffer.line_range(line);
+    let current = search.current.map(|i| &search.matches[i]);
+
+    search
+        .matches_in(line_range.clone())
+        .iter()
+        .map(|m| {
+            let start = m.start.max(line_range.start) - line_range.start;
+            let end = m.end.min(line_range.end) - line_range.start;
+            let style = if Some(m) == current {
+                format!("{}{}", BG_MAGENTA, BLACK)
+            } else {
+                format!("{}{}", BG_YELLOW, BLACK)
+            };
+            (start..end, style)
+        })
+        .collect()
+}
 
-    //Converts to terminal color codes
-    match highlighter.highlight_line(line, &SYNTAX_SET) {
-        Ok(ranges) => as_24_bit_terminal_escaped(&ranges, false),
-        Err(_) => line.to_string(),
+// Highlight for the part of the selection on buffer line `line`. The flag
+// is set when the selection runs on past the end of the line.
+fn selection_overlay(
+    file_manager: &FileManager,
+    line: usize,
+) -> Option<((Range<usize>, String), bool)> {
+    let selected = file_manager.selection_range()?;
+    let line_range = file_manager.doc().buffer.line_range(line);
+    if selected.start > line_range.end || selected.end < line_range.start {
+        return None;
     }
+
+    let start = selected.start.max(line_range.start) - line_range.start;
+    let end = selected.end.min(line_range.end) - line_range.start;
+    let past_end = selected.end > line_range.end;
+    Some(((start..end, format!("{}{}", BG_BLUE, BLACK)), past_end))
 }
 
-fn render_content(file_manager: &FileManager, terminal_rows: u16, used_lines: u16) {
+// Lay `overlays` (byte ranges into the plain line, with a style) over a
+// syntax highlighted line. The highlighted line has escapes mixed in, so we
+// track our byte offset into the plain text while copying it over. Later
+// overlays win where they overlap.
+fn apply_overlays(highlighted: &str, overlays: &[(Range<usize>, String)]) -> String {
+    if overlays.is_empty() {
+        return highlighted.to_string();
+    }
+
+    let mut result = String::new();
+    let mut byte_index = 0;
+
+    let mut in_escape = false;
+    let mut escape_sequence = String::new();
+    let mut last_style = String::new();
+    let mut active: Option<&str> = None;
+
+    for c in highlighted.chars() {
+        if in_escape {
+            escape_sequence.push(c);
+            if c == 'm' {
+                last_style = std::mem::take(&mut escape_sequence);
+                // Syntax colors would fight with the overlay, hold them back
+                if active.is_none() {
+                    result.push_str(&last_style);
+                }
+                in_escape = false;
+            }
+        } else if c == '\x1b' {
+            escape_sequence.push(c);
+            in_escape = true;
+        } else {
+            let style = overlays
+                .iter()
+                .rev()
+                .find(|(range, _)| range.contains(&byte_index))
+                .map(|(_, style)| style.as_str());
+            if style != active {
+                result.push_str(RESET);
+                result.push_str(style.unwrap_or(&last_style));
+                active = style;
+            }
+            result.push(c);
+            byte_index += c.len_utf8();
+        }
+    }
+
+    if active.is_some() {
+        result.push_str(RESET);
+    }
+    result
+}
+
+// Cut the columns `start..start + width` out of a line with color escapes
+// in it. Escapes are all kept so the colors in view are right, and a wide
+// character cut in half by an edge is replaced with spaces, as are tabs,
+// which reach the next stop of `tab_width` columns. Also says whether text
+// was cut off on the left and on the right.
+fn clip_columns(
+    styled: &str,
+    start: usize,
+    width: usize,
+    tab_width: usize,
+) -> (String, bool, bool) {
+    let end = start + width;
+    let mut result = String::new();
+    let mut column = 0;
+    let mut rest = styled;
+
+    while !rest.is_empty() {
+        if let Some(sequence) = rest.strip_prefix("\x1b[") {
+            let length = sequence
+                .find(|c: char| ('\x40'..='\x7e').contains(&c))
+                .map_or(sequence.len(), |i| i + 1);
+            result.push_str(&rest[..2 + length]);
+            rest = &sequence[length..];
+            continue;
+        }
+
+        let text_end = rest.find('\x1b').unwrap_or(rest.len());
+        for grapheme in rest[..text_end].graphemes(true) {
+            let next = column + text::grapheme_columns(grapheme, column, tab_width);
+            if grapheme == "\t" {
+                let visible = std::cmp::min(next, end).saturating_sub(std::cmp::max(column, start));
+                result.push_str(&" ".repeat(visible));
+            } else if column >= start && next <= end {
+                result.push_str(grapheme);
+            } else if next > start && column < end {
+                // Straddles an edge: show the part that is in view as blanks
+                let visible = std::cmp::min(next, end) - std::cmp::max(column, start);
+                result.push_str(&" ".repeat(visible));
+            }
+            column = next;
+        }
+        rest = &rest[text_end..];
+    }
+
+    (result, start > 0 && column > 0, column > end)
+}
+
+// Replace the first or last visible column of a clipped line with a marker.
+// Its tabs are spaces already.
+fn mark_clipped(content: String, width: usize, left: bool, right: bool) -> String {
+    let marker = |symbol: &str| format!("{}{}{}{}", RESET, BRIGHT_BLACK, symbol, RESET);
+    let mut content = content;
+    if left {
+        let (clipped, _, _) = clip_columns(&content, 1, width.saturating_sub(1), 1);
+        content = format!("{}{}", marker("«"), clipped);
+    }
+    if right {
+        let (clipped, _, _) = clip_columns(&content, 0, width.saturating_sub(1), 1);
+        content = format!("{}{}", clipped, marker("»"));
+    }
+    content
+}
+
+// Rows of wrapped text to show, as a buffer line and the byte range of it
+// on the row. The cursor row is kept in the middle of the screen where the
+// buffer allows, the same as without wrapping.
+fn wrapped_rows(
+    file_manager: &FileManager,
+    max_rows: usize,
+    width: usize,
+) -> Vec<(usize, Range<usize>)> {
+    let doc = file_manager.doc();
+    let buffer = &doc.buffer;
+    let tab_width = file_manager.settings().tab_width;
+    let line_rows = |i: usize| -> Vec<(usize, Range<usize>)> {
+        let rows = text::wrap_rows(&buffer.line(i), width, tab_width);
+        rows.into_iter().map(|row| (i, row)).collect()
+    };
+
+    let y = doc.pointer.y;
+    let mut rows = line_rows(y);
+    let ranges: Vec<_> = rows.iter().map(|(_, row)| row.clone()).collect();
+    let mut cursor = text::row_of(&ranges, doc.pointer.x);
+
+    // Half a screen above the cursor row
+    let half_height = max_rows / 2;
+    let mut above = y;
+    while cursor < half_height && above > 0 {
+        above -= 1;
+        let previous = line_rows(above);
+        cursor += previous.len();
+        rows.splice(0..0, previous);
+    }
+    let mut start = cursor.saturating_sub(half_height);
+
+    // Then as much as fits below it
+    let mut below = y;
+    while rows.len() - start < max_rows && below + 1 < buffer.len_lines() {
+        below += 1;
+        rows.extend(line_rows(below));
+    }
+
+    // Near the end of the buffer, fill the screen from further up
+    while rows.len() - start < max_rows {
+        if start > 0 {
+            start -= 1;
+        } else if above > 0 {
+            above -= 1;
+            let previous = line_rows(above);
+            start += previous.len();
+            rows.splice(0..0, previous);
+        } else {
+            break;
+        }
+    }
+
+    rows.truncate(start + max_rows);
+    rows.drain(..start);
+    rows
+}
+
+fn render_content(
+    file_manager: &FileManager,
+    frame: &mut Vec<String>,
+    terminal_rows: u16,
+    terminal_cols: u16,
+    used_lines: u16,
+) {
+    let doc = file_manager.doc();
     // Calculate max content lines from the passed used_lines
     let max_content_lines = terminal_rows.saturating_sub(used_lines) as usize;
     let max_content_lines = std::cmp::max(1, max_content_lines);
-    let current_line = file_manager.pointer.y;
-    let buffer_line_count = file_manager.buffer.data.len();
+    let current_line = doc.pointer.y;
+    let buffer_line_count = doc.buffer.len_lines();
     if buffer_line_count == 0 {
-        println!("{}     [Empty buffer]{}", DIM, RESET);
+        frame.push(format!("{}     [Empty buffer]{}", DIM, RESET));
         return;
     }
-    let half_height = max_content_lines / 2;
-    let start_line = if current_line > half_height {
-        if current_line + half_height > buffer_line_count {
-            let start = buffer_line_count.saturating_sub(max_content_lines);
-            std::cmp::min(start, buffer_line_count - 1)
-        } else {
-            current_line.saturating_sub(half_height)
-        }
+
+    let gutter = gutter_width(file_manager);
+    let text_width = (terminal_cols as usize).saturating_sub(gutter);
+    let tab_width = file_manager.settings().tab_width;
+
+    // Each row on screen is a buffer line, or part of one when wrapping
+    let rows = if doc.soft_wrap {
+        wrapped_rows(file_manager, max_content_lines, text_width)
     } else {
-        0
+        let half_height = max_content_lines / 2;
+        let start_line = if current_line > half_height {
+            if current_line + half_height > buffer_line_count {
+                let start = buffer_line_count.saturating_sub(max_content_lines);
+                std::cmp::min(start, buffer_line_count - 1)
+            } else {
+                current_line.saturating_sub(half_height)
+            }
+        } else {
+            0
+        };
+        let end_line = std::cmp::min(start_line + max_content_lines, buffer_line_count);
+        (start_line..end_line)
+            .map(|i| (i, doc.buffer.line_range(i)))
+            .collect()
     };
-    let end_line = std::cmp::min(start_line + max_content_lines, buffer_line_count);
 
-    // Get file extension for syntax
-    let extension = file_manager.file_info.name.split('.').last().unwrap_or("");
+    let start_line = rows.first().map_or(0, |(i, _)| *i);
+    let end_line = rows.last().map_or(0, |(i, _)| *i + 1);
 
     //Render the content
-    for i in start_line..end_line {
-        let line = &file_manager.buffer.data[i];
-
-        let is_current_line = i == file_manager.pointer.y;
+    let lines = doc.buffer.lines(start_line..end_line);
+    let mut content = String::new();
+    for (row, (i, range)) in rows.iter().enumerate() {
+        let i = *i;
+        let line = &lines[i - start_line];
+        let is_current_line = i == doc.pointer.y;
+        let first_row = row == 0 || rows[row - 1].0 != i;
+        let last_row = rows.get(row + 1).is_none_or(|(next, _)| *next != i);
+
+        // A whole line is styled once and then cut up into its rows
+        if first_row {
+            content = style_line(file_manager, i, line);
+        }
 
-        let line_num_style = if is_current_line {
+        let line_num_style = if is_current_line && first_row {
             format!("{}{}", BOLD, BRIGHT_CYAN)
         } else {
-            format!("{}", BRIGHT_BLACK)
+            BRIGHT_BLACK.to_string()
+        };
+        let line_number = if first_row {
+            (i + 1).to_string()
+        } else {
+            // Continuation of a wrapped line
+            "↪".to_string()
         };
 
-        let highlighted = highlight_line(line, extension);
-
-        if is_current_line {
-            //Line with the cursor visible
-            let cursor_line = if line.is_empty() {
-                format!("{}{}{}", REVERSE, " ", RESET)
+        let row_content = if doc.soft_wrap {
+            let start = text::byte_to_column(line, range.start, tab_width);
+            // The last row also shows the cursor or selection after the end
+            let width = if last_row {
+                text_width
             } else {
-                let char_count = line.chars().count();
-                //Cursor at the last
-                if file_manager.pointer.x >= char_count {
-                    format!("{}{}{}{}", highlighted, REVERSE, " ", RESET)
-                } else {
-                    // Insert cursor at the right position
-                    let mut result = String::new();
-                    let mut char_index = 0;
-
-                    let mut in_escape = false;
-                    let mut escape_sequence = String::new();
-
-                    for c in highlighted.chars() {
-                        if in_escape {
-                            escape_sequence.push(c);
-                            if c == 'm' {
-                                result.push_str(&escape_sequence);
-                                escape_sequence.clear();
-                                in_escape = false;
-                            }
-                        } else if c == '\x1b' {
-                            escape_sequence.push(c);
-                            in_escape = true;
-                        } else {
-                            if char_index == file_manager.pointer.x {
-                                result.push_str(REVERSE);
-                                result.push(c);
-                                result.push_str(RESET);
-                            } else {
-                                result.push(c);
-                            }
-                            char_index += 1;
-                        }
-                    }
-                    result
-                }
+                text::byte_to_column(line, range.end, tab_width) - start
             };
-
-            println!("{}{:3}{}│ {}", line_num_style, i + 1, RESET, cursor_line);
+            clip_columns(&content, start, width, tab_width).0
         } else {
-            println!(
-                "{}{:3}{}│ {}{}",
-                line_num_style,
-                i + 1,
-                RESET,
-                highlighted,
-                RESET
-            );
+            // Only the part of the line scrolled into view, with markers
+            // where it goes on past the edges
+            let (clipped, left, right) =
+                clip_columns(&content, doc.scroll_x, text_width, tab_width);
+            mark_clipped(clipped, text_width, left, right)
+        };
+
+        if gutter == 0 {
+            frame.push(format!("{}{}", row_content, RESET));
+            continue;
         }
+        frame.push(format!(
+            "{}{:>digits$}{}│ {}{}",
+            line_num_style,
+            line_number,
+            RESET,
+            row_content,
+            RESET,
+            digits = gutter - 2
+        ));
     }
 
-    let visible_lines = (end_line - start_line) as u16;
+    let visible_lines = rows.len() as u16;
     let max_visible_lines = terminal_rows.saturating_sub(used_lines);
     if visible_lines < max_visible_lines {
         let blank_lines = max_visible_lines - visible_lines;
         for _ in 0..blank_lines {
-            println!(" ");
+            frame.push(String::new());
         }
     }
 }
 
-fn render_status_bar(file_manager: &FileManager, terminal_cols: u16) {
-    let current_line = file_manager.pointer.y + 1;
-    let total_lines = file_manager.buffer.data.len();
-    let cursor_pos = format!(
-        "Line: {}/{}, Col: {}",
-        current_line,
-        total_lines,
-        file_manager.pointer.x + 1
+// A binary file as rows of an offset, sixteen bytes in hex and the same
+// bytes as ASCII, with the byte under the cursor picked out in both
+fn render_hex(
+    file_manager: &FileManager,
+    frame: &mut Vec<String>,
+    terminal_rows: u16,
+    terminal_cols: u16,
+    used_lines: u16,
+) {
+    let Some(hex) = &file_manager.doc().hex else {
+        return;
+    };
+    let max_rows = std::cmp::max(1, terminal_rows.saturating_sub(used_lines) as usize);
+    if hex.bytes.is_empty() {
+        frame.push(format!("{}     [Empty file]{}", DIM, RESET));
+    }
+
+    // Keep the cursor's row in the middle where possible
+    let cursor_row = hex.cursor / BYTES_PER_ROW;
+    let start_row = cursor_row
+        .saturating_sub(max_rows / 2)
+        .min(hex.rows().saturating_sub(max_rows));
+    let cursor_style = if hex.editing { BG_YELLOW } else { REVERSE };
+
+    for (row, chunk) in hex
+        .bytes
+        .chunks(BYTES_PER_ROW)
+        .enumerate()
+        .skip(start_row)
+        .take(max_rows)
+    {
+        let offset = row * BYTES_PER_ROW;
+        let mut bytes = String::new();
+        let mut ascii = String::new();
+        for (i, &byte) in chunk.iter().enumerate() {
+            if i == BYTES_PER_ROW / 2 {
+                bytes.push(' ');
+            }
+            let shown = if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            };
+            if offset + i == hex.cursor {
+                bytes.push_str(&format!("{}{:02x}{} ", cursor_style, byte, RESET));
+                ascii.push_str(&format!("{}{}{}", cursor_style, shown, RESET));
+            } else {
+                bytes.push_str(&format!("{:02x} ", byte));
+                ascii.push(shown);
+            }
+        }
+        // A short last row still lines up its ASCII column
+        let missing = BYTES_PER_ROW - chunk.len();
+        bytes.push_str(&"   ".repeat(missing));
+        if chunk.len() <= BYTES_PER_ROW / 2 {
+            bytes.push(' ');
+        }
+
+        let line = format!(
+            "{}{:08x}{}  {} {}│{}{}{}│{}",
+            BRIGHT_BLACK, offset, RESET, bytes, BRIGHT_BLACK, RESET, ascii, BRIGHT_BLACK, RESET
+        );
+        frame.push(format!(
+            "{}{}",
+            // A hex dump has no tabs
+            clip_columns(&line, 0, terminal_cols as usize, 1).0,
+            RESET
+        ));
+    }
+
+    let shown = hex.rows().saturating_sub(start_row).min(max_rows);
+    let shown = if hex.bytes.is_empty() { 1 } else { shown };
+    for _ in shown..max_rows {
+        frame.push(String::new());
+    }
+}
+
+// Rows of the sidebar, each `width` columns wide: the root directory, then
+// the tree with the open file picked out and, while the tree has the
+// focus, the selected row highlighted
+fn render_tree(file_manager: &FileManager, rows: usize, width: usize) -> Vec<String> {
+    let mut lines = Vec::with_capacity(rows);
+    let Some(tree) = &file_manager.tree else {
+        return lines;
+    };
+
+    let root = tree
+        .root
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| tree.root.to_string_lossy().to_string());
+    let header = text::truncate_to_width(&root, width.saturating_sub(1));
+    lines.push(format!(
+        "{}{} {}{}",
+        BOLD,
+        BRIGHT_BLUE,
+        fill_width(header, width - 1),
+        RESET
+    ));
+
+    let open_file = std::fs::canonicalize(&file_manager.doc().file_info.path).ok();
+    let list_rows = rows.saturating_sub(1);
+    let start = tree.pointer.saturating_sub(list_rows.saturating_sub(1));
+    for (i, node) in tree.nodes.iter().enumerate().skip(start).take(list_rows) {
+        let marker = match (node.is_dir, node.expanded) {
+            (true, true) => "▾ ",
+            (true, false) => "▸ ",
+            (false, _) => "  ",
+        };
+        let label = format!("{}{}{}", "  ".repeat(node.depth), marker, node.name);
+        let label = fill_width(text::truncate_to_width(&label, width), width);
+
+        let style = if i == tree.pointer && file_manager.tree_focused {
+            format!("{}{}{}", BG_CYAN, BLACK, BOLD)
+        } else if open_file.as_ref() == Some(&node.path) {
+            format!("{}{}", BOLD, BRIGHT_GREEN)
+        } else if node.is_dir {
+            BRIGHT_CYAN.to_string()
+        } else {
+            WHITE.to_string()
+        };
+        lines.push(format!("{}{}{}", style, label, RESET));
+    }
+
+    while lines.len() < rows {
+        lines.push(" ".repeat(width));
+    }
+    lines
+}
+
+// Buffer line `i` with syntax colors, search matches, the selection and the
+// cursor
+fn style_line(file_manager: &FileManager, i: usize, line: &str) -> String {
+    let doc = file_manager.doc();
+    let is_current_line = i == doc.pointer.y;
+
+    // Lines the highlighter hasn't got to yet are drawn plain
+    let highlighted = doc.highlighter.line(i).unwrap_or(line);
+
+    // Search matches, the selection, then the cursor on top of everything
+    let mut overlays = search_overlays(file_manager, i);
+    let selection = selection_overlay(file_manager, i);
+    let mut newline_selected = false;
+    if let Some((overlay, past_end)) = selection {
+        overlays.push(overlay);
+        newline_selected = past_end;
+    }
+    let cursor_past_end = is_current_line && doc.pointer.x >= line.len();
+    if is_current_line && !cursor_past_end {
+        let cursor_start = doc.pointer.x;
+        let cursor_end = text::next_grapheme(line, cursor_start);
+        overlays.push((cursor_start..cursor_end, REVERSE.to_string()));
+    }
+
+    let mut content = apply_overlays(highlighted, &overlays);
+    if cursor_past_end {
+        //Cursor at the last
+        content.push_str(&format!("{}{}{}", REVERSE, " ", RESET));
+    } else if newline_selected {
+        // Show the selected line break as a highlighted space
+        content.push_str(&format!("{}{} {}", BG_BLUE, BLACK, RESET));
+    }
+    content
+}
+
+fn render_status_bar(file_manager: &FileManager, frame: &mut Vec<String>, terminal_cols: u16) {
+    let doc = file_manager.doc();
+    let current_line = doc.pointer.y + 1;
+    let total_lines = doc.buffer.len_lines();
+    let column = text::byte_to_column(
+        &doc.buffer.line(doc.pointer.y),
+        doc.pointer.x,
+        file_manager.settings().tab_width,
     );
+    let mut cursor_pos = match &doc.hex {
+        Some(hex) => format!(
+            "Hex · {} │ Offset: 0x{:x} / {} bytes",
+            if hex.editing { "editing" } else { "read-only" },
+            hex.cursor,
+            hex.bytes.len()
+        ),
+        None => format!(
+            "{} │ Line: {}/{}{}, Col: {}",
+            doc.format.describe(),
+            current_line,
+            total_lines,
+            // Lines found so far in a large file still being indexed
+            if doc.buffer.is_indexed() { "" } else { "+" },
+            column + 1
+        ),
+    };
+    // Keys of a sequence waiting for the rest of it
+    let pending = file_manager.keymap.pending();
+    if !pending.is_empty() {
+        cursor_pos = format!("{} … │ {}", keymap::format_sequence(pending), cursor_pos);
+    }
 
-    let file_info = format!("File: {}", file_manager.file_info.path);
+    let file_info = format!("File: {}", doc.file_info.path);
 
     let cols_usize = terminal_cols as usize;
     let padding_size = cols_usize
-        .saturating_sub(file_info.len())
-        .saturating_sub(cursor_pos.len())
+        .saturating_sub(text::display_width(&file_info))
+        .saturating_sub(text::display_width(&cursor_pos))
         .saturating_sub(2); // 2 spaces for separation
     let padding = " ".repeat(padding_size);
 
-    println!(
-        "{}{}{}{}{}{}{}",
-        BG_BLACK, BRIGHT_WHITE, file_info, padding, cursor_pos, " ", RESET
-    );
+    frame.push(format!(
+        "{}{}{}{}{} {}",
+        BG_BLACK, BRIGHT_WHITE, file_info, padding, cursor_pos, RESET
+    ));
 }
 
-fn render_input_prompt(file_manager: &FileManager) {
+fn render_input_prompt(file_manager: &FileManager, frame: &mut Vec<String>) {
     if file_manager.input_handler.taking_input {
         let (_, terminal_cols) = get_terminal_size().unwrap_or((24, 80));
         let term_width = terminal_cols as usize;
 
-        let input_text = format!(
-            "{}: {}{}",
-            file_manager.input_handler.input_prompt, file_manager.input_handler.input_buffer, "■"
-        );
+        let action = file_manager.input_handler.action_type;
+        let input_text = if action == InputAction::ConfirmReplace {
+            format!(
+                "{} ({})",
+                file_manager.input_handler.input_prompt,
+                file_manager.search.status()
+            )
+        } else if matches!(
+            action,
+            InputAction::ConfirmClose
+                | InputAction::ConfirmQuit
+                | InputAction::ConfirmDelete
+                | InputAction::RecoverSwap
+        ) {
+            file_manager.input_handler.input_prompt.clone()
+        } else {
+            format!(
+                "{}: {}{}",
+                file_manager.input_handler.input_prompt,
+                file_manager.input_handler.input_buffer,
+                "■"
+            )
+        };
 
-        let display_text = if input_text.len() > term_width - 2 {
-            format!(" {}...", &input_text[..term_width - 6])
+        // Search options and match count after the query
+        let input_text = if matches!(action, InputAction::Find | InputAction::Replace) {
+            let search = &file_manager.search;
+            format!(
+                "{}  {}{}{}",
+                input_text,
+                if search.case_sensitive { "[Aa] " } else { "" },
+                if search.regex { "[.*] " } else { "" },
+                search.status()
+            )
         } else {
-            let padding = term_width
-                .saturating_sub(input_text.len())
-                .saturating_sub(1);
+            input_text
+        };
+
+        let input_width = text::display_width(&input_text);
+        let display_text = if input_width > term_width.saturating_sub(2) {
+            format!(
+                " {}...",
+                text::truncate_to_width(&input_text, term_width.saturating_sub(6))
+            )
+        } else {
+            let padding = term_width.saturating_sub(input_width).saturating_sub(1);
             format!(" {}{}", input_text, " ".repeat(padding))
         };
 
-        let (bg_color, fg_color) = match file_manager.input_handler.action_type {
+        let (bg_color, fg_color) = match action {
             InputAction::SaveAs => (BG_BLUE, BRIGHT_WHITE),
+            InputAction::ConfirmReplace => (BG_MAGENTA, BLACK),
+            InputAction::ConfirmClose | InputAction::ConfirmQuit | InputAction::RecoverSwap => {
+                (BG_YELLOW, BLACK)
+            }
+            InputAction::ConfirmDelete => (BG_RED, BRIGHT_WHITE),
             _ => (BG_CYAN, BRIGHT_WHITE),
         };
 
-        println!("{}{}{}{}{}", bg_color, fg_color, BOLD, display_text, RESET);
+        frame.push(format!(
+            "{}{}{}{}{}",
+            bg_color, fg_color, BOLD, display_text, RESET
+        ));
+
+        let help: &[(&str, &str)] = match action {
+            InputAction::Find | InputAction::Replace => &[
+                ("ESC", "cancel"),
+                ("ENTER", "confirm"),
+                ("↑/↓", "prev/next"),
+                ("Alt+C", "case"),
+                ("Alt+R", "regex"),
+            ],
+            InputAction::ConfirmReplace => &[
+                ("y", "replace"),
+                ("n", "skip"),
+                ("a", "replace all"),
+                ("q/ESC", "stop"),
+            ],
+            InputAction::ConfirmClose | InputAction::ConfirmQuit => {
+                &[("y", "save"), ("n", "discard"), ("ESC", "cancel")]
+            }
+            InputAction::CommandPalette => {
+                &[("ESC", "cancel"), ("ENTER", "run"), ("↑/↓", "select")]
+            }
+            InputAction::ConfirmDelete => &[("y", "delete"), ("n/ESC", "cancel")],
+            InputAction::RecoverSwap => &[
+                ("r", "recover"),
+                ("d", "discard"),
+                ("v", "diff"),
+                ("ESC", "decide later"),
+            ],
+            InputAction::FileFormat => {
+                &[("ESC", "cancel"), ("ENTER", "convert"), ("↑/↓", "select")]
+            }
+            InputAction::FindFile => &[("ESC", "cancel"), ("ENTER", "open"), ("↑/↓", "select")],
+            _ => &[("ESC", "cancel"), ("ENTER", "confirm")],
+        };
+
+        let help_text: Vec<String> = help
+            .iter()
+            .map(|(key, label)| format!("{}{}{}{} {}", BOLD, BRIGHT_WHITE, key, RESET, label))
+            .collect();
+        frame.push(format!(
+            "{}{}{}",
+            BRIGHT_BLACK,
+            help_text.join(" │ "),
+            RESET
+        ));
+    }
+}
 
-        println!(
-            "{}{}ESC{} cancel │ {}{}ENTER{} confirm{}",
-            BRIGHT_BLACK, BOLD, RESET, BOLD, BRIGHT_WHITE, RESET, BRIGHT_BLACK
-        );
+// The entries of a picker, matched characters highlighted and each
+// entry's detail against the right edge
+fn render_picker(picker: &Picker, frame: &mut Vec<String>, rows: usize, cols: usize) {
+    let rows = std::cmp::max(1, rows);
+    if picker.matches.is_empty() {
+        frame.push(format!("{}     [No matches]{}", DIM, RESET));
+        for _ in 1..rows {
+            frame.push(String::new());
+        }
+        return;
+    }
+
+    // Keep the selected entry on screen
+    let start = picker.selected.saturating_sub(rows - 1);
+    for (i, found) in picker.matches.iter().enumerate().skip(start).take(rows) {
+        let item = &picker.items[found.item];
+        let selected = i == picker.selected;
+        let (base, highlight) = if selected {
+            (format!("{}{}{}", BG_CYAN, BLACK, BOLD), BRIGHT_WHITE)
+        } else {
+            (RESET.to_string(), BRIGHT_YELLOW)
+        };
+
+        let detail_width = text::display_width(&item.detail);
+        let label_room = cols.saturating_sub(detail_width + 5);
+        let label = text::truncate_to_width(&item.label, label_room);
+        let label_width = text::display_width(label);
+
+        let mut line = format!("{}{}", base, if selected { "► " } else { "  " });
+        for (at, grapheme) in label.grapheme_indices(true) {
+            if found.positions.contains(&at) {
+                line.push_str(&format!("{}{}{}{}", highlight, BOLD, grapheme, RESET));
+                line.push_str(&base);
+            } else {
+                line.push_str(grapheme);
+            }
+        }
+
+        let padding = cols.saturating_sub(label_width + detail_width + 3);
+        line.push_str(&" ".repeat(padding));
+        if !selected {
+            line.push_str(BRIGHT_BLACK);
+        }
+        line.push_str(&format!("{} {}", item.detail, RESET));
+        frame.push(line);
+    }
+
+    let shown = picker.matches.len().saturating_sub(start).min(rows);
+    for _ in shown..rows {
+        frame.push(String::new());
     }
 }
 
-fn render_toasts(file_manager: &FileManager) {
+fn render_toasts(file_manager: &FileManager, frame: &mut Vec<String>) {
     if file_manager.toasts.is_empty() {
         return;
     }
@@ -321,157 +1035,224 @@ fn render_toasts(file_manager: &FileManager) {
 
         // Left-align the toast with some padding
         let padding_needed = term_width
-            .saturating_sub(message_text.len())
+            .saturating_sub(text::display_width(&message_text))
             .saturating_sub(2);
         let toast_line = format!(" {}{}", message_text, " ".repeat(padding_needed + 1));
 
-        println!("{}{}{}{}", bg_color, fg_color, toast_line, RESET);
+        frame.push(format!("{}{}{}{}", bg_color, fg_color, toast_line, RESET));
     }
 }
 
-fn render_footer() {
-    let shortcuts = [
-        ("Ctrl+S", "Save"),
-        ("Alt+S", "Save As"),
-        ("Ctrl+Q", "Exit"),
-        ("ESC", "Cancel Input"),
-        ("↑/↓/←/→", "Navigate"),
-    ];
-
-    print!("{}", BRIGHT_BLACK);
-
-    for (i, (key, action)) in shortcuts.iter().enumerate() {
-        if i > 0 {
-            print!(" │ ");
+// Commands listed in the footer, as far as they fit
+const FOOTER_COMMANDS: &[&str] = &["save", "save_as", "open_browser", "find", "quit"];
+
+// The footer shows whatever keys the commands are bound to right now
+fn render_footer(file_manager: &FileManager, frame: &mut Vec<String>, terminal_cols: u16) {
+    let mut line = BRIGHT_BLACK.to_string();
+    let mut used = 0;
+
+    let shortcuts = FOOTER_COMMANDS.iter().filter_map(|name| {
+        let command = commands::find(name)?;
+        let key = file_manager.keymap.binding_for(name)?;
+        Some((key, command.title))
+    });
+    for (i, (key, action)) in shortcuts.enumerate() {
+        let separator = if i > 0 { " │ " } else { "" };
+        let width = text::display_width(separator) + text::display_width(&key) + 1 + action.len();
+        if used + width > terminal_cols as usize {
+            break;
         }
-        print!("{}{}{}{} {}", BRIGHT_WHITE, BOLD, key, RESET, action);
+        used += width;
+
+        line.push_str(separator);
+        line.push_str(&format!(
+            "{}{}{}{} {}",
+            BRIGHT_WHITE, BOLD, key, RESET, action
+        ));
     }
 
-    println!("{}", RESET);
+    line.push_str(RESET);
+    frame.push(line);
 }
 
-pub fn render_browser(fm: &FileManager) {
-    clear_screen();
-
+fn render_browser(fm: &FileManager, frame: &mut Vec<String>) {
     let (terminal_rows, terminal_cols) = get_terminal_size().unwrap_or((24, 80));
     let term_cols_usize = terminal_cols as usize;
 
     // Title bar for file browser
-    let mut title_line = String::new();
-    title_line.push_str(BG_BLUE);
-    title_line.push_str(BRIGHT_WHITE);
-    title_line.push_str(BOLD);
-
-    for _ in 0..term_cols_usize {
-        title_line.push(' ');
-    }
-
-    println!(
-        "{}\r{}{}{}{}",
-        title_line,
+    frame.push(format!(
+        "{}{}{}{}{}",
         BG_BLUE,
         BRIGHT_WHITE,
         BOLD,
-        center_text("📁 File Browser", term_cols_usize)
-    );
-
-    println!("{}{}", RESET, "═".repeat(term_cols_usize));
-
+        fill_width(
+            &center_text("📁 File Browser", term_cols_usize),
+            term_cols_usize
+        ),
+        RESET
+    ));
+
+    frame.push(format!("{}{}", RESET, "═".repeat(term_cols_usize)));
+    frame.push(format!(
+        "{}{}{}",
+        BRIGHT_CYAN,
+        breadcrumb(&fm.file_browser.dir, term_cols_usize),
+        RESET
+    ));
+
+    let filter = &fm.file_browser.filter;
     match &fm.file_browser.paths {
         Some(files) => {
             if files.is_empty() {
-                println!("{}     [Empty directory]{}", DIM, RESET);
-                println!();
-                println!("{}{}ESC{} to go back", BRIGHT_BLACK, BOLD, RESET);
-                return;
-            }
-
-            // Calculate available space for file list
-            let used_lines = 6; // title (2) + separator (1) + help (3)
-            let max_files = (terminal_rows as usize).saturating_sub(used_lines);
-
-            // Calculate scroll position
-            let current_pos = fm.file_browser.pointer;
-            let start_index = if current_pos >= max_files {
-                current_pos.saturating_sub(max_files / 2)
-            } else {
-                0
-            };
-            let end_index = std::cmp::min(start_index + max_files, files.len());
-
-            // Show scroll indicator if needed
-            if files.len() > max_files {
-                let percentage = (current_pos * 100) / std::cmp::max(1, files.len() - 1);
-                println!(
-                    "{}Showing {}-{} of {} files [{}%]{}",
-                    DIM,
-                    start_index + 1,
-                    end_index,
-                    files.len(),
-                    percentage,
-                    RESET
-                );
+                if filter.is_empty() {
+                    frame.push(format!("{}     [Empty directory]{}", DIM, RESET));
+                } else {
+                    frame.push(format!(
+                        "{}     [No matches for \"{}\"]{}",
+                        DIM, filter, RESET
+                    ));
+                }
+                // Title (2) + breadcrumb (1) + message (1) + help (2)
+                for _ in 0..(terminal_rows as usize).saturating_sub(6) {
+                    frame.push(String::new());
+                }
             } else {
-                println!("{}Showing {} files{}", DIM, files.len(), RESET);
-            }
-
-            for i in start_index..end_index {
-                let entry = &files[i];
-                let is_selected = i == fm.file_browser.pointer;
-                let is_dir = entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false);
-                let file_name = entry.file_name().to_string_lossy().to_string();
-
-                let (icon, name_color) = if is_dir {
-                    ("📁", BRIGHT_CYAN)
+                // Calculate available space for file list
+                let used_lines = 7; // title (2) + breadcrumb (1) + separator (1) + help (3)
+                let max_files = (terminal_rows as usize).saturating_sub(used_lines);
+
+                // Calculate scroll position
+                let current_pos = fm.file_browser.pointer;
+                let start_index = if current_pos >= max_files {
+                    current_pos.saturating_sub(max_files / 2)
                 } else {
-                    let ext = file_name.split('.').last().unwrap_or("");
-                    match ext {
-                        "rs" => ("🦀", BRIGHT_YELLOW),
-                        "js" | "ts" => ("📜", BRIGHT_YELLOW),
-                        "py" => ("🐍", BRIGHT_GREEN),
-                        "md" => ("📝", BRIGHT_WHITE),
-                        "txt" => ("📄", BRIGHT_WHITE),
-                        "json" => ("⚙️", BRIGHT_MAGENTA),
-                        "toml" | "yaml" | "yml" => ("⚙️", BRIGHT_BLUE),
-                        _ => ("📄", BRIGHT_WHITE),
-                    }
+                    0
                 };
-
-                if is_selected {
-                    print!("{}{}{}", BG_CYAN, BLACK, BOLD);
-                    let padding = term_cols_usize.saturating_sub(file_name.len() + 4);
-                    println!("► {} {}{}{}", icon, file_name, " ".repeat(padding), RESET);
+                let end_index = std::cmp::min(start_index + max_files, files.len());
+
+                // Show scroll indicator if needed
+                let showing = if files.len() > max_files {
+                    let percentage = (current_pos * 100) / std::cmp::max(1, files.len() - 1);
+                    format!(
+                        "Showing {}-{} of {} files [{}%]",
+                        start_index + 1,
+                        end_index,
+                        files.len(),
+                        percentage
+                    )
                 } else {
-                    println!("  {}{} {}{}", icon, name_color, file_name, RESET);
+                    format!("Showing {} files", files.len())
+                };
+                if filter.is_empty() {
+                    frame.push(format!("{}{}{}", DIM, showing, RESET));
+                } else {
+                    frame.push(format!(
+                        "{}{}Filter:{} {}■  {}{}{}",
+                        BRIGHT_WHITE, BOLD, RESET, filter, DIM, showing, RESET
+                    ));
                 }
-            }
 
-            let shown_files = end_index - start_index;
-            let remaining_lines = max_files.saturating_sub(shown_files);
-            for _ in 0..remaining_lines {
-                println!();
+                for (i, entry) in files.iter().enumerate().take(end_index).skip(start_index) {
+                    let is_selected = i == fm.file_browser.pointer;
+                    let is_dir = entry.is_dir;
+                    let file_name = &entry.name;
+
+                    let (icon, name_color) = if is_dir {
+                        ("📁", BRIGHT_CYAN)
+                    } else {
+                        let ext = file_name.split('.').next_back().unwrap_or("");
+                        match ext {
+                            "rs" => ("🦀", BRIGHT_YELLOW),
+                            "js" | "ts" => ("📜", BRIGHT_YELLOW),
+                            "py" => ("🐍", BRIGHT_GREEN),
+                            "md" => ("📝", BRIGHT_WHITE),
+                            "txt" => ("📄", BRIGHT_WHITE),
+                            "json" => ("⚙️", BRIGHT_MAGENTA),
+                            "toml" | "yaml" | "yml" => ("⚙️", BRIGHT_BLUE),
+                            _ => ("📄", BRIGHT_WHITE),
+                        }
+                    };
+
+                    if is_selected {
+                        let used = text::display_width(icon) + text::display_width(file_name) + 3;
+                        let padding = term_cols_usize.saturating_sub(used);
+                        frame.push(format!(
+                            "{}{}{}► {} {}{}{}",
+                            BG_CYAN,
+                            BLACK,
+                            BOLD,
+                            icon,
+                            file_name,
+                            " ".repeat(padding),
+                            RESET
+                        ));
+                    } else {
+                        frame.push(format!("  {}{} {}{}", icon, name_color, file_name, RESET));
+                    }
+                }
+
+                let shown_files = end_index - start_index;
+                let remaining_lines = max_files.saturating_sub(shown_files);
+                for _ in 0..remaining_lines {
+                    frame.push(String::new());
+                }
             }
         }
         None => {
-            println!("{}     [No files found]{}", DIM, RESET);
-            println!();
+            frame.push(format!("{}     [No files found]{}", DIM, RESET));
+            frame.push(String::new());
         }
     }
 
-    println!("{}{}", RESET, "─".repeat(term_cols_usize));
-    println!(
-        "{}{}↑/↓{} Navigate │ {}{}ENTER{} Open/Select │ {}{}ESC{} Close Browser{}",
+    // Prompts for file operations and their results replace the help
+    if fm.input_handler.taking_input {
+        render_input_prompt(fm, frame);
+        return;
+    }
+    if fm.toasts.is_empty() {
+        frame.push(format!("{}{}", RESET, "─".repeat(term_cols_usize)));
+    } else {
+        render_toasts(fm, frame);
+    }
+    let help = [
+        ("ENTER", "Open"),
+        ("BKSP", "Up"),
+        ("Alt+N/M", "New file/dir"),
+        ("F2", "Rename"),
+        ("Alt+C", "Copy"),
+        ("DEL", "Delete"),
+        ("Alt+H", "Hidden"),
+        ("ESC", "Close"),
+    ];
+    let help_text: Vec<String> = help
+        .iter()
+        .map(|(key, label)| format!("{}{}{}{} {}", BOLD, BRIGHT_WHITE, key, RESET, label))
+        .collect();
+    frame.push(format!(
+        "{}{} │ type to filter{}",
         BRIGHT_BLACK,
-        BOLD,
-        RESET,
-        BOLD,
-        BRIGHT_WHITE,
-        RESET,
-        BOLD,
-        BRIGHT_WHITE,
-        RESET,
-        BRIGHT_BLACK
-    );
+        help_text.join(" │ "),
+        RESET
+    ));
+}
 
-    stdout().flush().expect("Failed to flush stdout");
+// "~ › src › renderer", cut from the left when it doesn't fit
+fn breadcrumb(dir: &std::path::Path, width: usize) -> String {
+    let home = std::env::var_os("HOME").map(std::path::PathBuf::from);
+    let (root, rest) = match home.as_deref().and_then(|home| dir.strip_prefix(home).ok()) {
+        Some(rest) => ("~".to_string(), rest),
+        None => ("/".to_string(), dir.strip_prefix("/").unwrap_or(dir)),
+    };
+
+    let mut parts = vec![root];
+    parts.extend(
+        rest.components()
+            .map(|part| part.as_os_str().to_string_lossy().to_string()),
+    );
+    let mut crumbs = format!("📂 {}", parts.join(" › "));
+    while text::display_width(&crumbs) > width && parts.len() > 1 {
+        parts.remove(0);
+        crumbs = format!("📂 … › {}", parts.join(" › "));
+    }
+    text::truncate_to_width(&crumbs, width).to_string()
 }
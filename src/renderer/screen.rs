@@ -0,0 +1,255 @@
+use std::fmt::Write as _;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use nox_editor::text;
+
+// A frame as a grid of styled cells. Render functions still produce rows
+// of text with SGR escapes; they are parsed into cells here so the frame
+// can be compared with the one before it and only the cells that changed
+// are sent to the terminal.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Color {
+    Default,
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+const BOLD: u8 = 1;
+const DIM: u8 = 2;
+const ITALIC: u8 = 4;
+const UNDERLINE: u8 = 8;
+const BLINK: u8 = 16;
+const REVERSE: u8 = 32;
+const HIDDEN: u8 = 64;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Style {
+    fg: Color,
+    bg: Color,
+    attrs: u8,
+}
+
+impl Style {
+    const DEFAULT: Style = Style {
+        fg: Color::Default,
+        bg: Color::Default,
+        attrs: 0,
+    };
+
+    // Update the style with the parameters of one `ESC [ ... m` sequence
+    fn apply_sgr(&mut self, params: &str) {
+        let params: Vec<u16> = params.split(';').map(|p| p.parse().unwrap_or(0)).collect();
+
+        let mut i = 0;
+        while i < params.len() {
+            match params[i] {
+                0 => *self = Style::DEFAULT,
+                1 => self.attrs |= BOLD,
+                2 => self.attrs |= DIM,
+                3 => self.attrs |= ITALIC,
+                4 => self.attrs |= UNDERLINE,
+                5 => self.attrs |= BLINK,
+                7 => self.attrs |= REVERSE,
+                8 => self.attrs |= HIDDEN,
+                22 => self.attrs &= !(BOLD | DIM),
+                23 => self.attrs &= !ITALIC,
+                24 => self.attrs &= !UNDERLINE,
+                25 => self.attrs &= !BLINK,
+                27 => self.attrs &= !REVERSE,
+                28 => self.attrs &= !HIDDEN,
+                n @ 30..=37 => self.fg = Color::Indexed((n - 30) as u8),
+                n @ 90..=97 => self.fg = Color::Indexed((n - 90 + 8) as u8),
+                39 => self.fg = Color::Default,
+                n @ 40..=47 => self.bg = Color::Indexed((n - 40) as u8),
+                n @ 100..=107 => self.bg = Color::Indexed((n - 100 + 8) as u8),
+                49 => self.bg = Color::Default,
+                n @ (38 | 48) => {
+                    let (color, used) = parse_extended_color(&params[i + 1..]);
+                    if n == 38 {
+                        self.fg = color;
+                    } else {
+                        self.bg = color;
+                    }
+                    i += used;
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+
+    // The escape sequence that switches the terminal to this style from
+    // any other
+    fn sgr(&self) -> String {
+        let mut sgr = String::from("\x1b[0");
+        let names = [
+            (BOLD, 1),
+            (DIM, 2),
+            (ITALIC, 3),
+            (UNDERLINE, 4),
+            (BLINK, 5),
+            (REVERSE, 7),
+            (HIDDEN, 8),
+        ];
+        for (flag, code) in names {
+            if self.attrs & flag != 0 {
+                let _ = write!(sgr, ";{}", code);
+            }
+        }
+        for (color, base) in [(self.fg, 38), (self.bg, 48)] {
+            match color {
+                Color::Default => {}
+                Color::Indexed(n) => {
+                    let _ = write!(sgr, ";{};5;{}", base, n);
+                }
+                Color::Rgb(r, g, b) => {
+                    let _ = write!(sgr, ";{};2;{};{};{}", base, r, g, b);
+                }
+            }
+        }
+        sgr.push('m');
+        sgr
+    }
+}
+
+// `5;n` or `2;r;g;b` after a 38 or 48, with the number of parameters used
+fn parse_extended_color(params: &[u16]) -> (Color, usize) {
+    let byte = |i: usize| params.get(i).copied().unwrap_or(0) as u8;
+    match params.first() {
+        Some(5) => (Color::Indexed(byte(1)), 2),
+        Some(2) => (Color::Rgb(byte(1), byte(2), byte(3)), 4),
+        _ => (Color::Default, 0),
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Cell {
+    // One grapheme, or empty for the second column of a wide one
+    text: String,
+    style: Style,
+}
+
+impl Cell {
+    fn blank() -> Self {
+        Cell {
+            text: " ".to_string(),
+            style: Style::DEFAULT,
+        }
+    }
+
+    fn is_continuation(&self) -> bool {
+        self.text.is_empty()
+    }
+}
+
+pub struct Screen {
+    rows: usize,
+    cols: usize,
+    cells: Vec<Cell>,
+}
+
+impl Screen {
+    // Lay out `lines` one per row. Text past the right edge is cut off and
+    // rows without a line stay blank.
+    pub fn from_lines(lines: &[String], rows: usize, cols: usize) -> Self {
+        let mut screen = Screen {
+            rows,
+            cols,
+            cells: vec![Cell::blank(); rows * cols],
+        };
+        for (row, line) in lines.iter().take(rows).enumerate() {
+            screen.put_line(row, line);
+        }
+        screen
+    }
+
+    fn put_line(&mut self, row: usize, line: &str) {
+        let mut style = Style::DEFAULT;
+        let mut col = 0;
+        let mut rest = line;
+
+        while !rest.is_empty() && col < self.cols {
+            if let Some(sequence) = rest.strip_prefix("\x1b[") {
+                let end = sequence
+                    .find(|c: char| ('\x40'..='\x7e').contains(&c))
+                    .unwrap_or(sequence.len().saturating_sub(1));
+                if sequence[end..].starts_with('m') {
+                    style.apply_sgr(&sequence[..end]);
+                }
+                rest = &sequence[std::cmp::min(end + 1, sequence.len())..];
+                continue;
+            }
+
+            let mut grapheme = rest.graphemes(true).next().unwrap_or(rest);
+            rest = &rest[grapheme.len()..];
+            // Control characters take one blank column, the same as the
+            // cursor math counts them. Tabs in the text are spaces already.
+            if grapheme.chars().all(char::is_control) {
+                grapheme = " ";
+            }
+
+            let width = text::grapheme_width(grapheme);
+            if col + width > self.cols {
+                break;
+            }
+            self.cells[row * self.cols + col] = Cell {
+                text: grapheme.to_string(),
+                style,
+            };
+            for extra in 1..width {
+                self.cells[row * self.cols + col + extra] = Cell {
+                    text: String::new(),
+                    style,
+                };
+            }
+            col += width;
+        }
+    }
+
+    // Escape sequences that turn `previous` into this frame on the terminal.
+    // Without a previous frame of the same size everything is drawn.
+    pub fn diff(&self, previous: Option<&Screen>) -> String {
+        let previous = previous.filter(|p| p.rows == self.rows && p.cols == self.cols);
+        let mut out = String::new();
+        if previous.is_none() {
+            out.push_str("\x1b[0m\x1b[2J");
+        }
+
+        let mut style: Option<Style> = None;
+        for row in 0..self.rows {
+            // Where the terminal cursor is on this row, if we know
+            let mut cursor: Option<usize> = None;
+            for col in 0..self.cols {
+                let index = row * self.cols + col;
+                let cell = &self.cells[index];
+                let changed = previous.is_none_or(|p| {
+                    p.cells[index] != *cell
+                        // Redraw a wide character when what followed it changed
+                        || self.cells.get(index + 1).is_some_and(|next| {
+                            next.is_continuation() && p.cells[index + 1] != *next
+                        })
+                });
+                if !changed || cell.is_continuation() {
+                    continue;
+                }
+
+                if cursor != Some(col) {
+                    let _ = write!(out, "\x1b[{};{}H", row + 1, col + 1);
+                }
+                if style != Some(cell.style) {
+                    out.push_str(&cell.style.sgr());
+                    style = Some(cell.style);
+                }
+                out.push_str(&cell.text);
+                cursor = Some(col + text::grapheme_width(&cell.text));
+            }
+        }
+
+        if style.is_some() {
+            out.push_str("\x1b[0m");
+        }
+        out
+    }
+}
@@ -0,0 +1,242 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+// Writing a file so that a crash or a full disk halfway through never
+// leaves it truncated: the new contents go to a temporary file next to it,
+// which is synced and then renamed over the original in one step.
+
+// Replace the file at `path` with `contents`. A symlink is followed and the
+// file it points to replaced, keeping its permissions and, where allowed,
+// its owner. With `backup` the previous version is kept as `<name>~`.
+pub fn write_file(path: &Path, contents: &[u8], backup: bool) -> io::Result<()> {
+    write_file_with(path, backup, |out| out.write_all(contents))
+}
+
+// `write_file` with the contents written to the file by `write` as they are
+// produced, rather than built up in memory first
+pub fn write_file_with(
+    path: &Path,
+    backup: bool,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<()> {
+    let target = resolve_symlinks(path)?;
+    let existing = fs::metadata(&target).ok();
+
+    if backup && existing.is_some() {
+        let mut backup_path = target.clone().into_os_string();
+        backup_path.push("~");
+        fs::copy(&target, &backup_path)?;
+    }
+    let (mode, owner) = match &existing {
+        Some(metadata) => (Some(metadata.mode() & 0o7777), Some(metadata)),
+        None => (None, None),
+    };
+    replace(&target, write, mode, owner)
+}
+
+// Replace the file at `path` with `contents` readable and writable as `mode`
+// says, whatever permissions it had before
+pub fn write_file_with_mode(path: &Path, contents: &[u8], mode: u32) -> io::Result<()> {
+    let target = resolve_symlinks(path)?;
+    replace(&target, |out| out.write_all(contents), Some(mode), None)
+}
+
+// Write `target` through a temporary file with `mode`, or the umask
+// default, given to `owner` when there is one
+fn replace(
+    target: &Path,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+    mode: Option<u32>,
+    owner: Option<&fs::Metadata>,
+) -> io::Result<()> {
+    let dir = match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name = target
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file name"))?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(format!(".nox-{}.tmp", std::process::id()));
+    let temp = dir.join(temp_name);
+
+    let result = write_temp(&temp, write, mode, owner).and_then(|_| fs::rename(&temp, target));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+        return result;
+    }
+
+    // Make the rename itself durable; not every filesystem allows syncing
+    // a directory, so failing here isn't an error
+    if let Ok(dir) = File::open(&dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+fn write_temp(
+    temp: &Path,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+    mode: Option<u32>,
+    owner: Option<&fs::Metadata>,
+) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    if let Some(mode) = mode {
+        options.mode(mode);
+    }
+    let mut file = options.open(temp)?;
+
+    if let Some(metadata) = owner {
+        // Only root can give files away, so this is best effort
+        let _ = std::os::unix::fs::fchown(&file, Some(metadata.uid()), Some(metadata.gid()));
+    }
+    if let Some(mode) = mode {
+        // The mode passed to open is masked by the umask
+        file.set_permissions(fs::Permissions::from_mode(mode))?;
+    }
+
+    let mut out = BufWriter::new(&mut file);
+    write(&mut out)?;
+    out.flush()?;
+    drop(out);
+    file.sync_all()
+}
+
+// The real file behind `path`, which may not exist yet. A dangling symlink
+// resolves to the file it would point to.
+fn resolve_symlinks(path: &Path) -> io::Result<PathBuf> {
+    let mut path = path.to_path_buf();
+    // Give up on loops the way the kernel does
+    for _ in 0..40 {
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let link = fs::read_link(&path)?;
+                path = match path.parent() {
+                    Some(dir) => dir.join(link),
+                    None => link,
+                };
+            }
+            _ => return Ok(path),
+        }
+    }
+    Err(io::Error::other("too many levels of symbolic links"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An empty directory of its own for each test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nox-save-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().mode() & 0o7777
+    }
+
+    #[test]
+    fn replaces_contents_without_leaving_a_temp_file() {
+        let dir = temp_dir("replace");
+        let file = dir.join("file.txt");
+        write_file(&file, b"first", false).unwrap();
+        write_file(&file, b"second", false).unwrap();
+        assert_eq!(fs::read(&file).unwrap(), b"second");
+        assert_eq!(entries(&dir), ["file.txt"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_permissions() {
+        let dir = temp_dir("permissions");
+        let file = dir.join("script.sh");
+        fs::write(&file, "old").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o751)).unwrap();
+        write_file(&file, b"new", false).unwrap();
+        assert_eq!(mode(&file), 0o751);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backup_keeps_the_previous_version() {
+        let dir = temp_dir("backup");
+        let file = dir.join("file.txt");
+        // Nothing to back up for a new file
+        write_file(&file, b"one", true).unwrap();
+        assert_eq!(entries(&dir), ["file.txt"]);
+
+        write_file(&file, b"two", true).unwrap();
+        assert_eq!(fs::read(&file).unwrap(), b"two");
+        assert_eq!(fs::read(dir.join("file.txt~")).unwrap(), b"one");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn symlinks_are_followed() {
+        let dir = temp_dir("symlink");
+        let file = dir.join("real.txt");
+        let link = dir.join("link.txt");
+        fs::write(&file, "old").unwrap();
+        std::os::unix::fs::symlink("real.txt", &link).unwrap();
+        write_file(&link, b"new", false).unwrap();
+        assert!(
+            fs::symlink_metadata(&link)
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+        assert_eq!(fs::read(&file).unwrap(), b"new");
+
+        // A dangling link creates the file it points to
+        let dangling = dir.join("dangling.txt");
+        std::os::unix::fs::symlink("missing.txt", &dangling).unwrap();
+        write_file(&dangling, b"created", false).unwrap();
+        assert_eq!(fs::read(dir.join("missing.txt")).unwrap(), b"created");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_write_keeps_the_original() {
+        let dir = temp_dir("failed");
+        let file = dir.join("file.txt");
+        fs::write(&file, "original").unwrap();
+        let result = write_file_with(&file, false, |out| {
+            out.write_all(b"partial")?;
+            Err(io::Error::other("disk full"))
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read(&file).unwrap(), b"original");
+        assert_eq!(entries(&dir), ["file.txt"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mode_is_set_whatever_it_was() {
+        let dir = temp_dir("mode");
+        let file = dir.join("file.txt");
+        write_file_with_mode(&file, b"secret", 0o600).unwrap();
+        assert_eq!(mode(&file), 0o600);
+
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+        write_file_with_mode(&file, b"still secret", 0o600).unwrap();
+        assert_eq!(mode(&file), 0o600);
+        assert_eq!(fs::read(&file).unwrap(), b"still secret");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,395 @@
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::{Buffer, Pointer};
+
+// State of the find / replace prompt. Matches are byte ranges into the
+// buffer and are recomputed whenever the query or the options change. An
+// edit only has the lines it touched searched again.
+pub struct Search {
+    pub active: bool,
+    pub query: String,
+    pub replacement: String,
+    pub case_sensitive: bool,
+    pub regex: bool,
+    pub matches: Vec<Range<usize>>,
+    // Index into `matches` of the match the cursor is on
+    pub current: Option<usize>,
+    // Where the cursor was when the search started, restored on cancel
+    pub origin: Pointer,
+    // Set when the query is not a valid regex
+    pub error: Option<String>,
+    // Replacements made since the search started
+    pub replaced: usize,
+    pattern: Option<Regex>,
+    // The query can't match across a line break, so an edit only changes
+    // matches on the lines it touched
+    line_bound: bool,
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Search {
+    pub fn new() -> Self {
+        Search {
+            active: false,
+            query: String::new(),
+            replacement: String::new(),
+            case_sensitive: false,
+            regex: false,
+            matches: Vec::new(),
+            current: None,
+            origin: Pointer::new(0, 0),
+            error: None,
+            replaced: 0,
+            pattern: None,
+            line_bound: true,
+        }
+    }
+
+    fn compile(&mut self) {
+        self.pattern = None;
+        self.error = None;
+        if self.query.is_empty() {
+            return;
+        }
+
+        // Escapes, classes and flags are what let a regex take in a line
+        // break; other queries only do if they hold one
+        let may_span =
+            self.regex && (self.query.contains(['\\', '[']) || self.query.contains("(?"));
+        self.line_bound = !may_span && !self.query.contains('\n');
+        let source = if self.regex {
+            self.query.clone()
+        } else {
+            regex::escape(&self.query)
+        };
+
+        match RegexBuilder::new(&source)
+            .case_insensitive(!self.case_sensitive)
+            .multi_line(true)
+            .build()
+        {
+            Ok(pattern) => self.pattern = Some(pattern),
+            Err(e) => {
+                // Only the last line of the error says what's wrong
+                let message = e.to_string();
+                let reason = message.lines().last().unwrap_or("invalid regex").trim();
+                self.error = Some(reason.trim_start_matches("error: ").to_string());
+            }
+        }
+    }
+
+    // Recompile the query and collect every match in `buffer`
+    pub fn update(&mut self, buffer: &Buffer) {
+        self.compile();
+        self.refresh(buffer);
+    }
+
+    // Collect every match again, e.g. after undo changed the buffer
+    pub fn refresh(&mut self, buffer: &Buffer) {
+        self.matches.clear();
+        self.current = None;
+
+        if let Some(pattern) = &self.pattern {
+            let text = buffer.contents();
+            self.matches = pattern
+                .find_iter(&text)
+                .filter(|m| !m.is_empty())
+                .map(|m| m.range())
+                .collect();
+        }
+    }
+
+    // Bring the matches up to date after `removed` bytes at `at` were
+    // replaced with `inserted` bytes. Matches touching the change are looked
+    // for again along with the rest of the lines they are on; those after
+    // it just move.
+    pub fn edited(&mut self, buffer: &Buffer, at: usize, removed: usize, inserted: usize) {
+        if !self.line_bound {
+            self.refresh(buffer);
+            return;
+        }
+        self.current = None;
+        let Some(pattern) = &self.pattern else {
+            return;
+        };
+
+        // The lines to search again, in the buffer before the edit
+        let mut start = at;
+        let mut end = at + removed;
+        let first = self.matches.partition_point(|m| m.end < start);
+        let last = self.matches.partition_point(|m| m.start <= end);
+        if first < last {
+            start = start.min(self.matches[first].start);
+            end = end.max(self.matches[last - 1].end);
+        }
+        let end = end - removed + inserted;
+        let window = lines_around(buffer, start..end);
+        let old_end = window.end + removed - inserted;
+
+        let first = self.matches.partition_point(|m| m.end <= window.start);
+        let last = self.matches.partition_point(|m| m.start < old_end);
+        let text = buffer.slice(window.clone());
+        let found: Vec<Range<usize>> = pattern
+            .find_iter(&text)
+            .filter(|m| !m.is_empty())
+            .map(|m| window.start + m.start()..window.start + m.end())
+            .collect();
+
+        for m in &mut self.matches[last..] {
+            *m = m.start + inserted - removed..m.end + inserted - removed;
+        }
+        self.matches.splice(first..last, found);
+    }
+
+    // First match starting at or after `offset`, wrapping around to the top
+    pub fn next_from(&self, offset: usize) -> Option<usize> {
+        if self.matches.is_empty() {
+            return None;
+        }
+        let index = self.matches.partition_point(|m| m.start < offset);
+        Some(if index < self.matches.len() { index } else { 0 })
+    }
+
+    // Last match starting before `offset`, wrapping around to the bottom
+    pub fn prev_from(&self, offset: usize) -> Option<usize> {
+        if self.matches.is_empty() {
+            return None;
+        }
+        let index = self.matches.partition_point(|m| m.start < offset);
+        Some(if index > 0 {
+            index - 1
+        } else {
+            self.matches.len() - 1
+        })
+    }
+
+    // Matches overlapping the byte range `range`
+    pub fn matches_in(&self, range: Range<usize>) -> &[Range<usize>] {
+        let from = self.matches.partition_point(|m| m.end <= range.start);
+        let to = self.matches.partition_point(|m| m.start <= range.end);
+        &self.matches[from..std::cmp::max(from, to)]
+    }
+
+    // Text to put in place of `matched` in `buffer`, expanding `$1`-style
+    // groups in regex mode
+    pub fn replacement_for(&self, buffer: &Buffer, matched: &Range<usize>) -> String {
+        let Some(pattern) = self.pattern.as_ref().filter(|_| self.regex) else {
+            return self.replacement.clone();
+        };
+
+        // The lines of the match are enough to match it again
+        let window = lines_around(buffer, matched.clone());
+        let text = buffer.slice(window.clone());
+        let matched = matched.start - window.start..matched.end - window.start;
+        match pattern.captures_at(&text, matched.start) {
+            Some(caps) if caps.get(0).map(|m| m.range()) == Some(matched) => {
+                let mut expanded = String::new();
+                caps.expand(&self.replacement, &mut expanded);
+                expanded
+            }
+            _ => self.replacement.clone(),
+        }
+    }
+
+    // Short summary for the prompt, e.g. "3/17"
+    pub fn status(&self) -> String {
+        if let Some(error) = &self.error {
+            return error.clone();
+        }
+        if self.query.is_empty() {
+            return String::new();
+        }
+        match (self.current, self.matches.len()) {
+            (_, 0) => "no matches".to_string(),
+            (Some(i), total) => format!("{}/{}", i + 1, total),
+            (None, total) => format!("{} matches", total),
+        }
+    }
+}
+
+// From the start of the line `range` starts on to the '\n' after the one
+// it ends on, taking in the '\r' of a CRLF line break that a match can end
+// with
+fn lines_around(buffer: &Buffer, range: Range<usize>) -> Range<usize> {
+    let start = buffer.line_to_byte(buffer.byte_to_line(range.start));
+    let newline = buffer.line_break(buffer.byte_to_line(range.end));
+    let end = if newline.is_empty() {
+        newline.start
+    } else {
+        newline.end - 1
+    };
+    start..end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search(query: &str, regex: bool, buffer: &Buffer) -> Search {
+        let mut search = Search::new();
+        search.query = query.to_string();
+        search.regex = regex;
+        search.update(buffer);
+        search
+    }
+
+    // Make an edit and check the matches kept up to date against a search
+    // of the whole buffer
+    fn check_edit(text: &str, query: &str, regex: bool, at: usize, removed: usize, inserted: &str) {
+        let mut buffer = Buffer::new(text.to_string());
+        let mut search = search(query, regex, &buffer);
+        buffer.remove(at..at + removed);
+        buffer.insert(at, inserted);
+        search.edited(&buffer, at, removed, inserted.len());
+
+        let fresh = self::search(query, regex, &buffer);
+        assert_eq!(
+            search.matches,
+            fresh.matches,
+            "{:?} in {:?} after {}..{} became {:?}",
+            query,
+            text,
+            at,
+            at + removed,
+            inserted
+        );
+    }
+
+    #[test]
+    fn finds_every_match_case_insensitively_by_default() {
+        let buffer = Buffer::new("Foo foo\nFOO bar".to_string());
+        let mut found = search("foo", false, &buffer);
+        assert_eq!(found.matches, vec![0..3, 4..7, 8..11]);
+
+        found.case_sensitive = true;
+        found.update(&buffer);
+        assert_eq!(found.matches, vec![4..7]);
+    }
+
+    #[test]
+    fn plain_queries_are_not_patterns() {
+        let buffer = Buffer::new("a.b axb".to_string());
+        assert_eq!(search("a.b", false, &buffer).matches, vec![0..3]);
+        assert_eq!(search("a.b", true, &buffer).matches, vec![0..3, 4..7]);
+    }
+
+    #[test]
+    fn zero_length_matches_are_skipped() {
+        let buffer = Buffer::new("axxb\nb".to_string());
+        assert_eq!(search("x*", true, &buffer).matches, vec![1..3]);
+        assert!(search("^", true, &buffer).matches.is_empty());
+        assert!(
+            search("x*", true, &Buffer::new("ab".to_string()))
+                .matches
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn invalid_regex_reports_an_error() {
+        let buffer = Buffer::new("a(b".to_string());
+        let found = search("a(", true, &buffer);
+        assert!(found.matches.is_empty());
+        assert!(found.error.is_some());
+        assert_eq!(found.status(), *found.error.as_ref().unwrap());
+    }
+
+    #[test]
+    fn only_queries_that_can_span_lines_are_not_line_bound() {
+        let buffer = Buffer::new(String::new());
+        assert!(search("a b", false, &buffer).line_bound);
+        assert!(search("a.b+", true, &buffer).line_bound);
+        assert!(!search("a\nb", false, &buffer).line_bound);
+        assert!(!search("a\\sb", true, &buffer).line_bound);
+        assert!(!search("a[^x]b", true, &buffer).line_bound);
+        assert!(!search("(?s)a.b", true, &buffer).line_bound);
+        // Escapes are literal outside regex mode
+        assert!(search("a\\sb", false, &buffer).line_bound);
+    }
+
+    #[test]
+    fn edits_away_from_matches_move_them() {
+        check_edit("foo\nbar\nfoo", "foo", false, 5, 0, "xx");
+        check_edit("foo\nbar\nfoo", "foo", false, 4, 3, "");
+        check_edit("foo\nbar\nfoo", "foo", false, 4, 3, "a\nb\nc");
+        check_edit("foo\nbar\nfoo", "foo", false, 0, 0, "\n\n");
+        check_edit("foo\nbar\nfoo", "foo", false, 11, 0, "\nfoo");
+    }
+
+    #[test]
+    fn edits_inside_matches_search_their_lines_again() {
+        // Breaking a match, growing one and making new ones
+        check_edit("foo foo\nfoo", "foo", false, 1, 1, "");
+        check_edit("fo\nfoo", "fo+", true, 2, 0, "oo");
+        check_edit("fo fo\nfoo", "foo", false, 2, 0, "o");
+        check_edit("fo fo\nfoo", "foo", false, 2, 1, "o fo");
+        // An edit joining two lines and one splitting a match over two
+        check_edit("fo\no foo", "foo", false, 2, 1, "");
+        check_edit("foo foo\nfoo", "foo", false, 5, 0, "\n");
+        // Removing everything and starting over
+        check_edit("foo\nfoo", "foo", false, 0, 7, "");
+        check_edit("", "foo", false, 0, 0, "foo\nfoo");
+    }
+
+    #[test]
+    fn edits_at_line_edges_keep_neighbouring_matches() {
+        check_edit("ab\nab\nab", "ab", false, 2, 0, "ab");
+        check_edit("ab\nab\nab", "ab", false, 3, 0, "ab");
+        check_edit("ab\nab\nab", "b$", true, 2, 1, "");
+        check_edit("ab\nab\nab", "^a", true, 3, 0, "x");
+    }
+
+    #[test]
+    fn every_small_edit_matches_a_full_search() {
+        let text = "foo fo\nofoo\n\nfoo";
+        for at in 0..=text.len() {
+            for removed in 0..=std::cmp::min(3, text.len() - at) {
+                for inserted in ["", "o", "f", "\n", "oo\nf"] {
+                    check_edit(text, "foo", false, at, removed, inserted);
+                    check_edit(text, "o+", true, at, removed, inserted);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn queries_spanning_lines_search_everything_again() {
+        check_edit("a\nb a b", "a\\sb", true, 6, 1, "\n");
+        check_edit("a b\na", "a\nb", false, 1, 1, "\n");
+    }
+
+    #[test]
+    fn next_and_previous_wrap_around() {
+        let buffer = Buffer::new("x.x.x".to_string());
+        let found = search("x", false, &buffer);
+        assert_eq!(found.next_from(1), Some(1));
+        assert_eq!(found.next_from(5), Some(0));
+        assert_eq!(found.prev_from(2), Some(0));
+        assert_eq!(found.prev_from(0), Some(2));
+        assert_eq!(found.matches_in(1..3), vec![2..3]);
+    }
+
+    #[test]
+    fn replacement_expands_groups_in_regex_mode() {
+        let buffer = Buffer::new("let a = 1;\nlet bc = 2;".to_string());
+        let mut found = search("let (\\w+)", true, &buffer);
+        found.replacement = "const $1".to_string();
+        assert_eq!(
+            found.replacement_for(&buffer, &found.matches[1]),
+            "const bc"
+        );
+
+        found.regex = false;
+        assert_eq!(
+            found.replacement_for(&buffer, &found.matches[1]),
+            "const $1"
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::ops::Range;
+
+use crate::{Buffer, Pointer};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SelectionMode {
+    // From the anchor to the cursor, grapheme by grapheme
+    Characterwise,
+    // Every line between the anchor and the cursor, whole
+    Linewise,
+}
+
+// A selection runs from `anchor` to the cursor. Shift+movement drops it as
+// soon as the cursor moves without Shift; a mark set explicitly keeps
+// extending with plain movement until it is cleared.
+#[derive(Clone, Copy, Debug)]
+pub struct Selection {
+    pub anchor: Pointer,
+    pub mode: SelectionMode,
+    pub mark: bool,
+}
+
+impl Selection {
+    pub fn new(anchor: Pointer, mode: SelectionMode, mark: bool) -> Self {
+        Selection { anchor, mode, mark }
+    }
+
+    // First and last line touched by the selection
+    pub fn lines(&self, cursor: Pointer) -> Range<usize> {
+        let first = std::cmp::min(self.anchor.y, cursor.y);
+        let last = std::cmp::max(self.anchor.y, cursor.y);
+        first..last + 1
+    }
+
+    // Selected bytes of `buffer`. Linewise selections include the newline
+    // after the last line so removing them leaves no empty line behind.
+    pub fn range(&self, cursor: Pointer, buffer: &Buffer) -> Range<usize> {
+        match self.mode {
+            SelectionMode::Characterwise => {
+                let anchor = buffer.position_to_byte(self.anchor);
+                let cursor = buffer.position_to_byte(cursor);
+                std::cmp::min(anchor, cursor)..std::cmp::max(anchor, cursor)
+            }
+            SelectionMode::Linewise => line_span(buffer, self.lines(cursor)),
+        }
+    }
+}
+
+// Bytes of the whole lines in `lines` together with one line break, the
+// one after them or, at the end of the buffer, the one before them
+pub fn line_span(buffer: &Buffer, lines: Range<usize>) -> Range<usize> {
+    let start = buffer.line_to_byte(lines.start);
+    let end = buffer.line_range(lines.end - 1).end;
+
+    if lines.end < buffer.len_lines() {
+        start..buffer.line_break(lines.end - 1).end
+    } else if lines.start > 0 {
+        buffer.line_break(lines.start - 1).start..end
+    } else {
+        start..end
+    }
+}
+
+// Text that was copied or cut, kept for pasting
+#[derive(Clone, Default)]
+pub struct Register {
+    pub text: String,
+    // Pasted as whole lines above the cursor line instead of at the cursor
+    pub linewise: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer() -> Buffer {
+        Buffer::new("one\ntwo\nthree".to_string())
+    }
+
+    #[test]
+    fn line_span_takes_the_newline_after_the_lines() {
+        let buffer = buffer();
+        assert_eq!(line_span(&buffer, 0..1), 0..4);
+        assert_eq!(line_span(&buffer, 1..2), 4..8);
+        assert_eq!(line_span(&buffer, 0..2), 0..8);
+    }
+
+    #[test]
+    fn line_span_at_the_end_takes_the_newline_before() {
+        let buffer = buffer();
+        assert_eq!(line_span(&buffer, 2..3), 7..13);
+        assert_eq!(line_span(&buffer, 1..3), 3..13);
+        // A trailing empty line
+        let buffer = Buffer::new("one\n".to_string());
+        assert_eq!(line_span(&buffer, 1..2), 3..4);
+    }
+
+    #[test]
+    fn line_span_of_every_line_is_the_whole_buffer() {
+        let buffer = buffer();
+        assert_eq!(line_span(&buffer, 0..3), 0..13);
+        let buffer = Buffer::new("only".to_string());
+        assert_eq!(line_span(&buffer, 0..1), 0..4);
+        let buffer = Buffer::new(String::new());
+        assert_eq!(line_span(&buffer, 0..1), 0..0);
+    }
+
+    #[test]
+    fn line_span_takes_whole_crlf_line_breaks() {
+        let buffer = Buffer::new("one\r\ntwo\r\nthree".to_string());
+        assert_eq!(line_span(&buffer, 0..1), 0..5);
+        assert_eq!(line_span(&buffer, 1..2), 5..10);
+        assert_eq!(line_span(&buffer, 2..3), 8..15);
+    }
+
+    #[test]
+    fn removing_a_line_span_leaves_no_empty_line() {
+        for lines in [0..1, 1..2, 2..3, 0..2, 1..3, 0..3] {
+            let mut buffer = buffer();
+            let span = line_span(&buffer, lines.clone());
+            buffer.remove(span);
+            let kept: Vec<&str> = ["one", "two", "three"]
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| !lines.contains(i))
+                .map(|(_, line)| line)
+                .collect();
+            assert_eq!(buffer.contents(), kept.join("\n"), "removing {:?}", lines);
+        }
+    }
+
+    #[test]
+    fn characterwise_range_runs_either_way() {
+        let buffer = buffer();
+        let selection = Selection::new(Pointer::new(1, 0), SelectionMode::Characterwise, false);
+        assert_eq!(selection.range(Pointer::new(2, 1), &buffer), 1..6);
+        let selection = Selection::new(Pointer::new(2, 1), SelectionMode::Characterwise, false);
+        assert_eq!(selection.range(Pointer::new(1, 0), &buffer), 1..6);
+        assert_eq!(selection.range(Pointer::new(2, 1), &buffer), 6..6);
+    }
+
+    #[test]
+    fn linewise_range_covers_the_lines_between() {
+        let buffer = buffer();
+        let selection = Selection::new(Pointer::new(2, 2), SelectionMode::Linewise, true);
+        assert_eq!(selection.lines(Pointer::new(0, 0)), 0..3);
+        assert_eq!(selection.range(Pointer::new(0, 0), &buffer), 0..13);
+        assert_eq!(selection.range(Pointer::new(3, 1), &buffer), 3..13);
+        assert_eq!(selection.range(Pointer::new(0, 2), &buffer), 7..13);
+    }
+}
@@ -0,0 +1,360 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::save;
+
+// Swap files keep unsaved changes safe from crashes. While a file is open,
+// `.<name>.nox.swp` next to it says which nox process has it, and holds the
+// text whenever there are changes that aren't saved yet. A swap file left
+// behind by a process that is gone means it never got to clean up, and its
+// text can be recovered.
+
+const HEADER: &str = "nox swap 1";
+
+// How long changes wait before they are written to the swap file
+pub const SWAP_DELAY: Duration = Duration::from_secs(2);
+
+// The swap file of an open document
+pub struct Swap {
+    // The file it keeps changes to
+    pub file: PathBuf,
+    pub path: PathBuf,
+    // History state of the text last written to it
+    pub written: u64,
+    // When changes that aren't in it yet were first seen
+    pub changed_at: Option<Instant>,
+    // Text left by a nox that didn't exit cleanly, until the user decides
+    // what to do with it. The swap file isn't touched meanwhile.
+    pub recovery: Option<Recovery>,
+}
+
+pub struct Recovery {
+    pub text: String,
+    // Written after the file was last changed on disk
+    pub newer: bool,
+}
+
+// What a swap file on disk says
+pub struct SwapFile {
+    pub pid: u32,
+    // Unsaved text, None if there were no unsaved changes
+    pub text: Option<String>,
+    pub modified: Option<SystemTime>,
+}
+
+impl Swap {
+    pub fn new(file: &Path) -> Self {
+        Swap {
+            file: file.to_path_buf(),
+            path: swap_path(file),
+            // No history state is ever this, nothing is written yet
+            written: u64::MAX,
+            changed_at: None,
+            recovery: None,
+        }
+    }
+
+    // Record the text in `state`, or just that the file is open when the
+    // text is the saved one
+    pub fn write(&mut self, text: Option<&str>, state: u64) -> io::Result<()> {
+        let mut contents = format!("{}\npid {}\n", HEADER, std::process::id());
+        match text {
+            Some(text) => {
+                contents.push_str("text\n");
+                contents.push_str(text);
+            }
+            None => contents.push_str("clean\n"),
+        }
+        // Readable by whoever can read the file, and only by its owner while
+        // there is no file yet
+        let mode = fs::metadata(&self.file)
+            .map(|metadata| metadata.permissions().mode() & 0o666)
+            .unwrap_or(0o600);
+        save::write_file_with_mode(&self.path, contents.as_bytes(), mode)?;
+        self.written = state;
+        self.changed_at = None;
+        Ok(())
+    }
+
+    // Delete the swap file, unless it holds text still waiting to be
+    // recovered
+    pub fn remove(&self) {
+        if self.recovery.is_none() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+// `dir/.name.nox.swp` for `dir/name`
+pub fn swap_path(file: &Path) -> PathBuf {
+    let name = file
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    file.with_file_name(format!(".{}.nox.swp", name))
+}
+
+// The swap file at `path`, None if there is none or it isn't one of ours
+pub fn read(path: &Path) -> Option<SwapFile> {
+    let contents = fs::read_to_string(path).ok()?;
+    let rest = contents.strip_prefix(HEADER)?.strip_prefix('\n')?;
+    let (pid, rest) = rest.strip_prefix("pid ")?.split_once('\n')?;
+    let pid = pid.parse().ok()?;
+    let text = if let Some(text) = rest.strip_prefix("text\n") {
+        Some(text.to_string())
+    } else if rest == "clean\n" {
+        None
+    } else {
+        return None;
+    };
+
+    Some(SwapFile {
+        pid,
+        text,
+        modified: fs::metadata(path).and_then(|m| m.modified()).ok(),
+    })
+}
+
+// Whether the process with `pid` is still running
+pub fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks the process exists. Someone else's process
+    // can't be signalled but is still there.
+    let signalled = unsafe { libc::kill(pid, 0) } == 0;
+    signalled || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+// Lines of the file kept around a change in the diff
+const CONTEXT: usize = 3;
+
+// Above this many line pairs to compare, the changed part is shown as
+// removed and added whole
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+// `old` against `new` as a unified diff of their lines
+pub fn diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    let old: Vec<&str> = old.split('\n').collect();
+    let new: Vec<&str> = new.split('\n').collect();
+
+    // Only the part between the common start and end needs comparing
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    let mut ops: Vec<(char, &str)> = old[..prefix].iter().map(|line| (' ', *line)).collect();
+    ops.extend(diff_lines(old_middle, new_middle));
+    ops.extend(old[old.len() - suffix..].iter().map(|line| (' ', *line)));
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    // Line numbers in `old` and `new` where each op starts
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut old_line, mut new_line) = (0, 0);
+    for (kind, _) in &ops {
+        positions.push((old_line, new_line));
+        match kind {
+            '-' => old_line += 1,
+            '+' => new_line += 1,
+            _ => {
+                old_line += 1;
+                new_line += 1;
+            }
+        }
+    }
+    positions.push((old_line, new_line));
+
+    let mut next = 0;
+    while let Some(first) = (next..ops.len()).find(|&i| ops[i].0 != ' ') {
+        // Changes close enough to share their context go in one hunk
+        let mut last = first;
+        while let Some(change) = (last + 1..ops.len())
+            .take(2 * CONTEXT + 1)
+            .find(|&i| ops[i].0 != ' ')
+        {
+            last = change;
+        }
+        let start = first.saturating_sub(CONTEXT).max(next);
+        let end = std::cmp::min(last + CONTEXT + 1, ops.len());
+
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            old_start + 1,
+            old_end - old_start,
+            new_start + 1,
+            new_end - new_start
+        ));
+        for (kind, line) in &ops[start..end] {
+            out.push(*kind);
+            out.push_str(line);
+            out.push('\n');
+        }
+        next = end;
+    }
+    out
+}
+
+// Removed and added lines turning `old` into `new`, by their longest
+// common subsequence
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(char, &'a str)> {
+    if old.len().saturating_mul(new.len()) > MAX_DIFF_CELLS {
+        let removed = old.iter().map(|line| ('-', *line));
+        return removed.chain(new.iter().map(|line| ('+', *line))).collect();
+    }
+
+    // `common[i][j]` is the length of the longest common subsequence of
+    // `old[i..]` and `new[j..]`
+    let mut common = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                std::cmp::max(common[i + 1][j], common[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::with_capacity(old.len() + new.len());
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            ops.push(('-', old[i]));
+            i += 1;
+        } else {
+            ops.push(('+', new[j]));
+            j += 1;
+        }
+    }
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(count: usize) -> Vec<String> {
+        (1..=count).map(|n| n.to_string()).collect()
+    }
+
+    fn hunks(diff: &str) -> usize {
+        diff.lines().filter(|line| line.starts_with("@@")).count()
+    }
+
+    #[test]
+    fn identical_texts_have_no_hunks() {
+        assert_eq!(diff("a\nb\n", "a\nb\n", "old", "new"), "--- old\n+++ new\n");
+    }
+
+    #[test]
+    fn changed_line_keeps_context_around_it() {
+        let old = numbered(10);
+        let mut new = old.clone();
+        new[4] = "five".to_string();
+        let out = diff(&old.join("\n"), &new.join("\n"), "a", "b");
+        assert_eq!(
+            out,
+            "--- a\n+++ b\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n"
+        );
+    }
+
+    #[test]
+    fn added_lines_at_the_end() {
+        assert_eq!(
+            diff("a\nb", "a\nb\nc\nd", "a", "b"),
+            "--- a\n+++ b\n@@ -1,2 +1,4 @@\n a\n b\n+c\n+d\n"
+        );
+    }
+
+    #[test]
+    fn removed_lines_at_the_start() {
+        assert_eq!(
+            diff("x\ny\na\nb", "a\nb", "a", "b"),
+            "--- a\n+++ b\n@@ -1,4 +1,2 @@\n-x\n-y\n a\n b\n"
+        );
+    }
+
+    #[test]
+    fn distant_changes_get_their_own_hunks() {
+        let old = numbered(20);
+        let mut new = old.clone();
+        new[1] = "two".to_string();
+        new[17] = "eighteen".to_string();
+        let out = diff(&old.join("\n"), &new.join("\n"), "a", "b");
+        assert_eq!(hunks(&out), 2);
+        assert!(out.contains("@@ -1,5 +1,5 @@\n 1\n-2\n+two\n 3\n 4\n 5\n"));
+        assert!(out.contains("@@ -15,6 +15,6 @@\n 15\n 16\n 17\n-18\n+eighteen\n 19\n 20\n"));
+    }
+
+    #[test]
+    fn nearby_changes_share_a_hunk() {
+        let old = numbered(20);
+        let mut new = old.clone();
+        new[4] = "five".to_string();
+        new[10] = "eleven".to_string();
+        let out = diff(&old.join("\n"), &new.join("\n"), "a", "b");
+        assert_eq!(hunks(&out), 1);
+        assert!(out.contains("@@ -2,13 +2,13 @@\n"));
+    }
+
+    #[test]
+    fn line_counts_follow_inserted_lines() {
+        let old = numbered(20);
+        let mut new = old.clone();
+        new.insert(2, "new".to_string());
+        new[16] = "changed".to_string();
+        let out = diff(&old.join("\n"), &new.join("\n"), "a", "b");
+        assert_eq!(hunks(&out), 2);
+        assert!(out.contains("@@ -1,5 +1,6 @@\n"));
+        // The second hunk starts one line later in the new text
+        assert!(out.contains("@@ -13,7 +14,7 @@\n"));
+    }
+
+    #[test]
+    fn swap_path_is_hidden_next_to_the_file() {
+        assert_eq!(
+            swap_path(Path::new("/tmp/dir/notes.txt")),
+            PathBuf::from("/tmp/dir/.notes.txt.nox.swp")
+        );
+    }
+
+    #[test]
+    fn written_swap_reads_back() {
+        let dir = std::env::temp_dir().join(format!("nox-swap-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("file.txt");
+        fs::write(&file, "saved").unwrap();
+
+        let mut swap = Swap::new(&file);
+        swap.write(Some("unsaved\ntext"), 7).unwrap();
+        assert_eq!(swap.written, 7);
+        let read_back = read(&swap.path).unwrap();
+        assert_eq!(read_back.pid, std::process::id());
+        assert_eq!(read_back.text.as_deref(), Some("unsaved\ntext"));
+        assert!(is_running(read_back.pid));
+
+        swap.write(None, 8).unwrap();
+        assert_eq!(read(&swap.path).unwrap().text, None);
+
+        swap.remove();
+        assert!(!swap.path.exists());
+        fs::write(&swap.path, "something else").unwrap();
+        assert!(read(&swap.path).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// Helpers for moving around a line by grapheme cluster and measuring it in
+// terminal columns. Byte columns passed in are clamped to the line and
+// snapped back to the grapheme they fall inside, so callers never end up
+// slicing in the middle of a character. In buffer lines a tab reaches the
+// next multiple of `tab_width` columns from the start of the line.
+
+// Columns taken by a single grapheme. Control characters still take one so
+// the cursor stays visible on them.
+pub fn grapheme_width(grapheme: &str) -> usize {
+    std::cmp::max(1, grapheme.width())
+}
+
+// Columns taken by a grapheme of a buffer line that starts at `column`
+pub fn grapheme_columns(grapheme: &str, column: usize, tab_width: usize) -> usize {
+    if grapheme == "\t" {
+        let tab_width = std::cmp::max(1, tab_width);
+        tab_width - column % tab_width
+    } else {
+        grapheme_width(grapheme)
+    }
+}
+
+// Columns taken by `text` on screen
+pub fn display_width(text: &str) -> usize {
+    text.graphemes(true).map(grapheme_width).sum()
+}
+
+// Start of the grapheme containing byte column `x`
+pub fn snap_to_grapheme(line: &str, x: usize) -> usize {
+    if x >= line.len() {
+        return line.len();
+    }
+    line.grapheme_indices(true)
+        .take_while(|(i, _)| *i <= x)
+        .last()
+        .map_or(0, |(i, _)| i)
+}
+
+// Byte column of the grapheme before `x`
+pub fn prev_grapheme(line: &str, x: usize) -> usize {
+    let x = snap_to_grapheme(line, x);
+    line[..x]
+        .grapheme_indices(true)
+        .next_back()
+        .map_or(0, |(i, _)| i)
+}
+
+// Byte column of the grapheme after `x`
+pub fn next_grapheme(line: &str, x: usize) -> usize {
+    let x = snap_to_grapheme(line, x);
+    line[x..]
+        .graphemes(true)
+        .next()
+        .map_or(line.len(), |g| x + g.len())
+}
+
+// Start of the next word after `x`, or the end of the line
+pub fn next_word(line: &str, x: usize) -> usize {
+    line.split_word_bound_indices()
+        .map(|(i, word)| (i, word.chars().any(char::is_alphanumeric)))
+        .find(|(i, is_word)| *i > x && *is_word)
+        .map_or(line.len(), |(i, _)| i)
+}
+
+// Start of the word before `x`, or the start of the line
+pub fn prev_word(line: &str, x: usize) -> usize {
+    line.split_word_bound_indices()
+        .rev()
+        .map(|(i, word)| (i, word.chars().any(char::is_alphanumeric)))
+        .find(|(i, is_word)| *i < x && *is_word)
+        .map_or(0, |(i, _)| i)
+}
+
+// Screen column where byte column `x` is drawn
+pub fn byte_to_column(line: &str, x: usize, tab_width: usize) -> usize {
+    line[..snap_to_grapheme(line, x)]
+        .graphemes(true)
+        .fold(0, |column, grapheme| {
+            column + grapheme_columns(grapheme, column, tab_width)
+        })
+}
+
+// Byte column of the grapheme drawn at screen column `column`. Columns past
+// the end of the line map to the end.
+pub fn column_to_byte(line: &str, column: usize, tab_width: usize) -> usize {
+    let mut width = 0;
+    for (i, grapheme) in line.grapheme_indices(true) {
+        let next = width + grapheme_columns(grapheme, width, tab_width);
+        if next > column {
+            return i;
+        }
+        width = next;
+    }
+    line.len()
+}
+
+// Longest prefix of `text` that fits in `width` columns
+pub fn truncate_to_width(text: &str, width: usize) -> &str {
+    let mut used = 0;
+    for (i, grapheme) in text.grapheme_indices(true) {
+        used += grapheme_width(grapheme);
+        if used > width {
+            return &text[..i];
+        }
+    }
+    text
+}
+
+// Split `line` into rows at most `width` columns wide for soft wrapping,
+// as byte ranges. Rows break after whitespace so words stay whole; only a
+// word wider than a row is split. A line that exactly fills its last row
+// gets an empty row after it, where the cursor goes at the end of the line.
+pub fn wrap_rows(line: &str, width: usize, tab_width: usize) -> Vec<Range<usize>> {
+    let width = std::cmp::max(1, width);
+    let mut rows = Vec::new();
+    let mut start = 0;
+    let mut used = 0;
+    // Column from the start of the line, where tab stops are counted from
+    let mut column = 0;
+    let mut in_word = false;
+
+    for (i, grapheme) in line.grapheme_indices(true) {
+        let blank = grapheme.starts_with(char::is_whitespace);
+        if !blank && !in_word {
+            // Move a word that would run over onto the next row, if it fits
+            // there
+            let end = line[i..]
+                .find(char::is_whitespace)
+                .map_or(line.len(), |e| i + e);
+            let word_width = display_width(&line[i..end]);
+            if used > 0 && used + word_width > width && word_width <= width {
+                rows.push(start..i);
+                start = i;
+                used = 0;
+            }
+        }
+
+        let width_here = grapheme_columns(grapheme, column, tab_width);
+        if used > 0 && used + width_here > width {
+            rows.push(start..i);
+            start = i;
+            used = 0;
+        }
+        used += width_here;
+        column += width_here;
+        in_word = !blank;
+    }
+
+    rows.push(start..line.len());
+    if used >= width {
+        rows.push(line.len()..line.len());
+    }
+    rows
+}
+
+// Index of the row in `rows` that byte column `x` is drawn on. The end of a
+// row is the start of the next, so it belongs to the next one.
+pub fn row_of(rows: &[Range<usize>], x: usize) -> usize {
+    rows.iter().rposition(|row| row.start <= x).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_lines_are_one_row() {
+        assert_eq!(wrap_rows("", 10, 4), vec![0..0]);
+        assert_eq!(wrap_rows("hello world", 20, 4), vec![0..11]);
+    }
+
+    #[test]
+    fn words_move_to_the_next_row_whole() {
+        assert_eq!(wrap_rows("hello world", 8, 4), [0..6, 6..11]);
+        assert_eq!(wrap_rows("a bb ccc dddd", 6, 4), [0..5, 5..9, 9..13]);
+    }
+
+    #[test]
+    fn words_wider_than_a_row_are_split() {
+        assert_eq!(wrap_rows("abcdefghij", 4, 4), [0..4, 4..8, 8..10]);
+        // Without waiting for a row of its own
+        assert_eq!(wrap_rows("ab cdefghij", 4, 4), [0..4, 4..8, 8..11]);
+    }
+
+    #[test]
+    fn a_full_last_row_gets_an_empty_one_for_the_cursor() {
+        assert_eq!(wrap_rows("abcd", 4, 4), [0..4, 4..4]);
+        assert_eq!(wrap_rows("abc", 4, 4), vec![0..3]);
+    }
+
+    #[test]
+    fn wide_characters_and_tabs_count_their_columns() {
+        // Two columns each
+        assert_eq!(wrap_rows("日本語", 4, 4), [0..6, 6..9]);
+        // The tab runs to column 4, filling the first row
+        assert_eq!(wrap_rows("a\tb", 4, 4), [0..2, 2..3]);
+        assert_eq!(wrap_rows("a\tb", 8, 4), vec![0..3]);
+    }
+
+    #[test]
+    fn row_of_puts_row_ends_on_the_next_row() {
+        let rows = wrap_rows("hello world", 8, 4);
+        assert_eq!(row_of(&rows, 0), 0);
+        assert_eq!(row_of(&rows, 5), 0);
+        assert_eq!(row_of(&rows, 6), 1);
+        assert_eq!(row_of(&rows, 11), 1);
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// The file tree shown in the sidebar. Directories are read when they are
+// expanded and the visible rows are kept as a flat list, each with its
+// depth for indenting.
+
+pub struct TreeNode {
+    pub name: String,
+    pub path: PathBuf,
+    pub depth: usize,
+    pub is_dir: bool,
+    pub expanded: bool,
+}
+
+#[derive(Default)]
+pub struct FileTree {
+    pub root: PathBuf,
+    // Rows as shown, children right below their directory
+    pub nodes: Vec<TreeNode>,
+    // Index into `nodes`
+    pub pointer: usize,
+    expanded: HashSet<PathBuf>,
+}
+
+impl FileTree {
+    pub fn new(root: &Path) -> Self {
+        let root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+        let mut tree = FileTree {
+            root,
+            ..FileTree::default()
+        };
+        tree.refresh();
+        tree
+    }
+
+    // Read the expanded directories again, e.g. after files were added
+    pub fn refresh(&mut self) {
+        let selected = self.selected().map(|node| node.path.clone());
+        self.nodes.clear();
+        let root = self.root.clone();
+        self.add_children(&root, 0);
+
+        self.pointer = selected
+            .and_then(|path| self.nodes.iter().position(|node| node.path == path))
+            .unwrap_or(0)
+            .min(self.nodes.len().saturating_sub(1));
+    }
+
+    fn add_children(&mut self, dir: &Path, depth: usize) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        let mut children: Vec<(String, PathBuf, bool)> = entries
+            .filter_map(Result::ok)
+            .map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let path = entry.path();
+                let is_dir = path.is_dir();
+                (name, path, is_dir)
+            })
+            // Hidden files stay out of the way, like in the browser
+            .filter(|(name, _, _)| !name.starts_with('.'))
+            .collect();
+        // Directories first, then files alphabetically
+        children.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+
+        for (name, path, is_dir) in children {
+            let expanded = is_dir && self.expanded.contains(&path);
+            self.nodes.push(TreeNode {
+                name,
+                path: path.clone(),
+                depth,
+                is_dir,
+                expanded,
+            });
+            if expanded {
+                self.add_children(&path, depth + 1);
+            }
+        }
+    }
+
+    pub fn selected(&self) -> Option<&TreeNode> {
+        self.nodes.get(self.pointer)
+    }
+
+    pub fn move_pointer(&mut self, delta: isize) {
+        let last = self.nodes.len().saturating_sub(1);
+        self.pointer = self.pointer.saturating_add_signed(delta).min(last);
+    }
+
+    // Expand the selected directory or collapse it again
+    pub fn toggle(&mut self) {
+        let Some(node) = self.selected() else {
+            return;
+        };
+        if !node.is_dir {
+            return;
+        }
+        let path = node.path.clone();
+        if !self.expanded.remove(&path) {
+            self.expanded.insert(path);
+        }
+        self.refresh();
+    }
+
+    pub fn expand(&mut self) {
+        if self
+            .selected()
+            .is_some_and(|node| node.is_dir && !node.expanded)
+        {
+            self.toggle();
+        }
+    }
+
+    // Collapse the selected directory, or move up to the one holding it
+    pub fn collapse(&mut self) {
+        let Some(node) = self.selected() else {
+            return;
+        };
+        if node.is_dir && node.expanded {
+            self.toggle();
+        } else if node.depth > 0 {
+            let depth = node.depth;
+            if let Some(parent) = self.nodes[..self.pointer]
+                .iter()
+                .rposition(|node| node.depth < depth)
+            {
+                self.pointer = parent;
+            }
+        }
+    }
+
+    // Expand the directories down to `path` and select it, if it's in the
+    // tree at all
+    pub fn reveal(&mut self, path: &Path) {
+        let Ok(path) = fs::canonicalize(path) else {
+            return;
+        };
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return;
+        };
+        let mut dir = self.root.clone();
+        for part in relative.parent().into_iter().flat_map(Path::components) {
+            dir.push(part);
+            self.expanded.insert(dir.clone());
+        }
+        self.refresh();
+        if let Some(index) = self.nodes.iter().position(|node| node.path == path) {
+            self.pointer = index;
+        }
+    }
+}
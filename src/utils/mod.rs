@@ -1,5 +1,6 @@
 use libc::{TIOCGWINSZ, ioctl, winsize};
 use std::io::{self, stdin};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::{io::Write, os::unix::io::AsRawFd};
 use termios::{ECHO, ICANON, ISIG, IXON, TCSANOW, Termios, tcsetattr};
 
@@ -27,17 +28,61 @@ pub fn disable_terminal_raw_mode() -> io::Result<()> {
     Ok(())
 }
 
-pub fn clear_screen() {
-    if let Ok(output) = std::process::Command::new("clear").output() {
-        if output.status.success() {
-            print!("{}", String::from_utf8_lossy(&output.stdout));
-        } else {
-            print!("\x1B[2J\x1B[1;1H");
-        }
-    } else {
-        print!("\x1B[2J\x1B[1;1H");
+// Draw on the terminal's alternate screen, so whatever was on it before
+// comes back when we exit. The editor draws its own cursor, so the
+// terminal's is hidden meanwhile.
+pub fn enter_alternate_screen() {
+    print!("\x1b[?1049h\x1b[?25l");
+    io::stdout().flush().expect("Failed to switch screens");
+}
+
+// Set while the terminal is in raw mode on the alternate screen, so it is
+// put back only once whether nox exits or panics
+static TERMINAL_TAKEN: AtomicBool = AtomicBool::new(false);
+
+// Holds the terminal in raw mode on the alternate screen and puts it back
+// when dropped. A panic puts it back before the message is printed, so the
+// message shows on the normal screen.
+pub struct TerminalGuard;
+
+impl TerminalGuard {
+    pub fn new() -> io::Result<Self> {
+        set_terminal_raw_mode()?;
+        enter_alternate_screen();
+        TERMINAL_TAKEN.store(true, Ordering::SeqCst);
+
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore_terminal();
+            default_hook(info);
+        }));
+        Ok(TerminalGuard)
     }
-    io::stdout().flush().expect("Failed to clear screen");
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+// Leave the alternate screen, focus reporting and raw mode if we are still
+// in them. Nothing here may panic, it runs from the panic hook.
+fn restore_terminal() {
+    if !TERMINAL_TAKEN.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(b"\x1b[?1004l\x1b[0m\x1b[?25h\x1b[?1049l");
+    let _ = stdout.flush();
+    let _ = disable_terminal_raw_mode();
+}
+
+// Have the terminal send ESC [ I and ESC [ O when its window gains and
+// loses focus. Terminals that can't just ignore this.
+pub fn set_focus_reporting(on: bool) {
+    print!("\x1b[?1004{}", if on { 'h' } else { 'l' });
+    let _ = io::stdout().flush();
 }
 
 pub fn get_terminal_size() -> io::Result<(u16, u16)> {